@@ -0,0 +1,2 @@
+pub mod semantic;
+pub mod symbol_table;
@@ -0,0 +1,399 @@
+use crate::lexing::lexer::Span;
+use crate::parsing::parser::{Comparison, Expression, Identifier, Primary, PrintOption, Statement};
+use crate::reporting::diagnostic::Diagnostic;
+
+use super::symbol_table::SymbolTable;
+
+/// A `let` or `input` somewhere in the program, used to explain uses that come before it.
+struct Declaration {
+    id: String,
+    span: Span,
+    is_input: bool,
+}
+
+/// Resolves every identifier of a parsed program to the declaration it refers to and reports
+/// identifiers that are undeclared, redeclared or used before they are declared.
+pub struct SemanticAnalyzer {
+    symbols: SymbolTable,
+    declarations: Vec<Declaration>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl SemanticAnalyzer {
+    pub fn new() -> Self {
+        Self {
+            symbols: SymbolTable::new(),
+            declarations: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Fills in `Identifier::symbol` throughout `statements`. All problems are collected,
+    /// identifiers that could not be resolved are left without a symbol.
+    pub fn analyze(mut self, statements: &mut [Statement]) -> Result<(), Vec<Diagnostic>> {
+        collect_declarations(statements, &mut self.declarations);
+        self.analyze_statements(statements);
+        if self.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(self.diagnostics)
+        }
+    }
+
+    fn analyze_statements(&mut self, statements: &mut [Statement]) {
+        for statement in statements {
+            self.analyze_statement(statement);
+        }
+    }
+
+    /// Everything declared in a `{}` block is scoped to that block.
+    fn analyze_block(&mut self, statements: &mut [Statement]) {
+        self.symbols.enter_scope();
+        self.analyze_statements(statements);
+        self.symbols.exit_scope();
+    }
+
+    fn analyze_statement(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Print { option, .. } => {
+                if let PrintOption::PrintExpression(expression) = option {
+                    self.analyze_expression(expression);
+                }
+            }
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                self.analyze_comparison(comparison);
+                self.analyze_block(statements);
+                if let Some(else_statements) = else_statements {
+                    self.analyze_block(else_statements);
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                self.analyze_comparison(comparison);
+                self.analyze_block(statements);
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                // The loop variable gets a scope of its own, around the one of the body.
+                self.symbols.enter_scope();
+                self.analyze_statement(init);
+                self.analyze_comparison(comparison);
+                self.analyze_statement(step);
+                self.analyze_block(statements);
+                self.symbols.exit_scope();
+            }
+            Statement::Break { .. } | Statement::Continue { .. } => {}
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            } => {
+                // The value is resolved before declaring, so `let x = x + 1;` can refer to a
+                // shadowed `x`.
+                self.analyze_expression(expression);
+                self.declare(identifier);
+            }
+            Statement::Input { identifier, .. } => self.declare(identifier),
+            Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                let help = format!("did you mean `let {} = ...`?", identifier.id);
+                self.resolve(identifier, help);
+                self.analyze_expression(expression);
+            }
+        }
+    }
+
+    fn analyze_comparison(&mut self, comparison: &mut Comparison) {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                self.analyze_expression(lhs);
+                for (_, expression) in rhs {
+                    self.analyze_expression(expression);
+                }
+            }
+            Comparison::Value(expression) => self.analyze_expression(expression),
+            Comparison::Not(comparison) => self.analyze_comparison(comparison),
+            Comparison::And(lhs, rhs) | Comparison::Or(lhs, rhs) => {
+                self.analyze_comparison(lhs);
+                self.analyze_comparison(rhs);
+            }
+        }
+    }
+
+    fn analyze_expression(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Binary { lhs, rhs, .. } => {
+                self.analyze_expression(lhs);
+                self.analyze_expression(rhs);
+            }
+            Expression::Unary { operand, .. } => self.analyze_primary(operand),
+            Expression::Primary(primary) => self.analyze_primary(primary),
+        }
+    }
+
+    fn analyze_primary(&mut self, primary: &mut Primary) {
+        match primary {
+            Primary::LiteralNumber(..) => {}
+            Primary::IdentifierExpression(identifier) => {
+                let help = format!(
+                    "declare it first with `let {} = ...` or `input {};`",
+                    identifier.id, identifier.id
+                );
+                self.resolve(identifier, help);
+            }
+            Primary::Grouped(expression, _) => self.analyze_expression(expression),
+        }
+    }
+
+    /// Declares `identifier` in the current scope, shadowing is only allowed across scopes.
+    fn declare(&mut self, identifier: &mut Identifier) {
+        match self.symbols.declare(&identifier.id) {
+            Some(symbol) => identifier.symbol = Some(symbol),
+            None => self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "Identifier {} is already declared in this scope",
+                        identifier.id
+                    ),
+                    identifier.span,
+                )
+                .with_help(format!(
+                    "use `{} = ...` to assign a new value",
+                    identifier.id
+                )),
+            ),
+        }
+    }
+
+    /// Resolves `identifier` to the innermost visible declaration, `help` is shown when there
+    /// is none at all.
+    fn resolve(&mut self, identifier: &mut Identifier, help: String) {
+        if let Some(symbol) = self.symbols.lookup(&identifier.id) {
+            identifier.symbol = Some(symbol.clone());
+            return;
+        }
+        let id = &identifier.id;
+        let later = self
+            .declarations
+            .iter()
+            .find(|d| &d.id == id && d.span.start > identifier.span.start);
+        let diagnostic = match later {
+            Some(declaration) if declaration.is_input => Diagnostic::error(
+                format!("Identifier {} is used before `input {};`", id, id),
+                identifier.span,
+            )
+            .with_label("used before it has a value")
+            .with_help(format!(
+                "`input {};` on line {} declares it, move that before this use",
+                id, declaration.span.line
+            )),
+            Some(declaration) => Diagnostic::error(
+                format!("Identifier {} is used before it is declared", id),
+                identifier.span,
+            )
+            .with_help(format!(
+                "`{}` is declared on line {}, move that before this use",
+                id, declaration.span.line
+            )),
+            None => Diagnostic::error(
+                format!("Identifier {} is never declared", id),
+                identifier.span,
+            )
+            .with_help(help),
+        };
+        self.diagnostics.push(diagnostic);
+    }
+}
+
+impl Default for SemanticAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn collect_declarations(statements: &[Statement], declarations: &mut Vec<Declaration>) {
+    for statement in statements {
+        match statement {
+            Statement::Let { identifier, .. } | Statement::Input { identifier, .. } => declarations
+                .push(Declaration {
+                    id: identifier.id.clone(),
+                    span: identifier.span,
+                    is_input: matches!(statement, Statement::Input { .. }),
+                }),
+            Statement::If {
+                statements,
+                else_statements,
+                ..
+            } => {
+                collect_declarations(statements, declarations);
+                if let Some(else_statements) = else_statements {
+                    collect_declarations(else_statements, declarations);
+                }
+            }
+            Statement::While { statements, .. } => collect_declarations(statements, declarations),
+            Statement::For {
+                init, statements, ..
+            } => {
+                collect_declarations(std::slice::from_ref(init), declarations);
+                collect_declarations(statements, declarations);
+            }
+            Statement::Print { .. }
+            | Statement::Break { .. }
+            | Statement::Continue { .. }
+            | Statement::Assign { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexing::lexer::{Lexer, Token};
+    use crate::parsing::parser::Parser;
+
+    fn analyze_source(source: &str) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+        let tokens: Vec<Token> = Lexer::from(source).collect();
+        let mut statements = Parser::new(&tokens)
+            .parse()
+            .expect("Expected source to parse");
+        SemanticAnalyzer::new().analyze(&mut statements)?;
+        Ok(statements)
+    }
+
+    fn resolved_name(expression: &Expression) -> &str {
+        match expression {
+            Expression::Primary(Primary::IdentifierExpression(identifier)) => identifier.name(),
+            e => panic!("Expected identifier, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_if_reports_all_problems() {
+        let source = "let a = 1;\nprint b;\nlet a = 2;\nc = a;\nprint a;";
+        let diagnostics = analyze_source(source).unwrap_err();
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Identifier b is never declared",
+                "Identifier a is already declared in this scope",
+                "Identifier c is never declared",
+            ]
+        );
+        assert_eq!(diagnostics[0].span, Span::new(17, 18, 2, 7));
+        assert_eq!(
+            diagnostics[2].help.as_deref(),
+            Some("did you mean `let c = ...`?")
+        );
+    }
+
+    #[test]
+    fn check_if_reports_use_before_declaration() {
+        let source = "print n;\nm = 2;\ninput n;\nlet m = 1;";
+        let diagnostics = analyze_source(source).unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].message,
+            "Identifier n is used before `input n;`"
+        );
+        assert_eq!(
+            diagnostics[0].help.as_deref(),
+            Some("`input n;` on line 3 declares it, move that before this use")
+        );
+        assert_eq!(
+            diagnostics[1].message,
+            "Identifier m is used before it is declared"
+        );
+    }
+
+    #[test]
+    fn check_if_for_variable_is_scoped_to_loop() {
+        let source = "for let i = 0; i < 3; i = i + 1 {\n  let j = i;\n}\nprint i;\nprint j;";
+        let diagnostics = analyze_source(source).unwrap_err();
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.span.line).collect();
+        assert_eq!(lines, vec![4, 5]);
+
+        let source = "let i = 5;\nfor i = 0; i < 3; i = i + 1 {\n}\nprint i;";
+        assert!(analyze_source(source).is_ok());
+    }
+
+    #[test]
+    fn check_if_let_is_scoped_to_block() {
+        let source = "let a = 1;\nwhile a < 3 {\n  let c = a;\n  a = a + c;\n}\nprint c;";
+        let diagnostics = analyze_source(source).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.line, 6);
+
+        let source = "if 1 {\n  let c = 1;\n} else {\n  let c = 2;\n}\nlet c = 3;";
+        assert!(analyze_source(source).is_ok());
+    }
+
+    #[test]
+    fn check_if_shadowing_resolves_to_innermost_declaration() {
+        let source = "let x = 1;\nif x {\n  let x = x + 1;\n  print x;\n}\nprint x;";
+        let statements = analyze_source(source).unwrap();
+        let Statement::If {
+            statements: body, ..
+        } = &statements[1]
+        else {
+            panic!("Expected if, got {:?}", statements[1]);
+        };
+        match (&body[0], &body[1], &statements[2]) {
+            (
+                Statement::Let {
+                    identifier,
+                    expression: Expression::Binary { lhs, .. },
+                    ..
+                },
+                Statement::Print {
+                    option: PrintOption::PrintExpression(inner),
+                    ..
+                },
+                Statement::Print {
+                    option: PrintOption::PrintExpression(outer),
+                    ..
+                },
+            ) => {
+                assert_eq!(identifier.name(), "x_1");
+                assert_eq!(resolved_name(lhs), "x");
+                assert_eq!(resolved_name(inner), "x_1");
+                assert_eq!(resolved_name(outer), "x");
+            }
+            s => panic!("Expected let and prints, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_redeclaration_in_same_scope_fails() {
+        let source = "if 1 {\n  let x = 1;\n  input x;\n}";
+        let diagnostics = analyze_source(source).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Identifier x is already declared in this scope"
+        );
+        assert_eq!(diagnostics[0].span.line, 3);
+    }
+
+    #[test]
+    fn check_if_analyzes_data_3() {
+        let res = analyze_source(include_str!("../../data/3.scrpt"));
+        assert!(res.is_ok(), "Test failed with error: {:?}", res);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+pub type SymbolId = usize;
+
+/// A declared variable. Every `let` and `input` declares a new symbol, even when it shadows an
+/// existing one, so backends can tell them apart by `name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub id: SymbolId,
+    /// Unique across the whole program: the first `x` is `x`, later ones are `x_1`, `x_2`, ...
+    /// Identifiers can not contain `_`, so these never clash with a name from the source.
+    pub name: String,
+}
+
+/// Scope stack used to resolve identifiers. Every `{}` block opens a new scope, a declaration
+/// is visible from its declaration to the end of the block it is declared in.
+pub struct SymbolTable {
+    scopes: Vec<HashMap<String, Symbol>>,
+    declarations: HashMap<String, usize>,
+    next_id: SymbolId,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            declarations: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn exit_scope(&mut self) {
+        assert!(self.scopes.len() > 1, "Can not exit the global scope");
+        self.scopes.pop();
+    }
+
+    /// Declares `id` in the innermost scope, shadowing declarations from outer scopes.
+    /// Returns `None` if `id` is already declared in the innermost scope.
+    pub fn declare(&mut self, id: &str) -> Option<Symbol> {
+        let scope = self.scopes.last_mut().expect("Expected a scope");
+        if scope.contains_key(id) {
+            return None;
+        }
+        let count = self.declarations.entry(id.to_string()).or_insert(0);
+        let name = if *count == 0 {
+            id.to_string()
+        } else {
+            format!("{}_{}", id, count)
+        };
+        *count += 1;
+        let symbol = Symbol {
+            id: self.next_id,
+            name,
+        };
+        self.next_id += 1;
+        scope.insert(id.to_string(), symbol.clone());
+        Some(symbol)
+    }
+
+    /// Finds the declaration `id` refers to, looking from the innermost scope outwards.
+    pub fn lookup(&self, id: &str) -> Option<&Symbol> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(id))
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_declare_and_lookup() {
+        let mut table = SymbolTable::new();
+        let x = table.declare("x").expect("Expected x to be declared");
+        assert_eq!(x.name, "x");
+        assert_eq!(table.lookup("x"), Some(&x));
+        assert_eq!(table.lookup("y"), None);
+        assert_eq!(table.declare("x"), None);
+    }
+
+    #[test]
+    fn check_scopes_and_shadowing() {
+        let mut table = SymbolTable::new();
+        let outer = table.declare("x").unwrap();
+        table.enter_scope();
+        let inner = table
+            .declare("x")
+            .expect("Expected shadowing to be allowed");
+        assert_eq!(inner.name, "x_1");
+        assert_ne!(inner.id, outer.id);
+        assert_eq!(table.lookup("x"), Some(&inner));
+        table.declare("y").unwrap();
+        table.exit_scope();
+
+        assert_eq!(table.lookup("x"), Some(&outer));
+        assert_eq!(table.lookup("y"), None);
+
+        table.enter_scope();
+        assert_eq!(table.declare("y").unwrap().name, "y_1");
+        assert_eq!(table.declare("x").unwrap().name, "x_2");
+        table.exit_scope();
+    }
+}
@@ -0,0 +1,813 @@
+use super::common::{JumpLowering, LoopStack};
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement, UnaryOp,
+};
+
+/// Emits ILAsm text for a console program, to be assembled with `ilasm script.il` from Mono or
+/// the `dotnet` SDK. Every variable is an `int64` local of `Main`, expressions are evaluated on
+/// the evaluation stack.
+///
+/// Numbers are printed with the invariant culture. `input` reads standard input a byte at a time
+/// and parses the longest prefix that is a number like `scanf("%lld")` does, saturating on
+/// overflow. Anything else reads as 0 and the next word is skipped.
+pub struct CilEmitter {
+    statements: Vec<Statement>,
+    code: String,
+    locals: Vec<String>,
+    labels: usize,
+    loops: LoopStack<String>,
+    depth: usize,
+    max_depth: usize,
+}
+
+const PRELUDE: &str = r#".assembly extern mscorlib {}
+.assembly Script {}
+.module Script.exe
+
+.class private auto ansi abstract sealed beforefieldinit Program extends [mscorlib]System.Object
+{
+	// Standard input and its next byte, -1 at the end of the input.
+	.field private static class [mscorlib]System.IO.Stream stdin
+	.field private static int32 peeked
+
+	.method private hidebysig static int32 Peek() cil managed
+	{
+		.maxstack 2
+		ldsfld class [mscorlib]System.IO.Stream Program::stdin
+		brtrue OPENED
+		call class [mscorlib]System.IO.Stream [mscorlib]System.Console::OpenStandardInput()
+		dup
+		stsfld class [mscorlib]System.IO.Stream Program::stdin
+		callvirt instance int32 [mscorlib]System.IO.Stream::ReadByte()
+		stsfld int32 Program::peeked
+	OPENED:
+		ldsfld int32 Program::peeked
+		ret
+	}
+
+	.method private hidebysig static void Advance() cil managed
+	{
+		.maxstack 1
+		ldsfld class [mscorlib]System.IO.Stream Program::stdin
+		callvirt instance int32 [mscorlib]System.IO.Stream::ReadByte()
+		stsfld int32 Program::peeked
+		ret
+	}
+
+	// Whitespace as `isspace` sees it in the C locale.
+	.method private hidebysig static bool IsSpace(int32 'byte') cil managed
+	{
+		.maxstack 2
+		ldarg.0
+		ldc.i4.s 32
+		beq SPACE
+		ldarg.0
+		ldc.i4.s 9
+		sub
+		ldc.i4.5
+		clt.un
+		ret
+	SPACE:
+		ldc.i4.1
+		ret
+	}
+
+	.method private hidebysig static int64 ReadInteger() cil managed
+	{
+		.maxstack 3
+		// The number is accumulated negated, so that the smallest int64 fits as well.
+		.locals init (int64 'value', int64 'digit', bool 'negative', bool 'digits')
+	SKIP_SPACE:
+		call int32 Program::Peek()
+		call bool Program::IsSpace(int32)
+		brfalse SIGN
+		call void Program::Advance()
+		br SKIP_SPACE
+	SIGN:
+		call int32 Program::Peek()
+		ldc.i4.s 45
+		bne.un PLUS
+		ldc.i4.1
+		stloc 'negative'
+		call void Program::Advance()
+		br DIGITS
+	PLUS:
+		call int32 Program::Peek()
+		ldc.i4.s 43
+		bne.un DIGITS
+		call void Program::Advance()
+	DIGITS:
+		call int32 Program::Peek()
+		ldc.i4.s 48
+		sub
+		dup
+		conv.i8
+		stloc 'digit'
+		ldc.i4.s 10
+		bge.un END
+		ldc.i4.1
+		stloc 'digits'
+		ldloc 'value'
+		ldc.i8 -922337203685477580
+		blt OVERFLOW
+		ldloc 'value'
+		ldc.i8 10
+		mul
+		dup
+		stloc 'value'
+		ldc.i8 0x8000000000000000
+		ldloc 'digit'
+		add
+		blt OVERFLOW
+		ldloc 'value'
+		ldloc 'digit'
+		sub
+		stloc 'value'
+		br NEXT_DIGIT
+	OVERFLOW:
+		ldc.i8 0x8000000000000000
+		stloc 'value'
+	NEXT_DIGIT:
+		call void Program::Advance()
+		br DIGITS
+	END:
+		ldloc 'digits'
+		brtrue RESULT
+		// Not a number, scanf("%*s") skips the next word.
+	SKIP_SPACE_BEFORE_WORD:
+		call int32 Program::Peek()
+		call bool Program::IsSpace(int32)
+		brfalse SKIP_WORD
+		call void Program::Advance()
+		br SKIP_SPACE_BEFORE_WORD
+	SKIP_WORD:
+		call int32 Program::Peek()
+		ldc.i4.m1
+		beq ZERO
+		call int32 Program::Peek()
+		call bool Program::IsSpace(int32)
+		brtrue ZERO
+		call void Program::Advance()
+		br SKIP_WORD
+	ZERO:
+		ldc.i8 0
+		ret
+	RESULT:
+		ldloc 'negative'
+		brtrue NEGATIVE
+		ldloc 'value'
+		ldc.i8 0x8000000000000000
+		beq MAXIMUM
+		ldloc 'value'
+		neg
+		ret
+	MAXIMUM:
+		ldc.i8 9223372036854775807
+		ret
+	NEGATIVE:
+		ldloc 'value'
+		ret
+	}
+
+	.method public hidebysig static void Main() cil managed
+	{
+		.entrypoint
+"#;
+
+impl CilEmitter {
+    pub fn new(program: &[Statement]) -> Self {
+        CilEmitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            locals: Vec::new(),
+            labels: 0,
+            loops: LoopStack::default(),
+            depth: 0,
+            max_depth: 0,
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        for statement in &self.statements.clone() {
+            self.emit_statement(statement);
+        }
+        self.instruction("ret", 0);
+
+        let mut emit = String::from(PRELUDE);
+        emit.push_str(&format!("\t\t.maxstack {}\n", self.max_depth.max(1)));
+        if !self.locals.is_empty() {
+            let locals: Vec<String> = self
+                .locals
+                .iter()
+                .map(|local| format!("int64 '{}'", local))
+                .collect();
+            emit.push_str(&format!("\t\t.locals init ({})\n", locals.join(", ")));
+        }
+        emit.push_str(&self.code);
+        emit.push_str("\t}\n");
+        emit.push_str("}\n");
+        emit
+    }
+
+    /// `effect` is how many values the instruction leaves on the stack minus how many it takes,
+    /// which is what `.maxstack` is computed from.
+    fn instruction(&mut self, instruction: &str, effect: isize) {
+        self.depth = self.depth.saturating_add_signed(effect);
+        self.max_depth = self.max_depth.max(self.depth);
+        self.code.push_str("\t\t");
+        self.code.push_str(instruction);
+        self.code.push('\n');
+    }
+
+    /// Local names are quoted, so they can never clash with an ILAsm keyword.
+    fn local(&mut self, name: &str) -> String {
+        if !self.locals.iter().any(|local| local == name) {
+            self.locals.push(name.to_string());
+        }
+        format!("'{}'", name)
+    }
+
+    /// Declarations have unique names after semantic analysis, so they can name the local.
+    fn variable(&mut self, identifier: &Identifier) -> String {
+        self.local(identifier.name())
+    }
+
+    fn emit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print { option, .. } => self.emit_print(option),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                let else_label = self.new_label();
+                self.emit_jump(comparison, false, &else_label);
+                self.emit_block(statements);
+                match else_statements {
+                    Some(else_statements) => {
+                        let end_label = self.new_label();
+                        self.instruction(&format!("br {}", end_label), 0);
+                        self.place_label(&else_label);
+                        self.emit_block(else_statements);
+                        self.place_label(&end_label);
+                    }
+                    None => self.place_label(&else_label),
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                let start_label = self.new_label();
+                let end_label = self.new_label();
+                self.place_label(&start_label);
+                self.emit_jump(comparison, false, &end_label);
+                self.emit_loop_body(statements, &start_label, &end_label);
+                self.instruction(&format!("br {}", start_label), 0);
+                self.place_label(&end_label);
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                let start_label = self.new_label();
+                let step_label = self.new_label();
+                let end_label = self.new_label();
+                self.emit_statement(init);
+                self.place_label(&start_label);
+                self.emit_jump(comparison, false, &end_label);
+                self.emit_loop_body(statements, &step_label, &end_label);
+                self.place_label(&step_label);
+                self.emit_statement(step);
+                self.instruction(&format!("br {}", start_label), 0);
+                self.place_label(&end_label);
+            }
+            Statement::Break { .. } => {
+                let break_label = self.loops.break_target();
+                self.emit_goto(&break_label);
+            }
+            Statement::Continue { .. } => {
+                let continue_label = self.loops.continue_target();
+                self.emit_goto(&continue_label);
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                self.emit_expression(expression);
+                let variable = self.variable(identifier);
+                self.instruction(&format!("stloc {}", variable), -1);
+            }
+            Statement::Input { identifier, .. } => {
+                self.instruction("call int64 Program::ReadInteger()", 1);
+                let variable = self.variable(identifier);
+                self.instruction(&format!("stloc {}", variable), -1);
+            }
+        }
+    }
+
+    fn emit_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.emit_statement(statement);
+        }
+    }
+
+    fn emit_loop_body(&mut self, statements: &[Statement], continue_label: &str, end_label: &str) {
+        self.loops
+            .push(continue_label.to_string(), end_label.to_string());
+        self.emit_block(statements);
+        self.loops.pop();
+    }
+
+    fn emit_print(&mut self, print_option: &PrintOption) {
+        match print_option {
+            PrintOption::PrintLiteral(s) => {
+                self.instruction(&format!("ldstr {}", Self::emit_string(&unescape(s))), 1);
+            }
+            PrintOption::PrintExpression(e) => {
+                self.emit_expression(e);
+                // `Write(int64)` would format with the current culture.
+                self.instruction(
+                    "call class [mscorlib]System.Globalization.CultureInfo [mscorlib]System.Globalization.CultureInfo::get_InvariantCulture()",
+                    1,
+                );
+                self.instruction(
+                    "call string [mscorlib]System.Convert::ToString(int64, class [mscorlib]System.IFormatProvider)",
+                    -1,
+                );
+                self.instruction("call void [mscorlib]System.Console::Write(string)", -1);
+                // Not WriteLine, which would end the line with "\r\n" on Windows.
+                self.instruction("ldstr \"\\n\"", 1);
+            }
+        }
+        self.instruction("call void [mscorlib]System.Console::Write(string)", -1);
+    }
+
+    /// The branch taken when `lhs op rhs` does not hold.
+    fn inverse_branch(op: &RelationalOp) -> &'static str {
+        match op {
+            RelationalOp::GreaterThan => "ble",
+            RelationalOp::GreaterThanEquals => "blt",
+            RelationalOp::LessThan => "bge",
+            RelationalOp::LessThanEquals => "bgt",
+            RelationalOp::EqualsEquals => "bne.un",
+            RelationalOp::NotEquals => "beq",
+        }
+    }
+
+    fn emit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                self.emit_expression(lhs);
+                self.emit_expression(rhs);
+                let instruction = match op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Subtract => "sub",
+                    BinaryOp::Multiply => "mul",
+                    BinaryOp::Divide => "div",
+                    BinaryOp::Modulo => "rem",
+                };
+                self.instruction(instruction, -1);
+            }
+            Expression::Unary { op, operand, .. } => {
+                self.emit_primary(operand);
+                if *op == UnaryOp::Minus {
+                    self.instruction("neg", 0);
+                }
+            }
+            Expression::Primary(p) => self.emit_primary(p),
+        }
+    }
+
+    fn emit_primary(&mut self, primary: &Primary) {
+        match primary {
+            Primary::LiteralNumber(n, _) => self.instruction(&format!("ldc.i8 {}", n), 1),
+            Primary::IdentifierExpression(id) => {
+                let variable = self.variable(id);
+                self.instruction(&format!("ldloc {}", variable), 1);
+            }
+            Primary::Grouped(e, _) => self.emit_expression(e),
+        }
+    }
+
+    /// Quotes an already unescaped string for `ldstr`. Strings with anything but printable ASCII
+    /// and the common escapes are written as their UTF-16 bytes, which does not depend on how
+    /// ilasm reads the file.
+    fn emit_string(string: &str) -> String {
+        let quotable = |c: char| matches!(c, ' '..='~' | '\n' | '\t' | '\r');
+        if !string.chars().all(quotable) {
+            let bytes: Vec<String> = string
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            return format!("bytearray ({})", bytes.join(" "));
+        }
+        let mut emit = String::from("\"");
+        for c in string.chars() {
+            match c {
+                '"' => emit.push_str("\\\""),
+                '\\' => emit.push_str("\\\\"),
+                '\n' => emit.push_str("\\n"),
+                '\t' => emit.push_str("\\t"),
+                '\r' => emit.push_str("\\r"),
+                c => emit.push(c),
+            }
+        }
+        emit.push('"');
+        emit
+    }
+}
+
+impl JumpLowering for CilEmitter {
+    type Label = String;
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn place_label(&mut self, label: &String) {
+        self.code.push('\t');
+        self.code.push_str(label);
+        self.code.push_str(":\n");
+    }
+
+    fn emit_goto(&mut self, target: &String) {
+        self.instruction(&format!("br {}", target), 0);
+    }
+
+    fn emit_chain_failure(
+        &mut self,
+        lhs: &Expression,
+        rhs: &[(RelationalOp, Expression)],
+        target: &String,
+    ) {
+        self.emit_expression(lhs);
+        for (i, (op, operand)) in rhs.iter().enumerate() {
+            self.emit_expression(operand);
+            let temporary = if i + 1 < rhs.len() {
+                let temporary = self.local(&format!("mmc_tmp_{}", operand.span().start));
+                self.instruction("dup", 1);
+                self.instruction(&format!("stloc {}", temporary), -1);
+                Some(temporary)
+            } else {
+                None
+            };
+            self.instruction(&format!("{} {}", Self::inverse_branch(op), target), -2);
+            if let Some(temporary) = temporary {
+                self.instruction(&format!("ldloc {}", temporary), 1);
+            }
+        }
+    }
+
+    fn emit_value_jump(&mut self, expression: &Expression, when: bool, target: &String) {
+        self.emit_expression(expression);
+        let branch = if when { "brtrue" } else { "brfalse" };
+        self.instruction(&format!("{} {}", branch, target), -1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, interpret, EDGE_CASES};
+    use std::collections::HashMap;
+
+    /// The body of `Main` after `.entrypoint`.
+    fn emit_body(source: &str) -> String {
+        let statements = analyze(source);
+        let code = CilEmitter::new(&statements).emit();
+        code.strip_prefix(PRELUDE).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+while n > 0 && !(n == 3) {
+    if 0 < n <= 10 {
+        break;
+    }
+    n = -n / (2 - n);
+}
+print \"\u{e9}\\n\";";
+        let expected_code = "\t\t.maxstack 3
+\t\t.locals init (int64 'n', int64 'mmc_tmp_47')
+\t\tcall int64 Program::ReadInteger()
+\t\tstloc 'n'
+\tL1:
+\t\tldloc 'n'
+\t\tldc.i8 0
+\t\tble L2
+\t\tldloc 'n'
+\t\tldc.i8 3
+\t\tbne.un L3
+\t\tbr L2
+\tL3:
+\t\tldc.i8 0
+\t\tldloc 'n'
+\t\tdup
+\t\tstloc 'mmc_tmp_47'
+\t\tbge L4
+\t\tldloc 'mmc_tmp_47'
+\t\tldc.i8 10
+\t\tbgt L4
+\t\tbr L2
+\tL4:
+\t\tldloc 'n'
+\t\tneg
+\t\tldc.i8 2
+\t\tldloc 'n'
+\t\tsub
+\t\tdiv
+\t\tstloc 'n'
+\t\tbr L1
+\tL2:
+\t\tldstr bytearray (E9 00 0A 00)
+\t\tcall void [mscorlib]System.Console::Write(string)
+\t\tret
+\t}
+}
+";
+        assert_eq!(emit_body(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_for_and_print() {
+        let source = "\
+for let i = 0; i < 3; i = i + 1 {
+    if i == 1 {
+        continue;
+    }
+    print i * (i + 1);
+}
+print \"\\\\100%\\t\\n\";";
+        let expected_code = "\t\t.maxstack 3
+\t\t.locals init (int64 'i')
+\t\tldc.i8 0
+\t\tstloc 'i'
+\tL1:
+\t\tldloc 'i'
+\t\tldc.i8 3
+\t\tbge L3
+\t\tldloc 'i'
+\t\tldc.i8 1
+\t\tbne.un L4
+\t\tbr L2
+\tL4:
+\t\tldloc 'i'
+\t\tldloc 'i'
+\t\tldc.i8 1
+\t\tadd
+\t\tmul
+\t\tcall class [mscorlib]System.Globalization.CultureInfo [mscorlib]System.Globalization.CultureInfo::get_InvariantCulture()
+\t\tcall string [mscorlib]System.Convert::ToString(int64, class [mscorlib]System.IFormatProvider)
+\t\tcall void [mscorlib]System.Console::Write(string)
+\t\tldstr \"\\n\"
+\t\tcall void [mscorlib]System.Console::Write(string)
+\tL2:
+\t\tldloc 'i'
+\t\tldc.i8 1
+\t\tadd
+\t\tstloc 'i'
+\t\tbr L1
+\tL3:
+\t\tldstr \"\\\\100%\\t\\n\"
+\t\tcall void [mscorlib]System.Console::Write(string)
+\t\tret
+\t}
+}
+";
+        assert_eq!(emit_body(source), expected_code);
+    }
+
+    #[derive(Clone, Debug)]
+    enum Value {
+        Integer(i64),
+        String(String),
+    }
+
+    impl Value {
+        fn integer(self) -> i64 {
+            match self {
+                Value::Integer(value) => value,
+                Value::String(string) => panic!("Expected an integer, got {:?}", string),
+            }
+        }
+    }
+
+    /// Runs an assembly in the subset of CIL the emitter produces, the reading helpers of the
+    /// prelude included, with a runtime that provides the few framework methods they call.
+    struct Machine<'a> {
+        methods: HashMap<&'a str, Vec<&'a str>>,
+        fields: HashMap<&'a str, i64>,
+        input: std::str::Bytes<'a>,
+        output: String,
+    }
+
+    impl<'a> Machine<'a> {
+        fn new(assembly: &'a str, input: &'a str) -> Self {
+            let mut methods = HashMap::new();
+            let mut lines = assembly.lines().map(str::trim);
+            while let Some(line) = lines.next() {
+                if line.starts_with(".method") {
+                    let name = line.split('(').next().unwrap().rsplit(' ').next().unwrap();
+                    let body = lines.by_ref().skip(1).take_while(|line| *line != "}");
+                    methods.insert(name, body.collect());
+                }
+            }
+            Machine {
+                methods,
+                fields: HashMap::new(),
+                input: input.bytes(),
+                output: String::new(),
+            }
+        }
+
+        fn call(&mut self, method: &str, args: &[Value]) -> Option<Value> {
+            let body = self.methods[method].clone();
+            let labels: HashMap<&str, usize> = body
+                .iter()
+                .enumerate()
+                .filter_map(|(index, line)| Some((line.strip_suffix(':')?, index)))
+                .collect();
+            let mut locals = HashMap::new();
+            let mut stack = Vec::new();
+            let mut pc = 0;
+            while pc < body.len() {
+                let line = body[pc];
+                let (op, arg) = line.split_once(' ').unwrap_or((line, ""));
+                pc += 1;
+                match op {
+                    ".locals" => {
+                        for name in arg.split('\'').skip(1).step_by(2) {
+                            locals.insert(name, Value::Integer(0));
+                        }
+                    }
+                    "ldarg.0" => stack.push(args[0].clone()),
+                    "ldc.i4.s" => stack.push(Value::Integer(arg.parse().unwrap())),
+                    "ldc.i4.m1" => stack.push(Value::Integer(-1)),
+                    _ if op.starts_with("ldc.i4.") => {
+                        let value = op["ldc.i4.".len()..].parse().unwrap();
+                        stack.push(Value::Integer(value));
+                    }
+                    "ldc.i8" => stack.push(Value::Integer(match arg.strip_prefix("0x") {
+                        Some(hex) => u64::from_str_radix(hex, 16).unwrap() as i64,
+                        None => arg.parse().unwrap(),
+                    })),
+                    "ldstr" => stack.push(Value::String(decode_string(arg))),
+                    "ldloc" => stack.push(locals[arg.trim_matches('\'')].clone()),
+                    "stloc" => {
+                        locals.insert(arg.trim_matches('\''), stack.pop().unwrap());
+                    }
+                    "ldsfld" => {
+                        let field = arg.rsplit("::").next().unwrap();
+                        let value = self.fields.get(field).copied().unwrap_or(0);
+                        stack.push(Value::Integer(value));
+                    }
+                    "stsfld" => {
+                        let field = arg.rsplit("::").next().unwrap();
+                        let value = stack.pop().unwrap().integer();
+                        self.fields.insert(field, value);
+                    }
+                    "dup" => stack.push(stack.last().unwrap().clone()),
+                    "conv.i8" => {}
+                    "neg" => {
+                        let value = stack.pop().unwrap().integer();
+                        stack.push(Value::Integer(value.wrapping_neg()));
+                    }
+                    "br" => pc = labels[arg],
+                    "brtrue" | "brfalse" => {
+                        if (stack.pop().unwrap().integer() != 0) == (op == "brtrue") {
+                            pc = labels[arg];
+                        }
+                    }
+                    "call" | "callvirt" => {
+                        if let Some(value) = self.call_method(arg, &mut stack) {
+                            stack.push(value);
+                        }
+                    }
+                    "ret" => return stack.pop(),
+                    _ if op.starts_with('.') || op.starts_with("//") || op.ends_with(':') => {}
+                    _ => {
+                        let rhs = stack.pop().unwrap().integer();
+                        let lhs = stack.pop().unwrap().integer();
+                        // The reading helpers compare int32 bytes unsigned, which their sign
+                        // extension to int64 here keeps intact.
+                        let (unsigned_lhs, unsigned_rhs) = (lhs as u64, rhs as u64);
+                        let branch = match op {
+                            "beq" => Some(lhs == rhs),
+                            "bne.un" => Some(lhs != rhs),
+                            "bge" => Some(lhs >= rhs),
+                            "bgt" => Some(lhs > rhs),
+                            "ble" => Some(lhs <= rhs),
+                            "blt" => Some(lhs < rhs),
+                            "bge.un" => Some(unsigned_lhs >= unsigned_rhs),
+                            _ => None,
+                        };
+                        if let Some(taken) = branch {
+                            if taken {
+                                pc = labels[arg];
+                            }
+                            continue;
+                        }
+                        stack.push(Value::Integer(match op {
+                            "add" => lhs.wrapping_add(rhs),
+                            "sub" => lhs.wrapping_sub(rhs),
+                            "mul" => lhs.wrapping_mul(rhs),
+                            "div" => lhs.checked_div(rhs).expect("Expected no exception"),
+                            "rem" => lhs.checked_rem(rhs).expect("Expected no exception"),
+                            "clt.un" => (unsigned_lhs < unsigned_rhs) as i64,
+                            _ => panic!("Unknown instruction {}", line),
+                        }));
+                    }
+                }
+            }
+            panic!("Expected {} to return", method)
+        }
+
+        /// Calls `signature`, with its arguments popped off `stack`.
+        fn call_method(&mut self, signature: &str, stack: &mut Vec<Value>) -> Option<Value> {
+            let (name, parameters) = signature.split_once('(').unwrap();
+            if let Some((_, method)) = name.split_once(" Program::") {
+                let count = parameters.split(',').filter(|p| *p != ")").count();
+                let args = stack.split_off(stack.len() - count);
+                return self.call(method, &args);
+            }
+            match name.rsplit("::").next().unwrap() {
+                "OpenStandardInput" => Some(Value::Integer(1)),
+                "ReadByte" => {
+                    stack.pop();
+                    Some(Value::Integer(self.input.next().map_or(-1, i64::from)))
+                }
+                "get_InvariantCulture" => Some(Value::Integer(0)),
+                "ToString" => {
+                    stack.pop();
+                    let value = stack.pop().unwrap().integer();
+                    Some(Value::String(value.to_string()))
+                }
+                "Write" => match stack.pop().unwrap() {
+                    Value::String(string) => {
+                        self.output.push_str(&string);
+                        None
+                    }
+                    Value::Integer(value) => panic!("Expected a string, got {}", value),
+                },
+                _ => panic!("Unknown method {}", signature),
+            }
+        }
+    }
+
+    /// The string of an `ldstr` operand, quoted or as UTF-16 bytes.
+    fn decode_string(operand: &str) -> String {
+        if let Some(bytes) = operand.strip_prefix("bytearray (") {
+            let bytes: Vec<u8> = bytes
+                .trim_end_matches(')')
+                .split(' ')
+                .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+                .collect();
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            return String::from_utf16(&units).unwrap();
+        }
+        let mut string = String::new();
+        let mut chars = operand[1..operand.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            string.push(match c {
+                '\\' => match chars.next().unwrap() {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    c => c,
+                },
+                c => c,
+            });
+        }
+        string
+    }
+
+    #[test]
+    fn test_edge_cases_run_like_interpreter() {
+        for (source, input) in EDGE_CASES {
+            let assembly = CilEmitter::new(&analyze(source)).emit();
+            let mut machine = Machine::new(&assembly, input);
+            machine.call("Main", &[]);
+            assert_eq!(machine.output, interpret(source, input), "{}", source);
+        }
+    }
+}
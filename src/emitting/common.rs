@@ -0,0 +1,262 @@
+use crate::parsing::parser::{Comparison, Expression, Primary, RelationalOp, Statement};
+
+/// The `printf` and `scanf` formats of the backends that call into libc, by the name of the
+/// label they are emitted under. When `input` does not match, the variable is set to 0 and
+/// `skip` reads the word that is not a number, like the C backend does.
+pub const FORMATS: [(&str, &str); 4] = [
+    ("number", "%lld\n"),
+    ("string", "%s"),
+    ("input", "%lld"),
+    ("skip", "%*s"),
+];
+
+/// Continue and break targets of the loops being emitted, innermost last.
+pub struct LoopStack<L> {
+    targets: Vec<(L, L)>,
+}
+
+impl<L> Default for LoopStack<L> {
+    fn default() -> Self {
+        LoopStack {
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl<L: Clone> LoopStack<L> {
+    pub fn push(&mut self, continue_target: L, break_target: L) {
+        self.targets.push((continue_target, break_target));
+    }
+
+    pub fn pop(&mut self) {
+        self.targets.pop();
+    }
+
+    pub fn continue_target(&self) -> L {
+        let (target, _) = self.targets.last().expect("Continue outside of a loop");
+        target.clone()
+    }
+
+    pub fn break_target(&self) -> L {
+        let (_, target) = self.targets.last().expect("Break outside of a loop");
+        target.clone()
+    }
+}
+
+/// Escapes an already unescaped string for a `.string` directive of GNU as.
+pub fn escape_gas_string(string: &str) -> String {
+    let mut escaped = String::new();
+    for byte in string.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\t' => escaped.push_str("\\t"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
+
+/// Lowering of conditions for targets whose conditional jumps fall through when not taken.
+pub trait JumpLowering {
+    type Label: Clone;
+
+    fn new_label(&mut self) -> Self::Label;
+
+    fn place_label(&mut self, label: &Self::Label);
+
+    fn emit_goto(&mut self, target: &Self::Label);
+
+    /// Jumps to `target` at the first link of `lhs op rhs ...` that does not hold.
+    fn emit_chain_failure(
+        &mut self,
+        lhs: &Expression,
+        rhs: &[(RelationalOp, Expression)],
+        target: &Self::Label,
+    );
+
+    /// Jumps to `target` when `expression` is not zero, or when it is if `when` is false.
+    fn emit_value_jump(&mut self, expression: &Expression, when: bool, target: &Self::Label);
+
+    /// Jumps to `target` when `comparison` evaluates to `when`, falls through otherwise.
+    fn emit_jump(&mut self, comparison: &Comparison, when: bool, target: &Self::Label) {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                if when {
+                    let fail_label = self.new_label();
+                    self.emit_chain_failure(lhs, rhs, &fail_label);
+                    self.emit_goto(target);
+                    self.place_label(&fail_label);
+                } else {
+                    self.emit_chain_failure(lhs, rhs, target);
+                }
+            }
+            Comparison::Value(e) => self.emit_value_jump(e, when, target),
+            Comparison::Not(c) => self.emit_jump(c, !when, target),
+            Comparison::And(lhs, rhs) => {
+                if when {
+                    let skip_label = self.new_label();
+                    self.emit_jump(lhs, false, &skip_label);
+                    self.emit_jump(rhs, true, target);
+                    self.place_label(&skip_label);
+                } else {
+                    self.emit_jump(lhs, false, target);
+                    self.emit_jump(rhs, false, target);
+                }
+            }
+            Comparison::Or(lhs, rhs) => {
+                if when {
+                    self.emit_jump(lhs, true, target);
+                    self.emit_jump(rhs, true, target);
+                } else {
+                    let skip_label = self.new_label();
+                    self.emit_jump(lhs, true, &skip_label);
+                    self.emit_jump(rhs, false, target);
+                    self.place_label(&skip_label);
+                }
+            }
+        }
+    }
+}
+
+/// Lowering of conditions for SSA targets, where every block ends in a two-way branch.
+pub trait BranchLowering {
+    fn new_label(&mut self) -> String;
+
+    /// Starts a new block, the current one falls through into it unless it ends in a branch.
+    fn block(&mut self, label: &str);
+
+    /// Returns the operand holding the value of `expression`.
+    fn emit_operand(&mut self, expression: &Expression) -> String;
+
+    /// Returns the operand holding whether `lhs op rhs` holds.
+    fn emit_compare(&mut self, op: &RelationalOp, lhs: &str, rhs: &str) -> String;
+
+    fn emit_conditional_branch(&mut self, condition: &str, true_label: &str, false_label: &str);
+
+    /// Branches to `true_label` or `false_label` depending on `comparison`.
+    fn emit_branch(&mut self, comparison: &Comparison, true_label: &str, false_label: &str) {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                // Each link of a chain reuses the value of the one before, `0 <= i < n` needs
+                // `i` only once.
+                let mut left = self.emit_operand(lhs);
+                for (i, (op, operand)) in rhs.iter().enumerate() {
+                    let right = self.emit_operand(operand);
+                    let holds = self.emit_compare(op, &left, &right);
+                    let next_label = if i + 1 < rhs.len() {
+                        self.new_label()
+                    } else {
+                        true_label.to_string()
+                    };
+                    self.emit_conditional_branch(&holds, &next_label, false_label);
+                    if i + 1 < rhs.len() {
+                        self.block(&next_label);
+                    }
+                    left = right;
+                }
+            }
+            Comparison::Value(e) => {
+                // Branches take a boolean, so the value is compared against 0 first.
+                let value = self.emit_operand(e);
+                let holds = self.emit_compare(&RelationalOp::NotEquals, &value, "0");
+                self.emit_conditional_branch(&holds, true_label, false_label);
+            }
+            Comparison::Not(c) => self.emit_branch(c, false_label, true_label),
+            Comparison::And(lhs, rhs) => {
+                let rhs_label = self.new_label();
+                self.emit_branch(lhs, &rhs_label, false_label);
+                self.block(&rhs_label);
+                self.emit_branch(rhs, true_label, false_label);
+            }
+            Comparison::Or(lhs, rhs) => {
+                let rhs_label = self.new_label();
+                self.emit_branch(lhs, true_label, &rhs_label);
+                self.block(&rhs_label);
+                self.emit_branch(rhs, true_label, false_label);
+            }
+        }
+    }
+}
+
+/// Temporaries needed by the conditions of `statement`, including those of its `else if`s.
+pub fn temporaries(statement: &Statement) -> Vec<String> {
+    let mut temporaries = Vec::new();
+    match statement {
+        Statement::If {
+            comparison,
+            else_statements,
+            ..
+        } => {
+            collect_temporaries(comparison, &mut temporaries);
+            let mut else_statements = else_statements.as_deref();
+            // else if conditions can not get declarations of their own.
+            while let Some(
+                [Statement::If {
+                    comparison,
+                    else_statements: next,
+                    ..
+                }],
+            ) = else_statements
+            {
+                collect_temporaries(comparison, &mut temporaries);
+                else_statements = next.as_deref();
+            }
+        }
+        Statement::While { comparison, .. } | Statement::For { comparison, .. } => {
+            collect_temporaries(comparison, &mut temporaries)
+        }
+        _ => {}
+    }
+    temporaries
+}
+
+/// Middle operands of chained comparisons show up twice when they are emitted as source code,
+/// so anything more involved than a number or a variable is evaluated once into a temporary.
+fn collect_temporaries(comparison: &Comparison, temporaries: &mut Vec<String>) {
+    match comparison {
+        Comparison::Relational { rhs, .. } => {
+            for (_, operand) in rhs.iter().take(rhs.len() - 1) {
+                if !is_trivial(operand) {
+                    temporaries.push(temporary_name(operand));
+                }
+            }
+        }
+        Comparison::Value(_) => {}
+        Comparison::Not(c) => collect_temporaries(c, temporaries),
+        Comparison::And(lhs, rhs) | Comparison::Or(lhs, rhs) => {
+            collect_temporaries(lhs, temporaries);
+            collect_temporaries(rhs, temporaries);
+        }
+    }
+}
+
+pub fn is_trivial(expression: &Expression) -> bool {
+    match expression {
+        Expression::Unary { operand: p, .. } | Expression::Primary(p) => matches!(
+            p,
+            Primary::LiteralNumber(..) | Primary::IdentifierExpression(_)
+        ),
+        Expression::Binary { .. } => false,
+    }
+}
+
+/// Source identifiers never contain `_`, so these can not clash with user variables.
+pub fn temporary_name(expression: &Expression) -> String {
+    format!("mmc_tmp_{}", expression.span().start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_gas_string() {
+        assert_eq!(
+            escape_gas_string("a \"b\"\\\t\n\r"),
+            "a \\\"b\\\"\\\\\\t\\n\\015"
+        );
+    }
+}
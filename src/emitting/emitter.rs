@@ -1,7 +1,5 @@
-use crate::parsing::parser::{
-    Comparison, Expression, ExpressionOp, Identifier, Primary, PrintOption, Statement, Term,
-    TermOp, Unary,
-};
+use super::common::{is_trivial, temporaries, temporary_name};
+use crate::parsing::parser::{Comparison, Expression, Identifier, Primary, PrintOption, Statement};
 pub struct Indent {
     indent_level: usize,
 }
@@ -24,6 +22,11 @@ impl Indent {
     pub fn current_indent(&self) -> String {
         "\t".repeat(self.indent_level)
     }
+
+    /// Four spaces per level, for languages where that is the convention.
+    pub fn current_indent_spaces(&self) -> String {
+        "    ".repeat(self.indent_level)
+    }
 }
 pub struct CEmitter {
     statements: Vec<Statement>,
@@ -55,41 +58,80 @@ impl CEmitter {
     fn emit_statement(statement: &Statement, indent: &mut Indent) -> String {
         let mut emit = String::new();
         emit.push_str(&indent.current_indent());
+        emit.push_str(&Self::emit_temporaries(statement, indent));
         match statement {
-            Statement::Print { option } => emit.push_str(&Self::emit_print(option)),
+            Statement::Print { option, .. } => emit.push_str(&Self::emit_print(option)),
             Statement::If {
                 comparison,
                 statements,
-            } => emit.push_str(&Self::emit_if(comparison, statements, indent)),
+                else_statements,
+                ..
+            } => emit.push_str(&Self::emit_if(
+                comparison,
+                statements,
+                else_statements.as_deref(),
+                indent,
+            )),
             Statement::While {
                 comparison,
                 statements,
+                ..
             } => emit.push_str(&Self::emit_while(comparison, statements, indent)),
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => emit.push_str(&Self::emit_for(init, comparison, step, statements, indent)),
+            Statement::Break { .. } => emit.push_str("break;"),
+            Statement::Continue { .. } => emit.push_str("continue;"),
             Statement::Let {
                 identifier,
                 expression,
+                ..
             } => emit.push_str(&Self::emit_let(identifier, expression)),
-            Statement::Input { identifier } => emit.push_str(&Self::emit_input(identifier, indent)),
+            Statement::Input { identifier, .. } => {
+                emit.push_str(&Self::emit_input(identifier, indent))
+            }
             Statement::Assign {
                 identifier,
                 expression,
+                ..
             } => emit.push_str(&Self::emit_assign(identifier, expression)),
         };
         emit.push('\n');
         emit
     }
 
+    /// Declares the temporaries used by the conditions of `statement` right before it.
+    fn emit_temporaries(statement: &Statement, indent: &mut Indent) -> String {
+        let mut emit = String::new();
+        for temporary in temporaries(statement) {
+            emit.push_str(
+                format!("long long {};\n{}", temporary, &indent.current_indent()).as_str(),
+            );
+        }
+        emit
+    }
+
     fn emit_print(print_option: &PrintOption) -> String {
         let mut emit = String::new();
         match print_option {
-            PrintOption::PrintLiteral(s) => emit.push_str(format!("printf(\"{}\");", s).as_str()),
-            PrintOption::PrintExpression(e) => emit.push_str(
-                format!("printf(\"%d\\n\", (int)({}));", &Self::emit_expression(e)).as_str(),
-            ),
+            PrintOption::PrintLiteral(s) => {
+                emit.push_str(format!("printf(\"{}\");", s.replace('%', "%%")).as_str())
+            }
+            PrintOption::PrintExpression(e) => emit
+                .push_str(format!("printf(\"%lld\\n\", {});", &Self::emit_expression(e)).as_str()),
         }
         emit
     }
-    fn emit_if(comparison: &Comparison, statements: &[Statement], indent: &mut Indent) -> String {
+    fn emit_if(
+        comparison: &Comparison,
+        statements: &[Statement],
+        else_statements: Option<&[Statement]>,
+        indent: &mut Indent,
+    ) -> String {
         let mut emit = String::new();
         emit.push_str(format!("if ({}) {{\n", &Self::emit_comparison(comparison)).as_str());
         indent.increase();
@@ -99,6 +141,35 @@ impl CEmitter {
         indent.decrease();
         emit.push_str(&indent.current_indent());
         emit.push('}');
+        match else_statements {
+            Some(
+                [Statement::If {
+                    comparison,
+                    statements,
+                    else_statements,
+                    ..
+                }],
+            ) => {
+                emit.push_str(" else ");
+                emit.push_str(&Self::emit_if(
+                    comparison,
+                    statements,
+                    else_statements.as_deref(),
+                    indent,
+                ));
+            }
+            Some(else_statements) => {
+                emit.push_str(" else {\n");
+                indent.increase();
+                for statement in else_statements {
+                    emit.push_str(Self::emit_statement(statement, indent).as_str())
+                }
+                indent.decrease();
+                emit.push_str(&indent.current_indent());
+                emit.push('}');
+            }
+            None => {}
+        }
         emit
     }
     fn emit_while(
@@ -118,12 +189,60 @@ impl CEmitter {
         emit.push('}');
         emit
     }
+    fn emit_for(
+        init: &Statement,
+        comparison: &Comparison,
+        step: &Statement,
+        statements: &[Statement],
+        indent: &mut Indent,
+    ) -> String {
+        let init = match init {
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            } => Self::emit_let(identifier, expression),
+            Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => Self::emit_assign(identifier, expression),
+            _ => panic!("For loop init should be a let or an assignment"),
+        };
+        let step = match step {
+            Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => Self::emit_assign(identifier, expression),
+            _ => panic!("For loop step should be an assignment"),
+        };
+        let mut emit = String::new();
+        emit.push_str(
+            format!(
+                "for ({} {}; {}) {{\n",
+                init,
+                &Self::emit_comparison(comparison),
+                step.trim_end_matches(';')
+            )
+            .as_str(),
+        );
+        indent.increase();
+        for statement in statements {
+            emit.push_str(Self::emit_statement(statement, indent).as_str())
+        }
+        indent.decrease();
+        emit.push_str(&indent.current_indent());
+
+        emit.push('}');
+        emit
+    }
     fn emit_let(identifier: &Identifier, expression: &Expression) -> String {
         let mut emit = String::new();
         emit.push_str(
             format!(
-                "int {} = {};",
-                identifier.id,
+                "long long {} = {};",
+                identifier.name(),
                 &Self::emit_expression(expression)
             )
             .as_str(),
@@ -134,12 +253,12 @@ impl CEmitter {
         let mut emit = String::new();
         emit.push_str(
             format!(
-                "int {};\n{}if(0==scanf(\"%d\", &{})) {{\n{}\t{} = 0;\n{}\tscanf(\"%*s\");\n{}}}",
-                identifier.id,
+                "long long {};\n{}if(1!=scanf(\"%lld\", &{})) {{\n{}\t{} = 0;\n{}\tscanf(\"%*s\");\n{}}}",
+                identifier.name(),
                 &indent.current_indent(),
-                identifier.id,
+                identifier.name(),
                 &indent.current_indent(),
-                identifier.id,
+                identifier.name(),
                 &indent.current_indent(),
                 &indent.current_indent()
             )
@@ -152,7 +271,7 @@ impl CEmitter {
         emit.push_str(
             format!(
                 "{} = {};",
-                identifier.id,
+                identifier.name(),
                 &Self::emit_expression(expression)
             )
             .as_str(),
@@ -161,62 +280,90 @@ impl CEmitter {
     }
     fn emit_comparison(comparison: &Comparison) -> String {
         let mut emit = String::new();
-        let (lhs, rhs, sign) = match comparison {
-            Comparison::GreaterThan { lhs, rhs } => (lhs, rhs, ">"),
-            Comparison::GreaterThanEquals { lhs, rhs } => (lhs, rhs, ">="),
-            Comparison::LessThan { lhs, rhs } => (lhs, rhs, "<"),
-            Comparison::LessThanEquals { lhs, rhs } => (lhs, rhs, "<="),
-            Comparison::EqualsEquals { lhs, rhs } => (lhs, rhs, "=="),
-            Comparison::NotEquals { lhs, rhs } => (lhs, rhs, "!="),
-        };
-        emit.push_str(
-            format!(
-                "{} {} {}",
-                &Self::emit_expression(lhs),
-                sign,
-                &Self::emit_expression(rhs)
-            )
-            .as_str(),
-        );
-        emit
-    }
-    fn emit_expression(expression: &Expression) -> String {
-        let mut emit = String::new();
-        let rhs = match expression.rhs.as_ref() {
-            Some(ExpressionOp::Plus(e)) => format!(" + {}", &Self::emit_expression(e)),
-            Some(ExpressionOp::Minus(e)) => format!(" - {}", &Self::emit_expression(e)),
-            None => "".to_string(),
+        let str = match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                let mut parts = Vec::new();
+                let mut left = Self::emit_expression(lhs);
+                for (i, (op, operand)) in rhs.iter().enumerate() {
+                    let is_middle = i + 1 < rhs.len();
+                    let (right, next_left) = if is_middle && !is_trivial(operand) {
+                        let temporary = temporary_name(operand);
+                        (
+                            format!("({} = {})", temporary, &Self::emit_expression(operand)),
+                            temporary,
+                        )
+                    } else {
+                        let operand = Self::emit_expression(operand);
+                        (operand.clone(), operand)
+                    };
+                    parts.push(format!("{} {} {}", left, op.symbol(), right));
+                    left = next_left;
+                }
+                parts.join(" && ")
+            }
+            Comparison::Value(e) => Self::emit_expression(e),
+            Comparison::Not(c) => format!("!({})", &Self::emit_comparison(c)),
+            Comparison::And(lhs, rhs) => format!(
+                "{} && {}",
+                &Self::emit_logical_operand(lhs),
+                &Self::emit_logical_operand(rhs)
+            ),
+            Comparison::Or(lhs, rhs) => format!(
+                "{} || {}",
+                &Self::emit_logical_operand(lhs),
+                &Self::emit_logical_operand(rhs)
+            ),
         };
-        emit.push_str(format!("{}{}", &Self::emit_term(&expression.lhs), rhs).as_str());
+        emit.push_str(&str);
         emit
     }
-
-    fn emit_term(term: &Term) -> String {
-        let mut emit = String::new();
-        let rhs = match term.rhs.as_ref() {
-            Some(TermOp::Multiply(t)) => format!(" * {}", &Self::emit_term(t)),
-            Some(TermOp::Divide(t)) => format!(" / {}", &Self::emit_term(t)),
-            Some(TermOp::Modulo(t)) => format!(" % {}", &Self::emit_term(t)),
-            None => "".to_string(),
-        };
-        emit.push_str(format!("{}{}", &Self::emit_unary(&term.lhs), rhs).as_str());
-        emit
+    fn emit_logical_operand(comparison: &Comparison) -> String {
+        match comparison {
+            Comparison::Relational { rhs, .. } if rhs.len() > 1 => {
+                format!("({})", &Self::emit_comparison(comparison))
+            }
+            Comparison::And(..) | Comparison::Or(..) => {
+                format!("({})", &Self::emit_comparison(comparison))
+            }
+            _ => Self::emit_comparison(comparison),
+        }
     }
-    fn emit_unary(unary: &Unary) -> String {
+    fn emit_expression(expression: &Expression) -> String {
         let mut emit = String::new();
-        let str = match unary {
-            Unary::Positive(p) => format!("+{}", &Self::emit_primary(p)),
-            Unary::Negative(p) => format!("-{}", &Self::emit_primary(p)),
-            Unary::UnSigned(p) => Self::emit_primary(p),
+        let str = match expression {
+            Expression::Binary { op, lhs, rhs, .. } => format!(
+                "{} {} {}",
+                &Self::emit_operand(lhs, op.precedence(), false),
+                op.symbol(),
+                &Self::emit_operand(rhs, op.precedence(), true)
+            ),
+            Expression::Unary { op, operand, .. } => {
+                format!("{}{}", op.symbol(), &Self::emit_primary(operand))
+            }
+            Expression::Primary(p) => Self::emit_primary(p),
         };
         emit.push_str(&str);
         emit
     }
+    /// C has the same precedence and associativity as we do, so operands only need parentheses
+    /// where the tree goes against them.
+    fn emit_operand(expression: &Expression, precedence: u8, is_rhs: bool) -> String {
+        match expression {
+            Expression::Binary { op, .. }
+                if op.precedence() < precedence || (is_rhs && op.precedence() == precedence) =>
+            {
+                format!("({})", &Self::emit_expression(expression))
+            }
+            _ => Self::emit_expression(expression),
+        }
+    }
     fn emit_primary(primary: &Primary) -> String {
         let mut emit = String::new();
         let str = match primary {
-            Primary::LiteralNumber(n) => n.to_string(),
-            Primary::IdentifierExpression(id) => id.id.clone(),
+            Primary::LiteralNumber(n, _) => format!("{}LL", n),
+            Primary::IdentifierExpression(id) => id.name().to_string(),
+            // Keep the parentheses so C groups exactly like we parsed.
+            Primary::Grouped(e, _) => format!("({})", &Self::emit_expression(e)),
         };
         emit.push_str(&str);
         emit
@@ -225,11 +372,15 @@ impl CEmitter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexing::lexer::Span;
+    use crate::parsing::parser::BinaryOp;
+    use crate::test_utils::analyze;
 
     #[test]
     fn test_emit_print() {
         let print_statement = Statement::Print {
             option: PrintOption::PrintLiteral("Hello, World!".to_string()),
+            span: Span::default(),
         };
 
         let emitter = CEmitter::new(&[print_statement]);
@@ -244,4 +395,261 @@ mod tests {
 
         assert_eq!(emitted_code, expected_code);
     }
+
+    fn emit_source(source: &str) -> String {
+        let statements = analyze(source);
+        CEmitter::new(&statements).emit()
+    }
+
+    #[test]
+    fn test_emit_if_else() {
+        let source = "\
+let temp = 4;
+if temp % 2 == 0 {
+    print \"Temp is even\";
+} else {
+    print \"Temp is odd\";
+}";
+        let expected_code = "\
+        #include <stdio.h>\n\
+        int main(void){\n\
+        \tlong long temp = 4LL;\n\
+        \tif (temp % 2LL == 0LL) {\n\
+        \t\tprintf(\"Temp is even\");\n\
+        \t} else {\n\
+        \t\tprintf(\"Temp is odd\");\n\
+        \t}\n\
+        \treturn 0;\n\
+        }\n";
+
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_else_if_chain() {
+        let source = "\
+let y = 10;
+if y % 4 == 0 {
+    print \"Y is now a multiple of 4\";
+} else if y == 10 {
+    print \"Y hit 10\";
+} else {
+    print \"X is not greater than 5\";
+}";
+        let expected_code = "\
+        #include <stdio.h>\n\
+        int main(void){\n\
+        \tlong long y = 10LL;\n\
+        \tif (y % 4LL == 0LL) {\n\
+        \t\tprintf(\"Y is now a multiple of 4\");\n\
+        \t} else if (y == 10LL) {\n\
+        \t\tprintf(\"Y hit 10\");\n\
+        \t} else {\n\
+        \t\tprintf(\"X is not greater than 5\");\n\
+        \t}\n\
+        \treturn 0;\n\
+        }\n";
+
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_break_and_continue() {
+        let source = "\
+let temp = 9;
+while temp > 0 {
+    temp = temp - 1;
+    if temp == 5 {
+        break;
+    }
+    continue;
+}";
+        let expected_code = "\
+        #include <stdio.h>\n\
+        int main(void){\n\
+        \tlong long temp = 9LL;\n\
+        \twhile (temp > 0LL) {\n\
+        \t\ttemp = temp - 1LL;\n\
+        \t\tif (temp == 5LL) {\n\
+        \t\t\tbreak;\n\
+        \t\t}\n\
+        \t\tcontinue;\n\
+        \t}\n\
+        \treturn 0;\n\
+        }\n";
+
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_for() {
+        let source = "\
+let z = 0;
+for let i = 0; i < 10; i = i + 1 {
+    z = z + i;
+    if z > 50 {
+        break;
+    }
+}";
+        let expected_code = "\
+        #include <stdio.h>\n\
+        int main(void){\n\
+        \tlong long z = 0LL;\n\
+        \tfor (long long i = 0LL; i < 10LL; i = i + 1LL) {\n\
+        \t\tz = z + i;\n\
+        \t\tif (z > 50LL) {\n\
+        \t\t\tbreak;\n\
+        \t\t}\n\
+        \t}\n\
+        \treturn 0;\n\
+        }\n";
+
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_shadowed_let() {
+        let source = "\
+let x = 1;
+while x < 3 {
+    let x = x + 1;
+    print x;
+}
+print x;";
+        let expected_code = "\
+        #include <stdio.h>\n\
+        int main(void){\n\
+        \tlong long x = 1LL;\n\
+        \twhile (x < 3LL) {\n\
+        \t\tlong long x_1 = x + 1LL;\n\
+        \t\tprintf(\"%lld\\n\", x_1);\n\
+        \t}\n\
+        \tprintf(\"%lld\\n\", x);\n\
+        \treturn 0;\n\
+        }\n";
+
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_grouped_expression() {
+        let source = "\
+let a = 1;
+let b = 2;
+print (a + b) * -(b - (a));";
+        let expected_code = "\
+        #include <stdio.h>\n\
+        int main(void){\n\
+        \tlong long a = 1LL;\n\
+        \tlong long b = 2LL;\n\
+        \tprintf(\"%lld\\n\", (a + b) * -(b - (a)));\n\
+        \treturn 0;\n\
+        }\n";
+
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_logical_operators() {
+        let source = "\
+let x = 1;
+let y = 2;
+let done = 0;
+if x > 0 && y < 10 || !done {
+    print x;
+}
+while !(x == 1 || y == 1) && done {
+    done = 0;
+}";
+        let expected_code = "\
+        #include <stdio.h>\n\
+        int main(void){\n\
+        \tlong long x = 1LL;\n\
+        \tlong long y = 2LL;\n\
+        \tlong long done = 0LL;\n\
+        \tif ((x > 0LL && y < 10LL) || !(done)) {\n\
+        \t\tprintf(\"%lld\\n\", x);\n\
+        \t}\n\
+        \twhile (!(x == 1LL || y == 1LL) && done) {\n\
+        \t\tdone = 0LL;\n\
+        \t}\n\
+        \treturn 0;\n\
+        }\n";
+
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_chained_comparisons() {
+        let source = "\
+let i = 1;
+let n = 3;
+while 0 <= i < n {
+    i = i + 1;
+}
+if 0 < i * 2 <= n + 1 < 10 {
+    print i;
+} else if i < n - i < n {
+    print n;
+}";
+        let expected_code = "\
+        #include <stdio.h>\n\
+        int main(void){\n\
+        \tlong long i = 1LL;\n\
+        \tlong long n = 3LL;\n\
+        \twhile (0LL <= i && i < n) {\n\
+        \t\ti = i + 1LL;\n\
+        \t}\n\
+        \tlong long mmc_tmp_65;\n\
+        \tlong long mmc_tmp_74;\n\
+        \tlong long mmc_tmp_114;\n\
+        \tif (0LL < (mmc_tmp_65 = i * 2LL) && mmc_tmp_65 <= (mmc_tmp_74 = n + 1LL) && mmc_tmp_74 < 10LL) {\n\
+        \t\tprintf(\"%lld\\n\", i);\n\
+        \t} else if (i < (mmc_tmp_114 = n - i) && mmc_tmp_114 < n) {\n\
+        \t\tprintf(\"%lld\\n\", n);\n\
+        \t}\n\
+        \treturn 0;\n\
+        }\n";
+
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_operand_parentheses() {
+        let number = |n| Expression::Primary(Primary::LiteralNumber(n, Span::default()));
+        let binary = |op, lhs, rhs| Expression::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            span: Span::default(),
+        };
+        // 1 - (2 - 3) * 4, built by hand since the parser would use Grouped for the parentheses.
+        let expression = binary(
+            BinaryOp::Subtract,
+            number(1),
+            binary(
+                BinaryOp::Multiply,
+                binary(BinaryOp::Subtract, number(2), number(3)),
+                number(4),
+            ),
+        );
+        assert_eq!(
+            CEmitter::emit_expression(&expression),
+            "1LL - (2LL - 3LL) * 4LL"
+        );
+
+        let expression = binary(
+            BinaryOp::Divide,
+            number(8),
+            binary(BinaryOp::Divide, number(4), number(2)),
+        );
+        assert_eq!(CEmitter::emit_expression(&expression), "8LL / (4LL / 2LL)");
+
+        let expression = binary(
+            BinaryOp::Divide,
+            binary(BinaryOp::Divide, number(8), number(4)),
+            number(2),
+        );
+        assert_eq!(CEmitter::emit_expression(&expression), "8LL / 4LL / 2LL");
+    }
 }
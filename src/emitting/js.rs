@@ -0,0 +1,524 @@
+use super::common::{is_trivial, temporaries, temporary_name};
+use super::emitter::Indent;
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    Comparison, Expression, Identifier, Primary, PrintOption, Statement, UnaryOp,
+};
+
+/// Emits JavaScript defining `main(runtime)`. The runtime is any object with
+/// `print(text)` and `input()`, where `input` returns more text to read numbers from or `null`
+/// at the end, so a browser can pass `{ print: ..., input: () => prompt() }`. Run with Node the
+/// script reads stdin and writes stdout by itself.
+///
+/// Numbers are `BigInt`s wrapped to 64 bits after every operation, which truncates division
+/// and gives `%` the sign of the dividend just like C.
+pub struct JsEmitter {
+    statements: Vec<Statement>,
+    code: String,
+    indentor: Indent,
+}
+
+const PRELUDE: &str = r#""use strict";
+
+function mmc_wrap(value) {
+	return BigInt.asIntN(64, value);
+}
+
+// Reads numbers the way scanf("%lld") does: anything else reads as 0 and is skipped.
+function mmc_reader(runtime) {
+	let text = "";
+	const fill = () => {
+		for (;;) {
+			text = text.replace(/^\s+/, "");
+			if (text !== "") {
+				return true;
+			}
+			const more = runtime.input();
+			if (more === null || more === undefined) {
+				return false;
+			}
+			text = String(more);
+		}
+	};
+	return () => {
+		if (!fill()) {
+			return 0n;
+		}
+		const match = /^[+-]?(\d*)/.exec(text);
+		text = text.slice(match[0].length);
+		if (match[1] === "") {
+			if (fill()) {
+				text = text.replace(/^\S+/, "");
+			}
+			return 0n;
+		}
+		const value = BigInt(match[0]);
+		const max = 9223372036854775807n;
+		return value > max ? max : value < -max - 1n ? -max - 1n : value;
+	};
+}
+
+"#;
+
+const EPILOGUE: &str = r#"
+if (typeof module === "object") {
+	module.exports = { main };
+	if (require.main === module) {
+		let mmc_stdin = null;
+		main({
+			print: (text) => process.stdout.write(text),
+			input: () => {
+				if (mmc_stdin !== null) {
+					return null;
+				}
+				mmc_stdin = require("fs").readFileSync(0, "utf8");
+				return mmc_stdin;
+			},
+		});
+	}
+}
+"#;
+
+/// Words that can not name a variable in strict mode JavaScript.
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+impl JsEmitter {
+    pub fn new(program: &[Statement]) -> Self {
+        JsEmitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            indentor: Indent::new(),
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        self.code.push_str(PRELUDE);
+        self.code.push_str("function main(mmc_runtime) {\n");
+        self.code
+            .push_str("\tconst mmc_read = mmc_reader(mmc_runtime);\n");
+        self.indentor.increase();
+        for statement in &self.statements {
+            self.code
+                .push_str(&Self::emit_statement(statement, &mut self.indentor));
+        }
+        self.code.push_str("}\n");
+        self.code.push_str(EPILOGUE);
+        self.code
+    }
+
+    fn emit_statement(statement: &Statement, indent: &mut Indent) -> String {
+        let mut emit = String::new();
+        emit.push_str(&indent.current_indent());
+        for temporary in temporaries(statement) {
+            emit.push_str(&format!("let {};\n{}", temporary, &indent.current_indent()));
+        }
+        match statement {
+            Statement::Print { option, .. } => emit.push_str(&Self::emit_print(option)),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => emit.push_str(&Self::emit_if(
+                comparison,
+                statements,
+                else_statements.as_deref(),
+                indent,
+            )),
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                emit.push_str(&format!("while ({}) ", &Self::emit_comparison(comparison)));
+                emit.push_str(&Self::emit_block(statements, indent));
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                emit.push_str(&format!(
+                    "for ({} {}; {}) ",
+                    &Self::emit_simple(init),
+                    &Self::emit_comparison(comparison),
+                    Self::emit_simple(step).trim_end_matches(';')
+                ));
+                emit.push_str(&Self::emit_block(statements, indent));
+            }
+            Statement::Break { .. } => emit.push_str("break;"),
+            Statement::Continue { .. } => emit.push_str("continue;"),
+            Statement::Let { .. } | Statement::Assign { .. } => {
+                emit.push_str(&Self::emit_simple(statement))
+            }
+            Statement::Input { identifier, .. } => emit.push_str(&format!(
+                "let {} = mmc_read();",
+                Self::emit_identifier(identifier)
+            )),
+        };
+        emit.push('\n');
+        emit
+    }
+
+    /// A `let` or an assignment, the only statements allowed in the head of a `for`.
+    fn emit_simple(statement: &Statement) -> String {
+        match statement {
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            } => format!(
+                "let {} = {};",
+                Self::emit_identifier(identifier),
+                &Self::emit_expression(expression)
+            ),
+            Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => format!(
+                "{} = {};",
+                Self::emit_identifier(identifier),
+                &Self::emit_expression(expression)
+            ),
+            _ => panic!("Expected a let or an assignment"),
+        }
+    }
+
+    fn emit_block(statements: &[Statement], indent: &mut Indent) -> String {
+        let mut emit = String::from("{\n");
+        indent.increase();
+        for statement in statements {
+            emit.push_str(&Self::emit_statement(statement, indent));
+        }
+        indent.decrease();
+        emit.push_str(&indent.current_indent());
+        emit.push('}');
+        emit
+    }
+
+    fn emit_if(
+        comparison: &Comparison,
+        statements: &[Statement],
+        else_statements: Option<&[Statement]>,
+        indent: &mut Indent,
+    ) -> String {
+        let mut emit = format!("if ({}) ", &Self::emit_comparison(comparison));
+        emit.push_str(&Self::emit_block(statements, indent));
+        match else_statements {
+            Some(
+                [Statement::If {
+                    comparison,
+                    statements,
+                    else_statements,
+                    ..
+                }],
+            ) => {
+                emit.push_str(" else ");
+                emit.push_str(&Self::emit_if(
+                    comparison,
+                    statements,
+                    else_statements.as_deref(),
+                    indent,
+                ));
+            }
+            Some(else_statements) => {
+                emit.push_str(" else ");
+                emit.push_str(&Self::emit_block(else_statements, indent));
+            }
+            None => {}
+        }
+        emit
+    }
+
+    fn emit_print(print_option: &PrintOption) -> String {
+        match print_option {
+            PrintOption::PrintLiteral(s) => {
+                format!("mmc_runtime.print({});", Self::emit_string(&unescape(s)))
+            }
+            PrintOption::PrintExpression(e) => format!(
+                "mmc_runtime.print({} + \"\\n\");",
+                &Self::emit_expression(e)
+            ),
+        }
+    }
+
+    fn emit_comparison(comparison: &Comparison) -> String {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                let mut parts = Vec::new();
+                let mut left = Self::emit_expression(lhs);
+                for (i, (op, operand)) in rhs.iter().enumerate() {
+                    let is_middle = i + 1 < rhs.len();
+                    let (right, next_left) = if is_middle && !is_trivial(operand) {
+                        let temporary = temporary_name(operand);
+                        (
+                            format!("({} = {})", temporary, &Self::emit_expression(operand)),
+                            temporary,
+                        )
+                    } else {
+                        let operand = Self::emit_expression(operand);
+                        (operand.clone(), operand)
+                    };
+                    let symbol = match op.symbol() {
+                        "==" => "===",
+                        "!=" => "!==",
+                        symbol => symbol,
+                    };
+                    parts.push(format!("{} {} {}", left, symbol, right));
+                    left = next_left;
+                }
+                parts.join(" && ")
+            }
+            Comparison::Value(e) => format!("{} !== 0n", &Self::emit_expression(e)),
+            Comparison::Not(c) => format!("!({})", &Self::emit_comparison(c)),
+            Comparison::And(lhs, rhs) => format!(
+                "{} && {}",
+                &Self::emit_logical_operand(lhs),
+                &Self::emit_logical_operand(rhs)
+            ),
+            Comparison::Or(lhs, rhs) => format!(
+                "{} || {}",
+                &Self::emit_logical_operand(lhs),
+                &Self::emit_logical_operand(rhs)
+            ),
+        }
+    }
+
+    fn emit_logical_operand(comparison: &Comparison) -> String {
+        match comparison {
+            Comparison::Relational { rhs, .. } if rhs.len() > 1 => {
+                format!("({})", &Self::emit_comparison(comparison))
+            }
+            Comparison::And(..) | Comparison::Or(..) => {
+                format!("({})", &Self::emit_comparison(comparison))
+            }
+            _ => Self::emit_comparison(comparison),
+        }
+    }
+
+    /// Every operation goes through `mmc_wrap`, so the result is always a call and operands
+    /// never need parentheses.
+    fn emit_expression(expression: &Expression) -> String {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => format!(
+                "mmc_wrap({} {} {})",
+                &Self::emit_expression(lhs),
+                op.symbol(),
+                &Self::emit_expression(rhs)
+            ),
+            Expression::Unary {
+                op: UnaryOp::Minus,
+                operand: Primary::LiteralNumber(n, _),
+                ..
+            } => format!("-{}n", n),
+            Expression::Unary { op, operand, .. } => match op {
+                UnaryOp::Plus => Self::emit_primary(operand),
+                UnaryOp::Minus => {
+                    let operand = Self::emit_primary(operand);
+                    // `--` would be a decrement, which `-(-5)` turns into without parentheses.
+                    if operand.starts_with('-') {
+                        format!("mmc_wrap(-({}))", operand)
+                    } else {
+                        format!("mmc_wrap(-{})", operand)
+                    }
+                }
+            },
+            Expression::Primary(p) => Self::emit_primary(p),
+        }
+    }
+
+    fn emit_primary(primary: &Primary) -> String {
+        match primary {
+            Primary::LiteralNumber(n, _) => format!("{}n", n),
+            Primary::IdentifierExpression(id) => Self::emit_identifier(id),
+            Primary::Grouped(e, _) => Self::emit_expression(e),
+        }
+    }
+
+    /// Source identifiers never contain `$`, so a reserved word can take one as a suffix.
+    fn emit_identifier(identifier: &Identifier) -> String {
+        let name = identifier.name();
+        if RESERVED.contains(&name) {
+            format!("{}$", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Quotes an already unescaped string as a JavaScript string literal.
+    fn emit_string(string: &str) -> String {
+        let mut emit = String::from("\"");
+        for c in string.chars() {
+            match c {
+                '"' => emit.push_str("\\\""),
+                '\\' => emit.push_str("\\\\"),
+                '\n' => emit.push_str("\\n"),
+                '\t' => emit.push_str("\\t"),
+                '\r' => emit.push_str("\\r"),
+                c if c.is_control() => emit.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                c => emit.push(c),
+            }
+        }
+        emit.push('"');
+        emit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, interpret, run_file, EDGE_CASES};
+
+    /// Only `main`, without the prelude and the epilogue.
+    fn emit_main(source: &str) -> String {
+        let statements = analyze(source);
+        let code = JsEmitter::new(&statements).emit();
+        assert!(code.starts_with(PRELUDE) && code.ends_with(EPILOGUE));
+        code[PRELUDE.len()..code.len() - EPILOGUE.len()].to_string()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+for let i = 0; i < n; i = i + 1 {
+    if i == 2 || !n {
+        continue;
+    } else if 0 < i * 2 <= n {
+        break;
+    }
+    print -i * 3 % (2 - n);
+}
+print \"100%\\n\";";
+        let expected_code = "\
+function main(mmc_runtime) {
+\tconst mmc_read = mmc_reader(mmc_runtime);
+\tlet n = mmc_read();
+\tfor (let i = 0n; i < n; i = mmc_wrap(i + 1n)) {
+\t\tlet mmc_tmp_101;
+\t\tif (i === 2n || !(n !== 0n)) {
+\t\t\tcontinue;
+\t\t} else if (0n < (mmc_tmp_101 = mmc_wrap(i * 2n)) && mmc_tmp_101 <= n) {
+\t\t\tbreak;
+\t\t}
+\t\tmmc_runtime.print(mmc_wrap(mmc_wrap(mmc_wrap(-i) * 3n) % mmc_wrap(2n - n)) + \"\\n\");
+\t}
+\tmmc_runtime.print(\"100%\\n\");
+}
+";
+        assert_eq!(emit_main(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_reserved_names_and_strings() {
+        let source = "\
+let new = -5;
+while new < 0 {
+    let new = new + 1;
+    print \"\\ttab\\\\\";
+}";
+        let expected_code = "\
+function main(mmc_runtime) {
+\tconst mmc_read = mmc_reader(mmc_runtime);
+\tlet new$ = -5n;
+\twhile (new$ < 0n) {
+\t\tlet new_1 = mmc_wrap(new$ + 1n);
+\t\tmmc_runtime.print(\"\\ttab\\\\\");
+\t}
+}
+";
+        assert_eq!(emit_main(source), expected_code);
+    }
+
+    /// Runs the whole script in node and checks it prints what the interpreter does. Skipped when
+    /// node is not installed.
+    fn assert_runs_like_interpreter(name: &str, source: &str, input: &str) {
+        let code = JsEmitter::new(&analyze(source)).emit();
+        let file_name = format!("js_{}.js", name);
+        if let Some(output) = run_file("node", &[], &file_name, &code, input) {
+            assert_eq!(output, interpret(source, input), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_emit_double_negation() {
+        let source = "\
+input x;
+print -(-5);
+print -(-x);
+print -(-(-x));";
+        assert_eq!(
+            emit_main(source),
+            "\
+function main(mmc_runtime) {
+\tconst mmc_read = mmc_reader(mmc_runtime);
+\tlet x = mmc_read();
+\tmmc_runtime.print(mmc_wrap(-(-5n)) + \"\\n\");
+\tmmc_runtime.print(mmc_wrap(-mmc_wrap(-x)) + \"\\n\");
+\tmmc_runtime.print(mmc_wrap(-mmc_wrap(-mmc_wrap(-x))) + \"\\n\");
+}
+"
+        );
+        assert_runs_like_interpreter("double_negation", source, "7");
+        assert_runs_like_interpreter("double_negation_min", source, "-9223372036854775808");
+    }
+
+    #[test]
+    fn test_edge_cases_run_like_interpreter() {
+        for (index, (source, input)) in EDGE_CASES.iter().enumerate() {
+            assert_runs_like_interpreter(&format!("edge_case_{}", index), source, input);
+        }
+    }
+}
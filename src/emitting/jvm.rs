@@ -0,0 +1,998 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::common::{JumpLowering, LoopStack};
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement, UnaryOp,
+};
+
+/// Emits a Java 8 class file with a `main` method, to be run with `java -cp <dir> <class>`.
+/// Every variable is a `long` local. `print` writes UTF-8 whatever the platform's charset is,
+/// and `input` reads standard input a byte at a time, parsing the longest prefix that is a
+/// number like `scanf("%lld")` does and saturating on overflow. Anything else reads as 0 and the
+/// next word is skipped.
+///
+/// All locals are initialized before the first statement and every jump target has an empty
+/// stack, so the stack map frames of the verifier all describe the same state.
+pub struct JvmEmitter {
+    statements: Vec<Statement>,
+    class_name: String,
+    constants: ConstantPool,
+    code: Code,
+    /// Slots of the `long` locals, which take two slots each.
+    slots: HashMap<String, u16>,
+    loops: LoopStack<usize>,
+}
+
+const MAJOR_VERSION: u16 = 52;
+const ACC_PUBLIC: u16 = 0x0001;
+const ACC_PRIVATE: u16 = 0x0002;
+const ACC_STATIC: u16 = 0x0008;
+const ACC_FINAL: u16 = 0x0010;
+const ACC_SUPER: u16 = 0x0020;
+
+const ICONST_M1: u8 = 0x02;
+const ICONST_0: u8 = 0x03;
+const ICONST_1: u8 = 0x04;
+const LCONST_0: u8 = 0x09;
+const LCONST_1: u8 = 0x0a;
+const BIPUSH: u8 = 0x10;
+const LDC: u8 = 0x12;
+const LDC_W: u8 = 0x13;
+const LDC2_W: u8 = 0x14;
+const ILOAD: u8 = 0x15;
+const LLOAD: u8 = 0x16;
+const ALOAD: u8 = 0x19;
+const ISTORE: u8 = 0x36;
+const LSTORE: u8 = 0x37;
+const ASTORE: u8 = 0x3a;
+const DUP: u8 = 0x59;
+const DUP2: u8 = 0x5c;
+const LADD: u8 = 0x61;
+const ISUB: u8 = 0x64;
+const LSUB: u8 = 0x65;
+const LMUL: u8 = 0x69;
+const LDIV: u8 = 0x6d;
+const LREM: u8 = 0x71;
+const LNEG: u8 = 0x75;
+const I2L: u8 = 0x85;
+const LCMP: u8 = 0x94;
+const IFEQ: u8 = 0x99;
+const IFNE: u8 = 0x9a;
+const IFLT: u8 = 0x9b;
+const IFGE: u8 = 0x9c;
+const IFGT: u8 = 0x9d;
+const IFLE: u8 = 0x9e;
+const IF_ICMPEQ: u8 = 0x9f;
+const IF_ICMPNE: u8 = 0xa0;
+const IF_ICMPLT: u8 = 0xa1;
+const IF_ICMPGT: u8 = 0xa3;
+const IF_ICMPLE: u8 = 0xa4;
+const GOTO: u8 = 0xa7;
+const LRETURN: u8 = 0xad;
+const RETURN: u8 = 0xb1;
+const GETSTATIC: u8 = 0xb2;
+const INVOKEVIRTUAL: u8 = 0xb6;
+const INVOKESPECIAL: u8 = 0xb7;
+const INVOKESTATIC: u8 = 0xb8;
+const NEW: u8 = 0xbb;
+const WIDE: u8 = 0xc4;
+
+const INPUT_STREAM: &str = "java/io/PushbackInputStream";
+const PRINT_STREAM: &str = "java/io/PrintStream";
+/// `main` keeps its `String[]` argument in slot 0, standard input in slot 1 and the UTF-8
+/// `PrintStream` in slot 2.
+const INPUT_SLOT: u16 = 1;
+const OUTPUT_SLOT: u16 = 2;
+
+/// Slots of `readInteger`, which accumulates the number negated so the smallest `long` fits.
+const BYTE_SLOT: u16 = 1;
+const VALUE_SLOT: u16 = 2;
+const NEGATIVE_SLOT: u16 = 4;
+const DIGITS_SLOT: u16 = 5;
+
+impl JvmEmitter {
+    pub fn new(program: &[Statement], class_name: &str) -> Self {
+        JvmEmitter {
+            statements: program.to_vec(),
+            class_name: class_name.to_string(),
+            constants: ConstantPool::default(),
+            code: Code::default(),
+            slots: HashMap::new(),
+            loops: LoopStack::default(),
+        }
+    }
+
+    pub fn emit(mut self) -> Vec<u8> {
+        for statement in &self.statements.clone() {
+            self.emit_statement(statement);
+        }
+        self.code.op(RETURN, 0);
+        let body = std::mem::take(&mut self.code);
+
+        let mut prologue = Code::default();
+        let input_stream = self.constants.class(INPUT_STREAM);
+        let system_in = self
+            .constants
+            .field("java/lang/System", "in", "Ljava/io/InputStream;");
+        let init = self
+            .constants
+            .method(INPUT_STREAM, "<init>", "(Ljava/io/InputStream;)V");
+        prologue.op_u16(NEW, input_stream, 1);
+        prologue.op(DUP, 1);
+        prologue.op_u16(GETSTATIC, system_in, 1);
+        prologue.op_u16(INVOKESPECIAL, init, -2);
+        prologue.local(ASTORE, INPUT_SLOT, -1);
+        // `System.out` encodes with the platform's charset, this one passes UTF-8 bytes through.
+        let print_stream = self.constants.class(PRINT_STREAM);
+        let system_out = self
+            .constants
+            .field("java/lang/System", "out", "Ljava/io/PrintStream;");
+        let init = self.constants.method(
+            PRINT_STREAM,
+            "<init>",
+            "(Ljava/io/OutputStream;ZLjava/lang/String;)V",
+        );
+        let charset = self.constants.string("UTF-8");
+        prologue.op_u16(NEW, print_stream, 1);
+        prologue.op(DUP, 1);
+        prologue.op_u16(GETSTATIC, system_out, 1);
+        prologue.op(ICONST_1, 1);
+        prologue.ldc(charset);
+        prologue.op_u16(INVOKESPECIAL, init, -4);
+        prologue.local(ASTORE, OUTPUT_SLOT, -1);
+        let mut slots: Vec<u16> = self.slots.values().copied().collect();
+        slots.sort();
+        for &slot in &slots {
+            prologue.op(LCONST_0, 2);
+            prologue.local(LSTORE, slot, -2);
+        }
+        let code = prologue.append(body);
+
+        let arguments = self.constants.class("[Ljava/lang/String;");
+        let mut locals = vec![
+            Local::Object(arguments),
+            Local::Object(input_stream),
+            Local::Object(print_stream),
+        ];
+        locals.extend(slots.iter().map(|_| Local::Long));
+        let max_locals = OUTPUT_SLOT + 1 + 2 * slots.len() as u16;
+        let main = self.method(
+            ACC_PUBLIC | ACC_STATIC,
+            "main",
+            "([Ljava/lang/String;)V",
+            code,
+            max_locals,
+            &locals,
+        );
+        let read_integer = self.emit_read_integer();
+
+        let this_class = self.constants.class(&self.class_name);
+        let super_class = self.constants.class("java/lang/Object");
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0xCAFEBABEu32.to_be_bytes());
+        bytes.extend_from_slice(&0u16.to_be_bytes());
+        bytes.extend_from_slice(&MAJOR_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&self.constants.count.to_be_bytes());
+        bytes.extend_from_slice(&self.constants.bytes);
+        bytes.extend_from_slice(&(ACC_PUBLIC | ACC_FINAL | ACC_SUPER).to_be_bytes());
+        bytes.extend_from_slice(&this_class.to_be_bytes());
+        bytes.extend_from_slice(&super_class.to_be_bytes());
+        // No interfaces and no fields.
+        bytes.extend_from_slice(&0u16.to_be_bytes());
+        bytes.extend_from_slice(&0u16.to_be_bytes());
+        bytes.extend_from_slice(&2u16.to_be_bytes());
+        bytes.extend_from_slice(&main);
+        bytes.extend_from_slice(&read_integer);
+        // No attributes.
+        bytes.extend_from_slice(&0u16.to_be_bytes());
+        bytes
+    }
+
+    /// `static long readInteger(PushbackInputStream in)`, the byte that ends the number or the
+    /// skipped word is pushed back.
+    fn emit_read_integer(&mut self) -> Vec<u8> {
+        let read = self.constants.method(INPUT_STREAM, "read", "()I");
+        let unread = self.constants.method(INPUT_STREAM, "unread", "(I)V");
+        let ten = self.constants.long(10);
+        let minimum = self.constants.long(i64::MIN);
+        let maximum = self.constants.long(i64::MAX);
+        // Below this, multiplying by 10 overflows.
+        let limit = self.constants.long(i64::MIN / 10);
+
+        let mut code = Code::default();
+        code.op(ICONST_0, 1);
+        code.local(ISTORE, BYTE_SLOT, -1);
+        code.op(LCONST_0, 2);
+        code.local(LSTORE, VALUE_SLOT, -2);
+        code.op(ICONST_0, 1);
+        code.local(ISTORE, NEGATIVE_SLOT, -1);
+        code.op(ICONST_0, 1);
+        code.local(ISTORE, DIGITS_SLOT, -1);
+        let read_byte = |code: &mut Code| {
+            code.local(ALOAD, 0, 1);
+            code.op_u16(INVOKEVIRTUAL, read, 0);
+            code.local(ISTORE, BYTE_SLOT, -1);
+        };
+
+        let skip_space = code.new_label();
+        code.place(skip_space);
+        read_byte(&mut code);
+        code.space_jump(BYTE_SLOT, true, skip_space);
+
+        let plus = code.new_label();
+        let digits = code.new_label();
+        let next_byte = code.new_label();
+        code.local(ILOAD, BYTE_SLOT, 1);
+        code.op_u8(BIPUSH, b'-', 1);
+        code.jump(IF_ICMPNE, plus, -2);
+        code.op(ICONST_1, 1);
+        code.local(ISTORE, NEGATIVE_SLOT, -1);
+        code.jump(GOTO, next_byte, 0);
+        code.place(plus);
+        code.local(ILOAD, BYTE_SLOT, 1);
+        code.op_u8(BIPUSH, b'+', 1);
+        code.jump(IF_ICMPNE, digits, -2);
+        code.place(next_byte);
+        read_byte(&mut code);
+
+        let end = code.new_label();
+        let overflow = code.new_label();
+        let next_digit = code.new_label();
+        let load_digit = |code: &mut Code| {
+            code.local(ILOAD, BYTE_SLOT, 1);
+            code.op_u8(BIPUSH, b'0', 1);
+            code.op(ISUB, -1);
+            code.op(I2L, 1);
+        };
+        code.place(digits);
+        code.local(ILOAD, BYTE_SLOT, 1);
+        code.op_u8(BIPUSH, b'0', 1);
+        code.jump(IF_ICMPLT, end, -2);
+        code.local(ILOAD, BYTE_SLOT, 1);
+        code.op_u8(BIPUSH, b'9', 1);
+        code.jump(IF_ICMPGT, end, -2);
+        code.op(ICONST_1, 1);
+        code.local(ISTORE, DIGITS_SLOT, -1);
+        code.local(LLOAD, VALUE_SLOT, 2);
+        code.op_u16(LDC2_W, limit, 2);
+        code.op(LCMP, -3);
+        code.jump(IFLT, overflow, -1);
+        code.local(LLOAD, VALUE_SLOT, 2);
+        code.op_u16(LDC2_W, ten, 2);
+        code.op(LMUL, -2);
+        code.local(LSTORE, VALUE_SLOT, -2);
+        code.local(LLOAD, VALUE_SLOT, 2);
+        code.op_u16(LDC2_W, minimum, 2);
+        load_digit(&mut code);
+        code.op(LADD, -2);
+        code.op(LCMP, -3);
+        code.jump(IFLT, overflow, -1);
+        code.local(LLOAD, VALUE_SLOT, 2);
+        load_digit(&mut code);
+        code.op(LSUB, -2);
+        code.local(LSTORE, VALUE_SLOT, -2);
+        code.jump(GOTO, next_digit, 0);
+        code.place(overflow);
+        code.op_u16(LDC2_W, minimum, 2);
+        code.local(LSTORE, VALUE_SLOT, -2);
+        code.place(next_digit);
+        read_byte(&mut code);
+        code.jump(GOTO, digits, 0);
+
+        // Not a number, scanf("%*s") skips the next word.
+        let skip_word = code.new_label();
+        let push_back = code.new_label();
+        code.place(end);
+        code.local(ILOAD, DIGITS_SLOT, 1);
+        code.jump(IFNE, push_back, -1);
+        let skip_space_before_word = code.new_label();
+        code.place(skip_space_before_word);
+        code.space_jump(BYTE_SLOT, false, skip_word);
+        read_byte(&mut code);
+        code.jump(GOTO, skip_space_before_word, 0);
+        code.place(skip_word);
+        code.local(ILOAD, BYTE_SLOT, 1);
+        code.op(ICONST_M1, 1);
+        code.jump(IF_ICMPEQ, push_back, -2);
+        code.space_jump(BYTE_SLOT, true, push_back);
+        read_byte(&mut code);
+        code.jump(GOTO, skip_word, 0);
+
+        let result = code.new_label();
+        code.place(push_back);
+        code.local(ILOAD, BYTE_SLOT, 1);
+        code.op(ICONST_M1, 1);
+        code.jump(IF_ICMPEQ, result, -2);
+        code.local(ALOAD, 0, 1);
+        code.local(ILOAD, BYTE_SLOT, 1);
+        code.op_u16(INVOKEVIRTUAL, unread, -2);
+
+        // Without digits the value is still 0.
+        let negative = code.new_label();
+        let saturated = code.new_label();
+        code.place(result);
+        code.local(ILOAD, NEGATIVE_SLOT, 1);
+        code.jump(IFNE, negative, -1);
+        code.local(LLOAD, VALUE_SLOT, 2);
+        code.op_u16(LDC2_W, minimum, 2);
+        code.op(LCMP, -3);
+        code.jump(IFEQ, saturated, -1);
+        code.local(LLOAD, VALUE_SLOT, 2);
+        code.op(LNEG, 0);
+        code.op(LRETURN, -2);
+        code.place(saturated);
+        code.op_u16(LDC2_W, maximum, 2);
+        code.op(LRETURN, -2);
+        code.place(negative);
+        code.local(LLOAD, VALUE_SLOT, 2);
+        code.op(LRETURN, -2);
+
+        let input_stream = self.constants.class(INPUT_STREAM);
+        self.method(
+            ACC_PRIVATE | ACC_STATIC,
+            "readInteger",
+            "(Ljava/io/PushbackInputStream;)J",
+            code,
+            DIGITS_SLOT + 1,
+            &[
+                Local::Object(input_stream),
+                Local::Int,
+                Local::Long,
+                Local::Int,
+                Local::Int,
+            ],
+        )
+    }
+
+    /// A `method_info` with a `Code` attribute, and a `StackMapTable` in which every frame
+    /// has `locals` and an empty stack.
+    fn method(
+        &mut self,
+        access: u16,
+        name: &str,
+        descriptor: &str,
+        code: Code,
+        max_locals: u16,
+        locals: &[Local],
+    ) -> Vec<u8> {
+        let name = self.constants.utf8(name);
+        let descriptor = self.constants.utf8(descriptor);
+        let code_name = self.constants.utf8("Code");
+        let stack_map_name = self.constants.utf8("StackMapTable");
+        let max_stack = code.max_depth as u16;
+        let frames = code.frames.clone();
+        let code = code.finish();
+
+        let mut stack_map = Vec::new();
+        stack_map.extend_from_slice(&(frames.len() as u16).to_be_bytes());
+        let mut previous: Option<usize> = None;
+        for &offset in &frames {
+            match previous {
+                None => {
+                    stack_map.push(255);
+                    stack_map.extend_from_slice(&(offset as u16).to_be_bytes());
+                    stack_map.extend_from_slice(&(locals.len() as u16).to_be_bytes());
+                    for local in locals {
+                        match local {
+                            Local::Object(class) => {
+                                stack_map.push(7);
+                                stack_map.extend_from_slice(&class.to_be_bytes());
+                            }
+                            Local::Int => stack_map.push(1),
+                            Local::Long => stack_map.push(4),
+                        }
+                    }
+                    stack_map.extend_from_slice(&0u16.to_be_bytes());
+                }
+                Some(previous) => {
+                    let delta = offset - previous - 1;
+                    if delta < 64 {
+                        stack_map.push(delta as u8);
+                    } else {
+                        stack_map.push(251);
+                        stack_map.extend_from_slice(&(delta as u16).to_be_bytes());
+                    }
+                }
+            }
+            previous = Some(offset);
+        }
+
+        let mut attribute = Vec::new();
+        attribute.extend_from_slice(&max_stack.to_be_bytes());
+        attribute.extend_from_slice(&max_locals.to_be_bytes());
+        attribute.extend_from_slice(&(code.len() as u32).to_be_bytes());
+        attribute.extend_from_slice(&code);
+        // No exception handlers.
+        attribute.extend_from_slice(&0u16.to_be_bytes());
+        if frames.is_empty() {
+            attribute.extend_from_slice(&0u16.to_be_bytes());
+        } else {
+            attribute.extend_from_slice(&1u16.to_be_bytes());
+            attribute.extend_from_slice(&stack_map_name.to_be_bytes());
+            attribute.extend_from_slice(&(stack_map.len() as u32).to_be_bytes());
+            attribute.extend_from_slice(&stack_map);
+        }
+
+        let mut method = Vec::new();
+        method.extend_from_slice(&access.to_be_bytes());
+        method.extend_from_slice(&name.to_be_bytes());
+        method.extend_from_slice(&descriptor.to_be_bytes());
+        method.extend_from_slice(&1u16.to_be_bytes());
+        method.extend_from_slice(&code_name.to_be_bytes());
+        method.extend_from_slice(&(attribute.len() as u32).to_be_bytes());
+        method.extend_from_slice(&attribute);
+        method
+    }
+
+    /// Declarations have unique names after semantic analysis, so each name gets its own slot.
+    fn slot(&mut self, name: &str) -> u16 {
+        let next = OUTPUT_SLOT + 1 + 2 * self.slots.len() as u16;
+        *self.slots.entry(name.to_string()).or_insert(next)
+    }
+
+    fn variable(&mut self, identifier: &Identifier) -> u16 {
+        self.slot(identifier.name())
+    }
+
+    fn emit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print { option, .. } => self.emit_print(option),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                let else_label = self.code.new_label();
+                self.emit_jump(comparison, false, &else_label);
+                self.emit_block(statements);
+                match else_statements {
+                    Some(else_statements) => {
+                        let end_label = self.code.new_label();
+                        self.code.jump(GOTO, end_label, 0);
+                        self.code.place(else_label);
+                        self.emit_block(else_statements);
+                        self.code.place(end_label);
+                    }
+                    None => self.code.place(else_label),
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                let start_label = self.code.new_label();
+                let end_label = self.code.new_label();
+                self.code.place(start_label);
+                self.emit_jump(comparison, false, &end_label);
+                self.emit_loop_body(statements, start_label, end_label);
+                self.code.jump(GOTO, start_label, 0);
+                self.code.place(end_label);
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                let start_label = self.code.new_label();
+                let step_label = self.code.new_label();
+                let end_label = self.code.new_label();
+                self.emit_statement(init);
+                self.code.place(start_label);
+                self.emit_jump(comparison, false, &end_label);
+                self.emit_loop_body(statements, step_label, end_label);
+                self.code.place(step_label);
+                self.emit_statement(step);
+                self.code.jump(GOTO, start_label, 0);
+                self.code.place(end_label);
+            }
+            Statement::Break { .. } => {
+                let break_label = self.loops.break_target();
+                self.code.jump(GOTO, break_label, 0);
+            }
+            Statement::Continue { .. } => {
+                let continue_label = self.loops.continue_target();
+                self.code.jump(GOTO, continue_label, 0);
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                self.emit_expression(expression);
+                let slot = self.variable(identifier);
+                self.code.local(LSTORE, slot, -2);
+            }
+            Statement::Input { identifier, .. } => {
+                let read_integer = self.constants.method(
+                    &self.class_name,
+                    "readInteger",
+                    "(Ljava/io/PushbackInputStream;)J",
+                );
+                self.code.local(ALOAD, INPUT_SLOT, 1);
+                self.code.op_u16(INVOKESTATIC, read_integer, 1);
+                let slot = self.variable(identifier);
+                self.code.local(LSTORE, slot, -2);
+            }
+        }
+    }
+
+    fn emit_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.emit_statement(statement);
+        }
+    }
+
+    fn emit_loop_body(
+        &mut self,
+        statements: &[Statement],
+        continue_label: usize,
+        end_label: usize,
+    ) {
+        self.loops.push(continue_label, end_label);
+        self.emit_block(statements);
+        self.loops.pop();
+    }
+
+    fn emit_print(&mut self, print_option: &PrintOption) {
+        self.code.local(ALOAD, OUTPUT_SLOT, 1);
+        match print_option {
+            PrintOption::PrintLiteral(s) => {
+                let string = self.constants.string(&unescape(s));
+                self.code.ldc(string);
+                let print = self
+                    .constants
+                    .method(PRINT_STREAM, "print", "(Ljava/lang/String;)V");
+                self.code.op_u16(INVOKEVIRTUAL, print, -2);
+            }
+            PrintOption::PrintExpression(e) => {
+                self.emit_expression(e);
+                let print = self.constants.method(PRINT_STREAM, "print", "(J)V");
+                self.code.op_u16(INVOKEVIRTUAL, print, -3);
+                // Not println, which ends the line with the platform's line separator.
+                self.code.local(ALOAD, OUTPUT_SLOT, 1);
+                self.code.op_u8(BIPUSH, b'\n', 1);
+                let print = self.constants.method(PRINT_STREAM, "print", "(C)V");
+                self.code.op_u16(INVOKEVIRTUAL, print, -2);
+            }
+        }
+    }
+
+    /// The branch taken after `lcmp` when `lhs op rhs` does not hold.
+    fn inverse_branch(op: &RelationalOp) -> u8 {
+        match op {
+            RelationalOp::GreaterThan => IFLE,
+            RelationalOp::GreaterThanEquals => IFLT,
+            RelationalOp::LessThan => IFGE,
+            RelationalOp::LessThanEquals => IFGT,
+            RelationalOp::EqualsEquals => IFNE,
+            RelationalOp::NotEquals => IFEQ,
+        }
+    }
+
+    fn emit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                self.emit_expression(lhs);
+                self.emit_expression(rhs);
+                let opcode = match op {
+                    BinaryOp::Add => LADD,
+                    BinaryOp::Subtract => LSUB,
+                    BinaryOp::Multiply => LMUL,
+                    BinaryOp::Divide => LDIV,
+                    BinaryOp::Modulo => LREM,
+                };
+                self.code.op(opcode, -2);
+            }
+            Expression::Unary { op, operand, .. } => {
+                self.emit_primary(operand);
+                if *op == UnaryOp::Minus {
+                    self.code.op(LNEG, 0);
+                }
+            }
+            Expression::Primary(p) => self.emit_primary(p),
+        }
+    }
+
+    fn emit_primary(&mut self, primary: &Primary) {
+        match primary {
+            Primary::LiteralNumber(0, _) => self.code.op(LCONST_0, 2),
+            Primary::LiteralNumber(1, _) => self.code.op(LCONST_1, 2),
+            Primary::LiteralNumber(n, _) => {
+                let constant = self.constants.long(*n);
+                self.code.op_u16(LDC2_W, constant, 2);
+            }
+            Primary::IdentifierExpression(id) => {
+                let slot = self.variable(id);
+                self.code.local(LLOAD, slot, 2);
+            }
+            Primary::Grouped(e, _) => self.emit_expression(e),
+        }
+    }
+}
+
+impl JumpLowering for JvmEmitter {
+    type Label = usize;
+
+    fn new_label(&mut self) -> usize {
+        self.code.new_label()
+    }
+
+    fn place_label(&mut self, label: &usize) {
+        self.code.place(*label);
+    }
+
+    fn emit_goto(&mut self, target: &usize) {
+        self.code.jump(GOTO, *target, 0);
+    }
+
+    fn emit_chain_failure(
+        &mut self,
+        lhs: &Expression,
+        rhs: &[(RelationalOp, Expression)],
+        target: &usize,
+    ) {
+        self.emit_expression(lhs);
+        for (i, (op, operand)) in rhs.iter().enumerate() {
+            self.emit_expression(operand);
+            let temporary = if i + 1 < rhs.len() {
+                let temporary = self.slot(&format!("mmc_tmp_{}", operand.span().start));
+                self.code.op(DUP2, 2);
+                self.code.local(LSTORE, temporary, -2);
+                Some(temporary)
+            } else {
+                None
+            };
+            self.code.op(LCMP, -3);
+            self.code.jump(Self::inverse_branch(op), *target, -1);
+            if let Some(temporary) = temporary {
+                self.code.local(LLOAD, temporary, 2);
+            }
+        }
+    }
+
+    fn emit_value_jump(&mut self, expression: &Expression, when: bool, target: &usize) {
+        self.emit_expression(expression);
+        self.code.op(LCONST_0, 2);
+        self.code.op(LCMP, -3);
+        let branch = if when { IFNE } else { IFEQ };
+        self.code.jump(branch, *target, -1);
+    }
+}
+
+/// Verification type of a local in a stack map frame.
+enum Local {
+    Object(u16),
+    Int,
+    Long,
+}
+
+/// Bytecode of one method, jumps are patched once all labels are placed.
+#[derive(Default)]
+struct Code {
+    bytes: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    /// Offsets of jump instructions and the label they jump to.
+    jumps: Vec<(usize, usize)>,
+    /// Offsets that need a stack map frame: labels and whatever follows a `goto`.
+    frames: BTreeSet<usize>,
+    /// Stack depth in slots, a `long` takes two.
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Code {
+    /// `effect` is how many slots the instruction leaves on the stack minus how many it takes.
+    fn op(&mut self, opcode: u8, effect: isize) {
+        self.depth = self.depth.saturating_add_signed(effect);
+        self.max_depth = self.max_depth.max(self.depth);
+        self.bytes.push(opcode);
+    }
+
+    fn op_u8(&mut self, opcode: u8, operand: u8, effect: isize) {
+        self.op(opcode, effect);
+        self.bytes.push(operand);
+    }
+
+    fn op_u16(&mut self, opcode: u8, operand: u16, effect: isize) {
+        self.op(opcode, effect);
+        self.bytes.extend_from_slice(&operand.to_be_bytes());
+    }
+
+    /// A load or store, using `wide` for slots past 255.
+    fn local(&mut self, opcode: u8, slot: u16, effect: isize) {
+        match u8::try_from(slot) {
+            Ok(slot) => self.op_u8(opcode, slot, effect),
+            Err(_) => {
+                self.bytes.push(WIDE);
+                self.op_u16(opcode, slot, effect);
+            }
+        }
+    }
+
+    /// Loads a constant that takes one slot, using `ldc_w` for indices past 255.
+    fn ldc(&mut self, index: u16) {
+        match u8::try_from(index) {
+            Ok(index) => self.op_u8(LDC, index, 1),
+            Err(_) => self.op_u16(LDC_W, index, 1),
+        }
+    }
+
+    /// Jumps to `target` when the `int` in `slot` is whitespace as `isspace` sees it in the C
+    /// locale, or when it is not if `when` is false.
+    fn space_jump(&mut self, slot: u16, when: bool, target: usize) {
+        let other = self.new_label();
+        let (space, not_space) = if when {
+            (target, other)
+        } else {
+            (other, target)
+        };
+        self.local(ILOAD, slot, 1);
+        self.op_u8(BIPUSH, b' ', 1);
+        self.jump(IF_ICMPEQ, space, -2);
+        self.local(ILOAD, slot, 1);
+        self.op_u8(BIPUSH, b'\t', 1);
+        self.jump(IF_ICMPLT, not_space, -2);
+        self.local(ILOAD, slot, 1);
+        self.op_u8(BIPUSH, b'\r', 1);
+        if when {
+            self.jump(IF_ICMPLE, target, -2);
+        } else {
+            self.jump(IF_ICMPGT, target, -2);
+        }
+        self.place(other);
+    }
+
+    fn new_label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.bytes.len());
+        self.frames.insert(self.bytes.len());
+    }
+
+    fn jump(&mut self, opcode: u8, label: usize, effect: isize) {
+        self.jumps.push((self.bytes.len(), label));
+        self.op_u16(opcode, 0, effect);
+        // The verifier wants a frame after an unconditional jump, even if nothing jumps there.
+        if opcode == GOTO {
+            self.frames.insert(self.bytes.len());
+        }
+    }
+
+    /// Appends `other`, whose jumps stay relative and whose frames move along.
+    fn append(mut self, other: Code) -> Code {
+        let offset = self.bytes.len();
+        self.max_depth = self.max_depth.max(other.max_depth);
+        let other_labels = self.labels.len();
+        self.labels
+            .extend(other.labels.iter().map(|label| label.map(|l| l + offset)));
+        self.jumps.extend(
+            other
+                .jumps
+                .iter()
+                .map(|&(at, label)| (at + offset, label + other_labels)),
+        );
+        self.frames
+            .extend(other.frames.iter().map(|frame| frame + offset));
+        self.bytes.extend_from_slice(&other.bytes);
+        self
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for &(at, label) in &self.jumps {
+            let target = self.labels[label].expect("Jump to a label that was never placed");
+            let offset = (target as isize - at as isize) as i16;
+            self.bytes[at + 1..at + 3].copy_from_slice(&offset.to_be_bytes());
+        }
+        self.bytes
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Constant {
+    Utf8(String),
+    Class(String),
+    String(String),
+    Long(i64),
+    NameAndType(String, String),
+    Field(String, String, String),
+    Method(String, String, String),
+}
+
+/// The constant pool, each constant is written once and in the order it was first needed.
+struct ConstantPool {
+    bytes: Vec<u8>,
+    /// One more than the last index, as the class file wants it.
+    count: u16,
+    indices: HashMap<Constant, u16>,
+}
+
+impl Default for ConstantPool {
+    fn default() -> Self {
+        ConstantPool {
+            bytes: Vec::new(),
+            count: 1,
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl ConstantPool {
+    fn utf8(&mut self, value: &str) -> u16 {
+        self.add(Constant::Utf8(value.to_string()))
+    }
+
+    fn class(&mut self, name: &str) -> u16 {
+        self.add(Constant::Class(name.to_string()))
+    }
+
+    fn string(&mut self, value: &str) -> u16 {
+        self.add(Constant::String(value.to_string()))
+    }
+
+    fn long(&mut self, value: i64) -> u16 {
+        self.add(Constant::Long(value))
+    }
+
+    fn field(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        self.add(Constant::Field(
+            class.to_string(),
+            name.to_string(),
+            descriptor.to_string(),
+        ))
+    }
+
+    fn method(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        self.add(Constant::Method(
+            class.to_string(),
+            name.to_string(),
+            descriptor.to_string(),
+        ))
+    }
+
+    fn add(&mut self, constant: Constant) -> u16 {
+        if let Some(&index) = self.indices.get(&constant) {
+            return index;
+        }
+        // Constants refer to the ones they are made of, which therefore come first.
+        let mut entry = Vec::new();
+        match &constant {
+            Constant::Utf8(value) => {
+                let encoded = modified_utf8(value);
+                entry.push(1);
+                entry.extend_from_slice(&(encoded.len() as u16).to_be_bytes());
+                entry.extend_from_slice(&encoded);
+            }
+            Constant::Class(name) => {
+                let name = self.utf8(name);
+                entry.push(7);
+                entry.extend_from_slice(&name.to_be_bytes());
+            }
+            Constant::String(value) => {
+                let value = self.utf8(value);
+                entry.push(8);
+                entry.extend_from_slice(&value.to_be_bytes());
+            }
+            Constant::Long(value) => {
+                entry.push(5);
+                entry.extend_from_slice(&value.to_be_bytes());
+            }
+            Constant::NameAndType(name, descriptor) => {
+                let name = self.utf8(name);
+                let descriptor = self.utf8(descriptor);
+                entry.push(12);
+                entry.extend_from_slice(&name.to_be_bytes());
+                entry.extend_from_slice(&descriptor.to_be_bytes());
+            }
+            Constant::Field(class, name, descriptor)
+            | Constant::Method(class, name, descriptor) => {
+                let class = self.class(class);
+                let name_and_type =
+                    self.add(Constant::NameAndType(name.clone(), descriptor.clone()));
+                entry.push(if matches!(constant, Constant::Field(..)) {
+                    9
+                } else {
+                    10
+                });
+                entry.extend_from_slice(&class.to_be_bytes());
+                entry.extend_from_slice(&name_and_type.to_be_bytes());
+            }
+        }
+        let index = self.count;
+        // A long takes up two indices.
+        self.count += if matches!(constant, Constant::Long(_)) {
+            2
+        } else {
+            1
+        };
+        self.bytes.extend_from_slice(&entry);
+        self.indices.insert(constant, index);
+        index
+    }
+}
+
+/// The JVM's variant of UTF-8: NUL takes two bytes and characters outside the BMP are written
+/// as two encoded surrogates.
+fn modified_utf8(value: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for unit in value.encode_utf16() {
+        match unit {
+            0x0001..=0x007F => bytes.push(unit as u8),
+            0x0000 | 0x0080..=0x07FF => {
+                bytes.push(0xC0 | (unit >> 6) as u8);
+                bytes.push(0x80 | (unit & 0x3F) as u8);
+            }
+            _ => {
+                bytes.push(0xE0 | (unit >> 12) as u8);
+                bytes.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                bytes.push(0x80 | (unit & 0x3F) as u8);
+            }
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::analyze;
+
+    fn emit_main(source: &str) -> JvmEmitter {
+        let statements = analyze(source);
+        let mut emitter = JvmEmitter::new(&statements, "Script");
+        for statement in &statements {
+            emitter.emit_statement(statement);
+        }
+        emitter
+    }
+
+    #[test]
+    fn test_emit_chained_comparison() {
+        let mut emitter = emit_main(
+            "\
+let i = 1;
+while 0 < i * 2 <= 5 {
+    i = -i;
+}",
+        );
+        let [two_high, two_low] = emitter.constants.long(2).to_be_bytes();
+        let [five_high, five_low] = emitter.constants.long(5).to_be_bytes();
+        let frames: Vec<usize> = emitter.code.frames.iter().copied().collect();
+        #[rustfmt::skip]
+        let expected_code = vec![
+            LCONST_1, LSTORE, 3,
+            // 3: the start of the loop.
+            LCONST_0, LLOAD, 3, LDC2_W, two_high, two_low, LMUL, DUP2, LSTORE, 5,
+            LCMP, IFGE, 0, 20,
+            LLOAD, 5, LDC2_W, five_high, five_low, LCMP, IFGT, 0, 11,
+            LLOAD, 3, LNEG, LSTORE, 3,
+            GOTO, 0xFF, 0xE4,
+            // 34: the end of the loop.
+        ];
+        assert_eq!(emitter.code.finish(), expected_code);
+        assert_eq!(frames, vec![3, 34]);
+    }
+
+    #[test]
+    fn test_emit_class_file() {
+        let class = JvmEmitter::new(&[], "Script").emit();
+        assert_eq!(class[..8], [0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 52]);
+        assert_eq!(
+            modified_utf8("a\0\u{e9}\u{1F600}"),
+            [b'a', 0xC0, 0x80, 0xC3, 0xA9, 0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80]
+        );
+    }
+}
@@ -0,0 +1,491 @@
+use super::common::{BranchLowering, LoopStack, FORMATS};
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement, UnaryOp,
+};
+
+/// Emits textual LLVM IR, to be compiled with `llc -relocation-model=pic` and linked against libc
+/// with `cc`. Every variable gets an `alloca` in the entry block and is loaded and stored around
+/// each use, `opt` turns that into registers. Pointers are written typed (`i8*`) so older LLVM
+/// versions can read the output too.
+pub struct LlvmEmitter {
+    statements: Vec<Statement>,
+    code: String,
+    allocas: Vec<String>,
+    strings: Vec<String>,
+    registers: usize,
+    labels: usize,
+    /// Whether the current basic block already ends in a branch.
+    terminated: bool,
+    loops: LoopStack<String>,
+}
+
+impl LlvmEmitter {
+    pub fn new(program: &[Statement]) -> Self {
+        LlvmEmitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            allocas: Vec::new(),
+            strings: Vec::new(),
+            registers: 0,
+            labels: 0,
+            terminated: false,
+            loops: LoopStack::default(),
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        for statement in &self.statements.clone() {
+            self.emit_statement(statement);
+        }
+        self.terminate("ret i32 0");
+
+        let mut emit = String::new();
+        for (name, format) in FORMATS {
+            emit.push_str(&Self::emit_global(&format!("@.fmt.{}", name), format));
+        }
+        for (index, string) in self.strings.iter().enumerate() {
+            emit.push_str(&Self::emit_global(&format!("@.str.{}", index), string));
+        }
+        emit.push('\n');
+        emit.push_str("declare i32 @printf(i8*, ...)\n");
+        emit.push_str("declare i32 @scanf(i8*, ...)\n");
+        emit.push('\n');
+        emit.push_str("define i32 @main() {\n");
+        emit.push_str("entry:\n");
+        for alloca in &self.allocas {
+            emit.push_str(&format!("  {} = alloca i64\n", alloca));
+        }
+        emit.push_str(&self.code);
+        emit.push_str("}\n");
+        emit
+    }
+
+    fn emit_global(name: &str, string: &str) -> String {
+        format!(
+            "{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"\n",
+            name,
+            string.len() + 1,
+            Self::escape(string)
+        )
+    }
+
+    /// A pointer to the first character of a global string, as an `i8*` operand.
+    fn string_pointer(name: &str, string: &str) -> String {
+        let array = format!("[{} x i8]", string.len() + 1);
+        format!(
+            "i8* getelementptr inbounds ({}, {}* {}, i64 0, i64 0)",
+            array, array, name
+        )
+    }
+
+    /// A pointer to the format called `name`, see `FORMATS`.
+    fn format_pointer(name: &str) -> String {
+        let (_, format) = FORMATS
+            .iter()
+            .find(|(format_name, _)| *format_name == name)
+            .expect("Unknown format");
+        Self::string_pointer(&format!("@.fmt.{}", name), format)
+    }
+
+    fn instruction(&mut self, instruction: &str) {
+        if self.terminated {
+            // Code after a break or continue can not be reached but still needs a block.
+            let label = self.new_label();
+            self.block(&label);
+        }
+        self.code.push_str("  ");
+        self.code.push_str(instruction);
+        self.code.push('\n');
+    }
+
+    fn terminate(&mut self, instruction: &str) {
+        self.instruction(instruction);
+        self.terminated = true;
+    }
+
+    /// Emits an instruction producing a value and returns the register holding it.
+    fn value(&mut self, instruction: &str) -> String {
+        self.registers += 1;
+        let register = format!("%t{}", self.registers);
+        self.instruction(&format!("{} = {}", register, instruction));
+        register
+    }
+
+    /// Declarations have unique names after semantic analysis, so they can name the `alloca`.
+    fn variable(&mut self, identifier: &Identifier) -> String {
+        let variable = format!("%v.{}", identifier.name());
+        if !self.allocas.contains(&variable) {
+            self.allocas.push(variable.clone());
+        }
+        variable
+    }
+
+    fn emit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print { option, .. } => self.emit_print(option),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                let then_label = self.new_label();
+                let else_label = self.new_label();
+                self.emit_branch(comparison, &then_label, &else_label);
+                self.block(&then_label);
+                self.emit_block(statements);
+                match else_statements {
+                    Some(else_statements) => {
+                        let end_label = self.new_label();
+                        self.terminate(&format!("br label %{}", end_label));
+                        self.block(&else_label);
+                        self.emit_block(else_statements);
+                        self.block(&end_label);
+                    }
+                    None => self.block(&else_label),
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                let condition_label = self.new_label();
+                let body_label = self.new_label();
+                let end_label = self.new_label();
+                self.block(&condition_label);
+                self.emit_branch(comparison, &body_label, &end_label);
+                self.block(&body_label);
+                self.emit_loop_body(statements, &condition_label, &end_label);
+                self.terminate(&format!("br label %{}", condition_label));
+                self.block(&end_label);
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                let condition_label = self.new_label();
+                let body_label = self.new_label();
+                let step_label = self.new_label();
+                let end_label = self.new_label();
+                self.emit_statement(init);
+                self.block(&condition_label);
+                self.emit_branch(comparison, &body_label, &end_label);
+                self.block(&body_label);
+                self.emit_loop_body(statements, &step_label, &end_label);
+                self.block(&step_label);
+                self.emit_statement(step);
+                self.terminate(&format!("br label %{}", condition_label));
+                self.block(&end_label);
+            }
+            Statement::Break { .. } => {
+                let break_label = self.loops.break_target();
+                self.terminate(&format!("br label %{}", break_label));
+            }
+            Statement::Continue { .. } => {
+                let continue_label = self.loops.continue_target();
+                self.terminate(&format!("br label %{}", continue_label));
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                let value = self.emit_expression(expression);
+                let variable = self.variable(identifier);
+                self.instruction(&format!("store i64 {}, i64* {}", value, variable));
+            }
+            Statement::Input { identifier, .. } => self.emit_input(identifier),
+        }
+    }
+
+    fn emit_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.emit_statement(statement);
+        }
+    }
+
+    fn emit_loop_body(&mut self, statements: &[Statement], continue_label: &str, end_label: &str) {
+        self.loops
+            .push(continue_label.to_string(), end_label.to_string());
+        self.emit_block(statements);
+        self.loops.pop();
+    }
+
+    fn emit_print(&mut self, print_option: &PrintOption) {
+        let arguments = match print_option {
+            PrintOption::PrintLiteral(s) => {
+                let string = unescape(s);
+                let name = format!("@.str.{}", self.strings.len());
+                let pointer = Self::string_pointer(&name, &string);
+                self.strings.push(string);
+                format!("{}, {}", Self::format_pointer("string"), pointer)
+            }
+            PrintOption::PrintExpression(e) => {
+                let value = self.emit_expression(e);
+                format!("{}, i64 {}", Self::format_pointer("number"), value)
+            }
+        };
+        self.value(&format!("call i32 (i8*, ...) @printf({})", arguments));
+    }
+
+    fn emit_input(&mut self, identifier: &Identifier) {
+        let variable = self.variable(identifier);
+        let fail_label = self.new_label();
+        let done_label = self.new_label();
+        let read = self.value(&format!(
+            "call i32 (i8*, ...) @scanf({}, i64* {})",
+            Self::format_pointer("input"),
+            variable
+        ));
+        let ok = self.value(&format!("icmp eq i32 {}, 1", read));
+        self.terminate(&format!(
+            "br i1 {}, label %{}, label %{}",
+            ok, done_label, fail_label
+        ));
+        self.block(&fail_label);
+        self.instruction(&format!("store i64 0, i64* {}", variable));
+        self.value(&format!(
+            "call i32 (i8*, ...) @scanf({})",
+            Self::format_pointer("skip")
+        ));
+        self.block(&done_label);
+    }
+
+    fn condition(op: &RelationalOp) -> &'static str {
+        match op {
+            RelationalOp::GreaterThan => "sgt",
+            RelationalOp::GreaterThanEquals => "sge",
+            RelationalOp::LessThan => "slt",
+            RelationalOp::LessThanEquals => "sle",
+            RelationalOp::EqualsEquals => "eq",
+            RelationalOp::NotEquals => "ne",
+        }
+    }
+
+    /// Returns the operand holding the value of `expression`, a register or a constant.
+    fn emit_expression(&mut self, expression: &Expression) -> String {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                let lhs = self.emit_expression(lhs);
+                let rhs = self.emit_expression(rhs);
+                let instruction = match op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Subtract => "sub",
+                    BinaryOp::Multiply => "mul",
+                    BinaryOp::Divide => "sdiv",
+                    BinaryOp::Modulo => "srem",
+                };
+                self.value(&format!("{} i64 {}, {}", instruction, lhs, rhs))
+            }
+            Expression::Unary { op, operand, .. } => {
+                let operand = self.emit_primary(operand);
+                match op {
+                    UnaryOp::Plus => operand,
+                    UnaryOp::Minus => self.value(&format!("sub i64 0, {}", operand)),
+                }
+            }
+            Expression::Primary(p) => self.emit_primary(p),
+        }
+    }
+
+    fn emit_primary(&mut self, primary: &Primary) -> String {
+        match primary {
+            Primary::LiteralNumber(n, _) => n.to_string(),
+            Primary::IdentifierExpression(id) => {
+                let variable = self.variable(id);
+                self.value(&format!("load i64, i64* {}", variable))
+            }
+            Primary::Grouped(e, _) => self.emit_expression(e),
+        }
+    }
+
+    /// Escapes an already unescaped string for a `c"..."` constant.
+    fn escape(string: &str) -> String {
+        let mut escaped = String::new();
+        for byte in string.bytes() {
+            if (b' '..=b'~').contains(&byte) && byte != b'"' && byte != b'\\' {
+                escaped.push(byte as char);
+            } else {
+                escaped.push_str(&format!("\\{:02X}", byte));
+            }
+        }
+        escaped
+    }
+}
+
+impl BranchLowering for LlvmEmitter {
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn block(&mut self, label: &str) {
+        if !self.terminated {
+            self.terminate(&format!("br label %{}", label));
+        }
+        self.code.push_str(label);
+        self.code.push_str(":\n");
+        self.terminated = false;
+    }
+
+    fn emit_operand(&mut self, expression: &Expression) -> String {
+        self.emit_expression(expression)
+    }
+
+    fn emit_compare(&mut self, op: &RelationalOp, lhs: &str, rhs: &str) -> String {
+        self.value(&format!(
+            "icmp {} i64 {}, {}",
+            Self::condition(op),
+            lhs,
+            rhs
+        ))
+    }
+
+    fn emit_conditional_branch(&mut self, condition: &str, true_label: &str, false_label: &str) {
+        self.terminate(&format!(
+            "br i1 {}, label %{}, label %{}",
+            condition, true_label, false_label
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, interpret, run_file, EDGE_CASES};
+
+    fn emit_source(source: &str) -> String {
+        let statements = analyze(source);
+        LlvmEmitter::new(&statements).emit()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+for let i = 0; 0 <= i < n; i = i + 1 {
+    if i == 2 || !n {
+        break;
+    }
+    print -i * 3 % 2;
+}
+print \"100%\\n\";";
+        let expected_code = "\
+@.fmt.number = private unnamed_addr constant [6 x i8] c\"%lld\\0A\\00\"
+@.fmt.string = private unnamed_addr constant [3 x i8] c\"%s\\00\"
+@.fmt.input = private unnamed_addr constant [5 x i8] c\"%lld\\00\"
+@.fmt.skip = private unnamed_addr constant [4 x i8] c\"%*s\\00\"
+@.str.0 = private unnamed_addr constant [6 x i8] c\"100%\\0A\\00\"
+
+declare i32 @printf(i8*, ...)
+declare i32 @scanf(i8*, ...)
+
+define i32 @main() {
+entry:
+  %v.n = alloca i64
+  %v.i = alloca i64
+  %t1 = call i32 (i8*, ...) @scanf(i8* getelementptr inbounds ([5 x i8], [5 x i8]* @.fmt.input, i64 0, i64 0), i64* %v.n)
+  %t2 = icmp eq i32 %t1, 1
+  br i1 %t2, label %L2, label %L1
+L1:
+  store i64 0, i64* %v.n
+  %t3 = call i32 (i8*, ...) @scanf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @.fmt.skip, i64 0, i64 0))
+  br label %L2
+L2:
+  store i64 0, i64* %v.i
+  br label %L3
+L3:
+  %t4 = load i64, i64* %v.i
+  %t5 = icmp sle i64 0, %t4
+  br i1 %t5, label %L7, label %L6
+L7:
+  %t6 = load i64, i64* %v.n
+  %t7 = icmp slt i64 %t4, %t6
+  br i1 %t7, label %L4, label %L6
+L4:
+  %t8 = load i64, i64* %v.i
+  %t9 = icmp eq i64 %t8, 2
+  br i1 %t9, label %L8, label %L10
+L10:
+  %t10 = load i64, i64* %v.n
+  %t11 = icmp ne i64 %t10, 0
+  br i1 %t11, label %L9, label %L8
+L8:
+  br label %L6
+L9:
+  %t12 = load i64, i64* %v.i
+  %t13 = sub i64 0, %t12
+  %t14 = mul i64 %t13, 3
+  %t15 = srem i64 %t14, 2
+  %t16 = call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([6 x i8], [6 x i8]* @.fmt.number, i64 0, i64 0), i64 %t15)
+  br label %L5
+L5:
+  %t17 = load i64, i64* %v.i
+  %t18 = add i64 %t17, 1
+  store i64 %t18, i64* %v.i
+  br label %L3
+L6:
+  %t19 = call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([3 x i8], [3 x i8]* @.fmt.string, i64 0, i64 0), i8* getelementptr inbounds ([6 x i8], [6 x i8]* @.str.0, i64 0, i64 0))
+  ret i32 0
+}
+";
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_unreachable_code_after_continue() {
+        // Every block has to end in a terminator, so the dead print gets a block of its own.
+        let source = "\
+while 1 {
+    continue;
+    print 1;
+}";
+        let expected_code = "\
+entry:
+  br label %L1
+L1:
+  %t1 = icmp ne i64 1, 0
+  br i1 %t1, label %L2, label %L3
+L2:
+  br label %L1
+L4:
+  %t2 = call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([6 x i8], [6 x i8]* @.fmt.number, i64 0, i64 0), i64 1)
+  br label %L1
+L3:
+  ret i32 0
+}
+";
+        let code = emit_source(source);
+        assert_eq!(&code[code.find("entry:").unwrap()..], expected_code);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            LlvmEmitter::escape("a \"b\"\\\t\n"),
+            "a \\22b\\22\\5C\\09\\0A"
+        );
+    }
+
+    /// Runs the edge cases with lli, which is skipped when it is not installed.
+    #[test]
+    fn test_edge_cases_run_like_interpreter() {
+        for (index, (source, input)) in EDGE_CASES.iter().enumerate() {
+            let code = emit_source(source);
+            let file_name = format!("llvm_edge_case_{}.ll", index);
+            if let Some(output) = run_file("lli", &[], &file_name, &code, input) {
+                assert_eq!(output, interpret(source, input), "{}", source);
+            }
+        }
+    }
+}
@@ -1 +1,12 @@
+pub mod cil;
+pub mod common;
 pub mod emitter;
+pub mod js;
+pub mod jvm;
+pub mod llvm;
+pub mod python;
+pub mod qbe;
+pub mod riscv64;
+pub mod rust;
+pub mod wat;
+pub mod x86_64;
@@ -0,0 +1,516 @@
+use super::emitter::Indent;
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Comparison, Expression, Identifier, Primary, PrintOption, Statement, UnaryOp,
+};
+
+/// Emits a Python 3 script. Python integers are unbounded and `//` rounds down, so arithmetic
+/// goes through helpers that wrap to 64 bits and truncate like C. Output is written as bytes and
+/// input is read the way `scanf("%lld")` does, so the script prints exactly what the C backend's
+/// program prints.
+pub struct PythonEmitter {
+    statements: Vec<Statement>,
+    code: String,
+    indentor: Indent,
+}
+
+const PRELUDE: &str = r#"import sys
+
+
+def mmc_wrap(value):
+    return (value + 2**63) % 2**64 - 2**63
+
+
+def mmc_div(lhs, rhs):
+    quotient = abs(lhs) // abs(rhs)
+    return mmc_wrap(quotient if (lhs < 0) == (rhs < 0) else -quotient)
+
+
+def mmc_rem(lhs, rhs):
+    remainder = abs(lhs) % abs(rhs)
+    return remainder if lhs >= 0 else -remainder
+
+
+def mmc_print(value):
+    sys.stdout.buffer.write(b"%d\n" % value)
+
+
+def mmc_peek():
+    return sys.stdin.buffer.peek(1)[:1]
+
+
+def mmc_skip(predicate):
+    while mmc_peek() and predicate(mmc_peek()):
+        sys.stdin.buffer.read(1)
+
+
+def mmc_input():
+    # Prompts printed so far have to be visible before blocking on stdin.
+    sys.stdout.buffer.flush()
+    mmc_skip(bytes.isspace)
+    text = b""
+    if mmc_peek() in (b"+", b"-"):
+        text += sys.stdin.buffer.read(1)
+    while mmc_peek().isdigit():
+        text += sys.stdin.buffer.read(1)
+    if not text[-1:].isdigit():
+        # Not a number, scanf("%*s") skips the next word.
+        mmc_skip(bytes.isspace)
+        mmc_skip(lambda byte: not byte.isspace())
+        return 0
+    return max(-(2**63), min(int(text), 2**63 - 1))
+
+
+"#;
+
+const EPILOGUE: &str = r#"
+
+if __name__ == "__main__":
+    main()
+"#;
+
+/// Keywords and the module the prelude needs, neither can name a variable.
+const RESERVED: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "sys", "try",
+    "while", "with", "yield",
+];
+
+impl PythonEmitter {
+    pub fn new(program: &[Statement]) -> Self {
+        PythonEmitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            indentor: Indent::new(),
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        self.code.push_str(PRELUDE);
+        self.code.push_str("def main():\n");
+        self.indentor.increase();
+        self.code.push_str(&Self::emit_block(
+            &self.statements,
+            None,
+            &mut self.indentor,
+        ));
+        self.code.push_str(EPILOGUE);
+        self.code
+    }
+
+    /// Emits `statements` at the current indentation, `pass` if there are none. `step` is the
+    /// step of the innermost `for`, which has to run before every `continue` in it.
+    fn emit_block(
+        statements: &[Statement],
+        step: Option<&Statement>,
+        indent: &mut Indent,
+    ) -> String {
+        if statements.is_empty() {
+            return format!("{}pass\n", indent.current_indent_spaces());
+        }
+        let mut emit = String::new();
+        for statement in statements {
+            emit.push_str(&Self::emit_statement(statement, step, indent));
+        }
+        emit
+    }
+
+    fn emit_statement(
+        statement: &Statement,
+        step: Option<&Statement>,
+        indent: &mut Indent,
+    ) -> String {
+        let mut emit = indent.current_indent_spaces();
+        match statement {
+            Statement::Print { option, .. } => emit.push_str(&Self::emit_print(option)),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => emit.push_str(&Self::emit_if(
+                comparison,
+                statements,
+                else_statements.as_deref(),
+                step,
+                indent,
+            )),
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                emit.push_str(&format!("while {}:\n", &Self::emit_comparison(comparison)));
+                indent.increase();
+                emit.push_str(&Self::emit_block(statements, None, indent));
+                indent.decrease();
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                emit.push_str(Self::emit_statement(init, None, indent).trim_start());
+                emit.push_str(&indent.current_indent_spaces());
+                emit.push_str(&format!("while {}:\n", &Self::emit_comparison(comparison)));
+                indent.increase();
+                emit.push_str(&Self::emit_block(statements, Some(step), indent));
+                emit.push_str(&Self::emit_statement(step, None, indent));
+                indent.decrease();
+            }
+            Statement::Break { .. } => emit.push_str("break\n"),
+            Statement::Continue { .. } => {
+                if let Some(step) = step {
+                    emit.push_str(Self::emit_statement(step, None, indent).trim_start());
+                    emit.push_str(&indent.current_indent_spaces());
+                }
+                emit.push_str("continue\n");
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => emit.push_str(&format!(
+                "{} = {}\n",
+                Self::emit_identifier(identifier),
+                &Self::emit_expression(expression)
+            )),
+            Statement::Input { identifier, .. } => emit.push_str(&format!(
+                "{} = mmc_input()\n",
+                Self::emit_identifier(identifier)
+            )),
+        };
+        emit
+    }
+
+    fn emit_if(
+        comparison: &Comparison,
+        statements: &[Statement],
+        else_statements: Option<&[Statement]>,
+        step: Option<&Statement>,
+        indent: &mut Indent,
+    ) -> String {
+        let mut emit = format!("if {}:\n", &Self::emit_comparison(comparison));
+        indent.increase();
+        emit.push_str(&Self::emit_block(statements, step, indent));
+        indent.decrease();
+        match else_statements {
+            Some(
+                [Statement::If {
+                    comparison,
+                    statements,
+                    else_statements,
+                    ..
+                }],
+            ) => {
+                emit.push_str(&indent.current_indent_spaces());
+                emit.push_str("el");
+                emit.push_str(&Self::emit_if(
+                    comparison,
+                    statements,
+                    else_statements.as_deref(),
+                    step,
+                    indent,
+                ));
+            }
+            Some(else_statements) => {
+                emit.push_str(&indent.current_indent_spaces());
+                emit.push_str("else:\n");
+                indent.increase();
+                emit.push_str(&Self::emit_block(else_statements, step, indent));
+                indent.decrease();
+            }
+            None => {}
+        }
+        emit
+    }
+
+    fn emit_print(print_option: &PrintOption) -> String {
+        match print_option {
+            PrintOption::PrintLiteral(s) => format!(
+                "sys.stdout.buffer.write({})\n",
+                Self::emit_bytes(&unescape(s))
+            ),
+            PrintOption::PrintExpression(e) => {
+                format!("mmc_print({})\n", &Self::emit_expression(e))
+            }
+        }
+    }
+
+    /// Python chains comparisons itself, evaluating the middle operands only once.
+    fn emit_comparison(comparison: &Comparison) -> String {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                let mut emit = Self::emit_expression(lhs);
+                for (op, operand) in rhs {
+                    emit.push_str(&format!(
+                        " {} {}",
+                        op.symbol(),
+                        &Self::emit_expression(operand)
+                    ));
+                }
+                emit
+            }
+            Comparison::Value(e) => format!("{} != 0", &Self::emit_expression(e)),
+            Comparison::Not(c) => format!("not ({})", &Self::emit_comparison(c)),
+            Comparison::And(lhs, rhs) => format!(
+                "{} and {}",
+                &Self::emit_logical_operand(lhs),
+                &Self::emit_logical_operand(rhs)
+            ),
+            Comparison::Or(lhs, rhs) => format!(
+                "{} or {}",
+                &Self::emit_logical_operand(lhs),
+                &Self::emit_logical_operand(rhs)
+            ),
+        }
+    }
+
+    fn emit_logical_operand(comparison: &Comparison) -> String {
+        match comparison {
+            Comparison::And(..) | Comparison::Or(..) => {
+                format!("({})", &Self::emit_comparison(comparison))
+            }
+            _ => Self::emit_comparison(comparison),
+        }
+    }
+
+    /// Wrapping once after a chain of `+`, `-` and `*` gives the same value as wrapping every
+    /// step, so a chain is a single `mmc_wrap` call and long sums do not nest parentheses.
+    fn emit_expression(expression: &Expression) -> String {
+        match expression {
+            Expression::Binary {
+                op: BinaryOp::Divide,
+                lhs,
+                rhs,
+                ..
+            } => format!(
+                "mmc_div({}, {})",
+                Self::emit_expression(lhs),
+                Self::emit_expression(rhs)
+            ),
+            Expression::Binary {
+                op: BinaryOp::Modulo,
+                lhs,
+                rhs,
+                ..
+            } => format!(
+                "mmc_rem({}, {})",
+                Self::emit_expression(lhs),
+                Self::emit_expression(rhs)
+            ),
+            Expression::Unary {
+                op: UnaryOp::Minus,
+                operand: Primary::LiteralNumber(n, _),
+                ..
+            } => format!("-{}", n),
+            Expression::Binary { .. }
+            | Expression::Unary {
+                op: UnaryOp::Minus, ..
+            } => format!("mmc_wrap({})", Self::emit_term(expression).0),
+            Expression::Unary { operand, .. } | Expression::Primary(operand) => {
+                Self::emit_primary(operand)
+            }
+        }
+    }
+
+    /// Emits a link of an arithmetic chain without wrapping it, together with how tightly it
+    /// binds: 0 for sums, 1 for products and 2 for everything else.
+    fn emit_term(expression: &Expression) -> (String, u8) {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. }
+                if !matches!(op, BinaryOp::Divide | BinaryOp::Modulo) =>
+            {
+                let precedence = if *op == BinaryOp::Multiply { 1 } else { 0 };
+                let (lhs, lhs_precedence) = Self::emit_term(lhs);
+                let (rhs, rhs_precedence) = Self::emit_term(rhs);
+                // Sums are associative here, only `a - (b + c)` and products keep parentheses.
+                let lhs = Self::parenthesize(lhs, lhs_precedence < precedence);
+                let rhs = Self::parenthesize(
+                    rhs,
+                    rhs_precedence < precedence
+                        || (*op == BinaryOp::Subtract && rhs_precedence == 0),
+                );
+                (format!("{} {} {}", lhs, op.symbol(), rhs), precedence)
+            }
+            Expression::Unary {
+                op: UnaryOp::Minus,
+                operand: operand @ (Primary::IdentifierExpression(_) | Primary::Grouped(..)),
+                ..
+            } => {
+                let (operand, precedence) = Self::emit_primary_term(operand);
+                (
+                    format!("-{}", Self::parenthesize(operand, precedence < 2)),
+                    2,
+                )
+            }
+            Expression::Unary {
+                op: UnaryOp::Plus,
+                operand,
+                ..
+            }
+            | Expression::Primary(operand) => Self::emit_primary_term(operand),
+            _ => (Self::emit_expression(expression), 2),
+        }
+    }
+
+    fn emit_primary_term(primary: &Primary) -> (String, u8) {
+        match primary {
+            Primary::Grouped(e, _) => Self::emit_term(e),
+            _ => (Self::emit_primary(primary), 2),
+        }
+    }
+
+    fn parenthesize(term: String, needed: bool) -> String {
+        if needed {
+            format!("({})", term)
+        } else {
+            term
+        }
+    }
+
+    fn emit_primary(primary: &Primary) -> String {
+        match primary {
+            Primary::LiteralNumber(n, _) => n.to_string(),
+            Primary::IdentifierExpression(id) => Self::emit_identifier(id),
+            Primary::Grouped(e, _) => Self::emit_expression(e),
+        }
+    }
+
+    /// Declarations are named `x_1`, `x_2`, so a trailing `_` can not clash with them.
+    fn emit_identifier(identifier: &Identifier) -> String {
+        let name = identifier.name();
+        if RESERVED.contains(&name) {
+            format!("{}_", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Quotes an already unescaped string as a bytes literal holding its UTF-8 encoding.
+    fn emit_bytes(string: &str) -> String {
+        let mut emit = String::from("b\"");
+        for byte in string.bytes() {
+            match byte {
+                b'"' => emit.push_str("\\\""),
+                b'\\' => emit.push_str("\\\\"),
+                b'\n' => emit.push_str("\\n"),
+                b'\t' => emit.push_str("\\t"),
+                b'\r' => emit.push_str("\\r"),
+                b' '..=b'~' => emit.push(byte as char),
+                _ => emit.push_str(&format!("\\x{:02x}", byte)),
+            }
+        }
+        emit.push('"');
+        emit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, interpret, run_file, EDGE_CASES};
+
+    /// Only `main`, without the prelude and the epilogue.
+    fn emit_main(source: &str) -> String {
+        let statements = analyze(source);
+        let code = PythonEmitter::new(&statements).emit();
+        assert!(code.starts_with(PRELUDE) && code.ends_with(EPILOGUE));
+        code[PRELUDE.len()..code.len() - EPILOGUE.len()].to_string()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+for let i = 0; i < n; i = i + 1 {
+    if i == 2 || !n {
+        continue;
+    } else if 0 < i * 2 <= n {
+        break;
+    }
+    print -i * 3 % (2 - n);
+}
+print \"100%\\n\";";
+        let expected_code = "\
+def main():
+    n = mmc_input()
+    i = 0
+    while i < n:
+        if i == 2 or not (n != 0):
+            i = mmc_wrap(i + 1)
+            continue
+        elif 0 < mmc_wrap(i * 2) <= n:
+            break
+        mmc_print(mmc_rem(mmc_wrap(-i * 3), mmc_wrap(2 - n)))
+        i = mmc_wrap(i + 1)
+    sys.stdout.buffer.write(b\"100%\\n\")
+";
+        assert_eq!(emit_main(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_nested_loops_and_empty_blocks() {
+        // The step only runs before a `continue` of the `for` it belongs to.
+        let source = "\
+for let class = 0; class < 3; class = class + 1 {
+    while 1 {
+        continue;
+    }
+    if class {
+    } else {
+        print \"é\\t\\\\\";
+    }
+}";
+        let expected_code = "\
+def main():
+    class_ = 0
+    while class_ < 3:
+        while 1 != 0:
+            continue
+        if class_ != 0:
+            pass
+        else:
+            sys.stdout.buffer.write(b\"\\xc3\\xa9\\t\\\\\")
+        class_ = mmc_wrap(class_ + 1)
+";
+        assert_eq!(emit_main(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_arithmetic_chains() {
+        let source = "\
+let a = 1;
+print a - (a + 2) * -(a - 3) + -a * +(4 / a);";
+        let expected_code = "\
+def main():
+    a = 1
+    mmc_print(mmc_wrap(a - (a + 2) * -(a - 3) + -a * mmc_div(4, a)))
+";
+        assert_eq!(emit_main(source), expected_code);
+
+        // Python gives up on more than 200 nested parentheses.
+        let sum = vec!["a"; 300].join(" + ");
+        let code = emit_main(&format!("let a = 1;\nprint {};", sum));
+        assert!(code.ends_with(&format!("    mmc_print(mmc_wrap({}))\n", sum)));
+    }
+
+    #[test]
+    fn test_edge_cases_run_like_interpreter() {
+        for (index, (source, input)) in EDGE_CASES.iter().enumerate() {
+            let code = PythonEmitter::new(&analyze(source)).emit();
+            let file_name = format!("python_edge_case_{}.py", index);
+            if let Some(output) = run_file("python3", &[], &file_name, &code, input) {
+                assert_eq!(output, interpret(source, input), "{}", source);
+            }
+        }
+    }
+}
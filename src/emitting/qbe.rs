@@ -0,0 +1,546 @@
+use super::common::{escape_gas_string, BranchLowering, LoopStack, FORMATS};
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement, UnaryOp,
+};
+
+/// Emits QBE intermediate language, to be compiled with `qbe file.ssa | cc -x assembler -`.
+/// Every variable gets an `alloc8` slot in the start block and is loaded and stored around each
+/// use, QBE promotes those to temporaries. Calls to `printf` and `scanf` mark their variadic
+/// arguments with `...`, which needs QBE 1.1 or later.
+pub struct QbeEmitter {
+    statements: Vec<Statement>,
+    code: String,
+    slots: Vec<String>,
+    strings: Vec<String>,
+    temporaries: usize,
+    labels: usize,
+    /// Whether the current block already ends in a jump.
+    terminated: bool,
+    loops: LoopStack<String>,
+}
+
+impl QbeEmitter {
+    pub fn new(program: &[Statement]) -> Self {
+        QbeEmitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            slots: Vec::new(),
+            strings: Vec::new(),
+            temporaries: 0,
+            labels: 0,
+            terminated: false,
+            loops: LoopStack::default(),
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        for statement in &self.statements.clone() {
+            self.emit_statement(statement);
+        }
+        self.terminate("ret 0");
+
+        let mut emit = String::new();
+        for (name, format) in FORMATS {
+            emit.push_str(&Self::emit_data(&format!("$fmt_{}", name), format));
+        }
+        for (index, string) in self.strings.iter().enumerate() {
+            emit.push_str(&Self::emit_data(&format!("$str{}", index), string));
+        }
+        emit.push('\n');
+        emit.push_str("export function w $main() {\n");
+        emit.push_str("@start\n");
+        for slot in &self.slots {
+            emit.push_str(&format!("\t{} =l alloc8 8\n", slot));
+        }
+        emit.push_str(&self.code);
+        emit.push_str("}\n");
+        emit
+    }
+
+    /// QBE copies the string into the assembly as it is, so it takes the assembler's escapes.
+    fn emit_data(name: &str, string: &str) -> String {
+        format!(
+            "data {} = {{ b \"{}\", b 0 }}\n",
+            name,
+            escape_gas_string(string)
+        )
+    }
+
+    fn instruction(&mut self, instruction: &str) {
+        if self.terminated {
+            // Code after a break or continue can not be reached but still needs a block.
+            let label = self.new_label();
+            self.block(&label);
+        }
+        self.code.push('\t');
+        self.code.push_str(instruction);
+        self.code.push('\n');
+    }
+
+    fn terminate(&mut self, instruction: &str) {
+        self.instruction(instruction);
+        self.terminated = true;
+    }
+
+    /// Emits an instruction producing a value of type `class` and returns the temporary
+    /// holding it.
+    fn value(&mut self, class: char, instruction: &str) -> String {
+        self.temporaries += 1;
+        let temporary = format!("%t{}", self.temporaries);
+        self.instruction(&format!("{} ={} {}", temporary, class, instruction));
+        temporary
+    }
+
+    /// Declarations have unique names after semantic analysis, so they can name the slot.
+    fn variable(&mut self, identifier: &Identifier) -> String {
+        let variable = format!("%v.{}", identifier.name());
+        if !self.slots.contains(&variable) {
+            self.slots.push(variable.clone());
+        }
+        variable
+    }
+
+    fn emit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print { option, .. } => self.emit_print(option),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                let then_label = self.new_label();
+                let else_label = self.new_label();
+                self.emit_branch(comparison, &then_label, &else_label);
+                self.block(&then_label);
+                self.emit_block(statements);
+                match else_statements {
+                    Some(else_statements) => {
+                        let end_label = self.new_label();
+                        self.terminate(&format!("jmp {}", end_label));
+                        self.block(&else_label);
+                        self.emit_block(else_statements);
+                        self.block(&end_label);
+                    }
+                    None => self.block(&else_label),
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                let condition_label = self.new_label();
+                let body_label = self.new_label();
+                let end_label = self.new_label();
+                self.block(&condition_label);
+                self.emit_branch(comparison, &body_label, &end_label);
+                self.block(&body_label);
+                self.emit_loop_body(statements, &condition_label, &end_label);
+                self.terminate(&format!("jmp {}", condition_label));
+                self.block(&end_label);
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                let condition_label = self.new_label();
+                let body_label = self.new_label();
+                let step_label = self.new_label();
+                let end_label = self.new_label();
+                self.emit_statement(init);
+                self.block(&condition_label);
+                self.emit_branch(comparison, &body_label, &end_label);
+                self.block(&body_label);
+                self.emit_loop_body(statements, &step_label, &end_label);
+                self.block(&step_label);
+                self.emit_statement(step);
+                self.terminate(&format!("jmp {}", condition_label));
+                self.block(&end_label);
+            }
+            Statement::Break { .. } => {
+                let break_label = self.loops.break_target();
+                self.terminate(&format!("jmp {}", break_label));
+            }
+            Statement::Continue { .. } => {
+                let continue_label = self.loops.continue_target();
+                self.terminate(&format!("jmp {}", continue_label));
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                let value = self.emit_expression(expression);
+                let variable = self.variable(identifier);
+                self.instruction(&format!("storel {}, {}", value, variable));
+            }
+            Statement::Input { identifier, .. } => self.emit_input(identifier),
+        }
+    }
+
+    fn emit_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.emit_statement(statement);
+        }
+    }
+
+    fn emit_loop_body(&mut self, statements: &[Statement], continue_label: &str, end_label: &str) {
+        self.loops
+            .push(continue_label.to_string(), end_label.to_string());
+        self.emit_block(statements);
+        self.loops.pop();
+    }
+
+    fn emit_print(&mut self, print_option: &PrintOption) {
+        let arguments = match print_option {
+            PrintOption::PrintLiteral(s) => {
+                self.strings.push(unescape(s));
+                format!("l $fmt_string, ..., l $str{}", self.strings.len() - 1)
+            }
+            PrintOption::PrintExpression(e) => {
+                let value = self.emit_expression(e);
+                format!("l $fmt_number, ..., l {}", value)
+            }
+        };
+        self.value('w', &format!("call $printf({})", arguments));
+    }
+
+    fn emit_input(&mut self, identifier: &Identifier) {
+        let variable = self.variable(identifier);
+        let fail_label = self.new_label();
+        let done_label = self.new_label();
+        let read = self.value(
+            'w',
+            &format!("call $scanf(l $fmt_input, ..., l {})", variable),
+        );
+        let ok = self.value('w', &format!("ceqw {}, 1", read));
+        self.terminate(&format!("jnz {}, {}, {}", ok, done_label, fail_label));
+        self.block(&fail_label);
+        self.instruction(&format!("storel 0, {}", variable));
+        self.value('w', "call $scanf(l $fmt_skip, ...)");
+        self.block(&done_label);
+    }
+
+    fn comparison(op: &RelationalOp) -> &'static str {
+        match op {
+            RelationalOp::GreaterThan => "csgtl",
+            RelationalOp::GreaterThanEquals => "csgel",
+            RelationalOp::LessThan => "csltl",
+            RelationalOp::LessThanEquals => "cslel",
+            RelationalOp::EqualsEquals => "ceql",
+            RelationalOp::NotEquals => "cnel",
+        }
+    }
+
+    /// Returns the operand holding the value of `expression`, a temporary or a constant.
+    fn emit_expression(&mut self, expression: &Expression) -> String {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                let lhs = self.emit_expression(lhs);
+                let rhs = self.emit_expression(rhs);
+                let instruction = match op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Subtract => "sub",
+                    BinaryOp::Multiply => "mul",
+                    BinaryOp::Divide => "div",
+                    BinaryOp::Modulo => "rem",
+                };
+                self.value('l', &format!("{} {}, {}", instruction, lhs, rhs))
+            }
+            Expression::Unary { op, operand, .. } => {
+                let operand = self.emit_primary(operand);
+                match op {
+                    UnaryOp::Plus => operand,
+                    UnaryOp::Minus => self.value('l', &format!("sub 0, {}", operand)),
+                }
+            }
+            Expression::Primary(p) => self.emit_primary(p),
+        }
+    }
+
+    fn emit_primary(&mut self, primary: &Primary) -> String {
+        match primary {
+            Primary::LiteralNumber(n, _) => n.to_string(),
+            Primary::IdentifierExpression(id) => {
+                let variable = self.variable(id);
+                self.value('l', &format!("loadl {}", variable))
+            }
+            Primary::Grouped(e, _) => self.emit_expression(e),
+        }
+    }
+}
+
+impl BranchLowering for QbeEmitter {
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("@L{}", self.labels)
+    }
+
+    fn block(&mut self, label: &str) {
+        self.code.push_str(label);
+        self.code.push('\n');
+        self.terminated = false;
+    }
+
+    fn emit_operand(&mut self, expression: &Expression) -> String {
+        self.emit_expression(expression)
+    }
+
+    fn emit_compare(&mut self, op: &RelationalOp, lhs: &str, rhs: &str) -> String {
+        self.value('w', &format!("{} {}, {}", Self::comparison(op), lhs, rhs))
+    }
+
+    fn emit_conditional_branch(&mut self, condition: &str, true_label: &str, false_label: &str) {
+        self.terminate(&format!(
+            "jnz {}, {}, {}",
+            condition, true_label, false_label
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, decode_gas_string, interpret, EDGE_CASES};
+    use std::collections::HashMap;
+
+    fn emit_source(source: &str) -> String {
+        let statements = analyze(source);
+        QbeEmitter::new(&statements).emit()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+for let i = 0; 0 <= i < n; i = i + 1 {
+    if i == 2 || !n {
+        break;
+    }
+    print -i * 3 % 2;
+}
+print \"100%\\n\";";
+        let expected_code = "\
+data $fmt_number = { b \"%lld\\n\", b 0 }
+data $fmt_string = { b \"%s\", b 0 }
+data $fmt_input = { b \"%lld\", b 0 }
+data $fmt_skip = { b \"%*s\", b 0 }
+data $str0 = { b \"100%\\n\", b 0 }
+
+export function w $main() {
+@start
+\t%v.n =l alloc8 8
+\t%v.i =l alloc8 8
+\t%t1 =w call $scanf(l $fmt_input, ..., l %v.n)
+\t%t2 =w ceqw %t1, 1
+\tjnz %t2, @L2, @L1
+@L1
+\tstorel 0, %v.n
+\t%t3 =w call $scanf(l $fmt_skip, ...)
+@L2
+\tstorel 0, %v.i
+@L3
+\t%t4 =l loadl %v.i
+\t%t5 =w cslel 0, %t4
+\tjnz %t5, @L7, @L6
+@L7
+\t%t6 =l loadl %v.n
+\t%t7 =w csltl %t4, %t6
+\tjnz %t7, @L4, @L6
+@L4
+\t%t8 =l loadl %v.i
+\t%t9 =w ceql %t8, 2
+\tjnz %t9, @L8, @L10
+@L10
+\t%t10 =l loadl %v.n
+\t%t11 =w cnel %t10, 0
+\tjnz %t11, @L9, @L8
+@L8
+\tjmp @L6
+@L9
+\t%t12 =l loadl %v.i
+\t%t13 =l sub 0, %t12
+\t%t14 =l mul %t13, 3
+\t%t15 =l rem %t14, 2
+\t%t16 =w call $printf(l $fmt_number, ..., l %t15)
+@L5
+\t%t17 =l loadl %v.i
+\t%t18 =l add %t17, 1
+\tstorel %t18, %v.i
+\tjmp @L3
+@L6
+\t%t19 =w call $printf(l $fmt_string, ..., l $str0)
+\tret 0
+}
+";
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_unreachable_code_after_continue() {
+        // Instructions can only follow a jump in a new block, so the dead print gets one.
+        let source = "\
+while 1 {
+    continue;
+    print 1;
+}";
+        let expected_code = "\
+@start
+@L1
+\t%t1 =w cnel 1, 0
+\tjnz %t1, @L2, @L3
+@L2
+\tjmp @L1
+@L4
+\t%t2 =w call $printf(l $fmt_number, ..., l 1)
+\tjmp @L1
+@L3
+\tret 0
+}
+";
+        let code = emit_source(source);
+        assert_eq!(&code[code.find("@start").unwrap()..], expected_code);
+    }
+
+    /// Runs `$main` of a module in the subset of QBE IL the emitter produces, with `printf` and
+    /// `scanf` for the formats the emitter uses. Addresses of data are their index.
+    fn evaluate(module: &str, input: &str) -> String {
+        let mut symbols = HashMap::new();
+        let mut strings = Vec::new();
+        let mut labels = HashMap::new();
+        let mut instructions = Vec::new();
+        for line in module.lines() {
+            if let Some(definition) = line.strip_prefix("data ") {
+                let (name, contents) = definition.split_once(" = { b ").unwrap();
+                symbols.insert(name, strings.len() as i64);
+                strings.push(decode_gas_string(contents.strip_suffix(", b 0 }").unwrap()));
+            } else if line.starts_with('@') {
+                labels.insert(line, instructions.len());
+            } else if let Some(instruction) = line.strip_prefix('\t') {
+                instructions.push(instruction);
+            }
+        }
+        let value = |temporaries: &HashMap<&str, i64>, operand: &str| match &operand[..1] {
+            "%" => temporaries[operand],
+            "$" => symbols[operand],
+            _ => operand.parse().unwrap(),
+        };
+
+        let mut words = input.split_whitespace().peekable();
+        let mut output = Vec::new();
+        let mut temporaries = HashMap::new();
+        let mut memory = HashMap::new();
+        let mut allocated = 0;
+        let mut pc = 0;
+        loop {
+            let instruction = instructions[pc];
+            pc += 1;
+            let (op, operands) = instruction.split_once(' ').unwrap();
+            let operands: Vec<&str> = operands.split(", ").collect();
+            match op {
+                "jmp" => pc = labels[operands[0]],
+                "jnz" => {
+                    let taken = value(&temporaries, operands[0]) != 0;
+                    pc = labels[operands[if taken { 1 } else { 2 }]];
+                }
+                "storel" => {
+                    let address = value(&temporaries, operands[1]);
+                    memory.insert(address, value(&temporaries, operands[0]));
+                }
+                "ret" => break,
+                _ => {
+                    let (destination, expression) = instruction.split_once(" =").unwrap();
+                    // Past the class of the result.
+                    let (_, expression) = expression.split_once(' ').unwrap();
+                    let (op, arguments) = expression.split_once(' ').unwrap();
+                    let (first, second) = arguments.split_once(", ").unwrap_or((arguments, ""));
+                    let result = match op {
+                        "alloc8" => {
+                            allocated += 8;
+                            allocated
+                        }
+                        "loadl" => memory[&value(&temporaries, first)],
+                        "call" => {
+                            let (function, arguments) = arguments.split_once('(').unwrap();
+                            let arguments: Vec<i64> = arguments
+                                .trim_end_matches(')')
+                                .split(", ")
+                                .filter(|argument| *argument != "...")
+                                .map(|argument| value(&temporaries, &argument[2..]))
+                                .collect();
+                            let format = &strings[arguments[0] as usize][..];
+                            match (function, format) {
+                                ("$printf", b"%lld\n") => {
+                                    output.extend(format!("{}\n", arguments[1]).bytes());
+                                    0
+                                }
+                                ("$printf", b"%s") => {
+                                    output.extend(&strings[arguments[1] as usize]);
+                                    0
+                                }
+                                ("$scanf", b"%lld") => {
+                                    match words.peek().map(|word| word.parse()) {
+                                        Some(Ok(number)) => {
+                                            words.next();
+                                            memory.insert(arguments[1], number);
+                                            1
+                                        }
+                                        Some(Err(_)) => 0,
+                                        None => -1,
+                                    }
+                                }
+                                ("$scanf", b"%*s") => {
+                                    words.next();
+                                    0
+                                }
+                                _ => panic!("Unknown call {}", instruction),
+                            }
+                        }
+                        _ => {
+                            let lhs = value(&temporaries, first);
+                            let rhs = value(&temporaries, second);
+                            match op {
+                                "add" => lhs.wrapping_add(rhs),
+                                "sub" => lhs.wrapping_sub(rhs),
+                                "mul" => lhs.wrapping_mul(rhs),
+                                "div" => {
+                                    lhs.checked_div(rhs).expect("Expected no division by zero")
+                                }
+                                "rem" => {
+                                    lhs.checked_rem(rhs).expect("Expected no division by zero")
+                                }
+                                "ceqw" | "ceql" => (lhs == rhs) as i64,
+                                "cnel" => (lhs != rhs) as i64,
+                                "csltl" => (lhs < rhs) as i64,
+                                "cslel" => (lhs <= rhs) as i64,
+                                "csgtl" => (lhs > rhs) as i64,
+                                "csgel" => (lhs >= rhs) as i64,
+                                _ => panic!("Unknown instruction {}", instruction),
+                            }
+                        }
+                    };
+                    temporaries.insert(destination, result);
+                }
+            }
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_edge_cases_run_like_interpreter() {
+        for (source, input) in EDGE_CASES {
+            let output = evaluate(&emit_source(source), input);
+            assert_eq!(output, interpret(source, input), "{}", source);
+        }
+    }
+}
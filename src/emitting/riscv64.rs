@@ -0,0 +1,642 @@
+use std::collections::HashMap;
+
+use super::common::{escape_gas_string, JumpLowering, LoopStack, FORMATS};
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement, UnaryOp,
+};
+
+/// Emits RV64IM assembly in GNU as syntax for the LP64 ABI, to be linked against libc with
+/// `riscv64-linux-gnu-gcc file.s`. Every variable lives in a stack slot below `s0`, expressions
+/// are evaluated into `a0` with intermediate values pushed on the stack. `/` and `%` are `div`
+/// and `rem`, which do not trap on a zero divisor.
+pub struct Riscv64Emitter {
+    statements: Vec<Statement>,
+    code: String,
+    /// Stack slot of every variable, as an offset from `s0`.
+    slots: HashMap<String, i64>,
+    strings: Vec<String>,
+    labels: usize,
+    loops: LoopStack<String>,
+}
+
+/// `ra` and the caller's `s0` are saved right below `s0`, the variables come after them.
+const SAVED_REGISTERS_SIZE: i64 = 16;
+
+impl Riscv64Emitter {
+    pub fn new(program: &[Statement]) -> Self {
+        Riscv64Emitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            slots: HashMap::new(),
+            strings: Vec::new(),
+            labels: 0,
+            loops: LoopStack::default(),
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        for statement in &self.statements.clone() {
+            self.emit_statement(statement);
+        }
+        // The stack has to stay 16 byte aligned.
+        let frame_size = (self.slots.len() as i64 * 8 + 15) / 16 * 16;
+
+        let mut emit = String::new();
+        emit.push_str("\t.section .rodata\n");
+        for (name, format) in FORMATS {
+            emit.push_str(&format!(
+                ".Lfmt_{}:\n\t.string \"{}\"\n",
+                name,
+                escape_gas_string(format)
+            ));
+        }
+        for (index, string) in self.strings.iter().enumerate() {
+            emit.push_str(&format!(
+                ".Lstr{}:\n\t.string \"{}\"\n",
+                index,
+                escape_gas_string(string)
+            ));
+        }
+        emit.push_str("\t.text\n");
+        emit.push_str("\t.globl main\n");
+        emit.push_str("\t.type main, @function\n");
+        emit.push_str("main:\n");
+        emit.push_str(&format!("\taddi sp, sp, -{}\n", SAVED_REGISTERS_SIZE));
+        emit.push_str("\tsd ra, 8(sp)\n");
+        emit.push_str("\tsd s0, 0(sp)\n");
+        emit.push_str(&format!("\taddi s0, sp, {}\n", SAVED_REGISTERS_SIZE));
+        if frame_size > 2048 {
+            emit.push_str(&format!("\tli t0, {}\n", frame_size));
+            emit.push_str("\tsub sp, sp, t0\n");
+        } else if frame_size > 0 {
+            emit.push_str(&format!("\taddi sp, sp, -{}\n", frame_size));
+        }
+        emit.push_str(&self.code);
+        emit.push_str("\tli a0, 0\n");
+        emit.push_str(&format!("\taddi sp, s0, -{}\n", SAVED_REGISTERS_SIZE));
+        emit.push_str("\tld ra, 8(sp)\n");
+        emit.push_str("\tld s0, 0(sp)\n");
+        emit.push_str(&format!("\taddi sp, sp, {}\n", SAVED_REGISTERS_SIZE));
+        emit.push_str("\tret\n");
+        emit.push_str("\t.section .note.GNU-stack,\"\",@progbits\n");
+        emit
+    }
+
+    fn instruction(&mut self, instruction: &str) {
+        self.code.push('\t');
+        self.code.push_str(instruction);
+        self.code.push('\n');
+    }
+
+    /// Declarations have unique names after semantic analysis, so a name is all a slot needs.
+    fn slot(&mut self, name: &str) -> i64 {
+        let next = -SAVED_REGISTERS_SIZE - 8 * (self.slots.len() as i64 + 1);
+        *self.slots.entry(name.to_string()).or_insert(next)
+    }
+
+    /// The slot of `identifier` as an `offset(register)` operand. Offsets past the 12 bit
+    /// immediate of loads and stores are added to `s0` in `t2` first.
+    fn variable(&mut self, identifier: &Identifier) -> String {
+        let offset = self.slot(identifier.name());
+        if offset >= -2048 {
+            format!("{}(s0)", offset)
+        } else {
+            self.emit_address("t2", offset);
+            "0(t2)".to_string()
+        }
+    }
+
+    fn emit_address(&mut self, register: &str, offset: i64) {
+        if offset >= -2048 {
+            self.instruction(&format!("addi {}, s0, {}", register, offset));
+        } else {
+            self.instruction(&format!("li {}, {}", register, offset));
+            self.instruction(&format!("add {}, {}, s0", register, register));
+        }
+    }
+
+    fn emit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print { option, .. } => self.emit_print(option),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                let else_label = self.new_label();
+                self.emit_jump(comparison, false, &else_label);
+                self.emit_block(statements);
+                match else_statements {
+                    Some(else_statements) => {
+                        let end_label = self.new_label();
+                        self.instruction(&format!("j {}", end_label));
+                        self.place_label(&else_label);
+                        self.emit_block(else_statements);
+                        self.place_label(&end_label);
+                    }
+                    None => self.place_label(&else_label),
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                let start_label = self.new_label();
+                let end_label = self.new_label();
+                self.place_label(&start_label);
+                self.emit_jump(comparison, false, &end_label);
+                self.emit_loop_body(statements, &start_label, &end_label);
+                self.instruction(&format!("j {}", start_label));
+                self.place_label(&end_label);
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                let start_label = self.new_label();
+                let step_label = self.new_label();
+                let end_label = self.new_label();
+                self.emit_statement(init);
+                self.place_label(&start_label);
+                self.emit_jump(comparison, false, &end_label);
+                self.emit_loop_body(statements, &step_label, &end_label);
+                self.place_label(&step_label);
+                self.emit_statement(step);
+                self.instruction(&format!("j {}", start_label));
+                self.place_label(&end_label);
+            }
+            Statement::Break { .. } => {
+                let break_label = self.loops.break_target();
+                self.emit_goto(&break_label);
+            }
+            Statement::Continue { .. } => {
+                let continue_label = self.loops.continue_target();
+                self.emit_goto(&continue_label);
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                self.emit_expression(expression);
+                let variable = self.variable(identifier);
+                self.instruction(&format!("sd a0, {}", variable));
+            }
+            Statement::Input { identifier, .. } => self.emit_input(identifier),
+        }
+    }
+
+    fn emit_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.emit_statement(statement);
+        }
+    }
+
+    fn emit_loop_body(&mut self, statements: &[Statement], continue_label: &str, end_label: &str) {
+        self.loops
+            .push(continue_label.to_string(), end_label.to_string());
+        self.emit_block(statements);
+        self.loops.pop();
+    }
+
+    fn emit_print(&mut self, print_option: &PrintOption) {
+        match print_option {
+            PrintOption::PrintLiteral(s) => {
+                self.strings.push(unescape(s));
+                let string = format!(".Lstr{}", self.strings.len() - 1);
+                self.instruction(&format!("la a1, {}", string));
+                self.instruction("la a0, .Lfmt_string");
+            }
+            PrintOption::PrintExpression(e) => {
+                self.emit_expression(e);
+                self.instruction("mv a1, a0");
+                self.instruction("la a0, .Lfmt_number");
+            }
+        }
+        self.instruction("call printf");
+    }
+
+    fn emit_input(&mut self, identifier: &Identifier) {
+        let done_label = self.new_label();
+        let offset = self.slot(identifier.name());
+        self.emit_address("a1", offset);
+        self.instruction("la a0, .Lfmt_input");
+        self.instruction("call scanf");
+        self.instruction("li t0, 1");
+        self.instruction(&format!("beq a0, t0, {}", done_label));
+        let variable = self.variable(identifier);
+        self.instruction(&format!("sd zero, {}", variable));
+        self.instruction("la a0, .Lfmt_skip");
+        self.instruction("call scanf");
+        self.place_label(&done_label);
+    }
+
+    /// The branch taken when `t0 op a0` does not hold.
+    fn inverse_branch(op: &RelationalOp) -> &'static str {
+        match op {
+            RelationalOp::GreaterThan => "ble",
+            RelationalOp::GreaterThanEquals => "blt",
+            RelationalOp::LessThan => "bge",
+            RelationalOp::LessThanEquals => "bgt",
+            RelationalOp::EqualsEquals => "bne",
+            RelationalOp::NotEquals => "beq",
+        }
+    }
+
+    /// Pushes in steps of 16 bytes, which keeps the stack aligned.
+    fn push(&mut self, register: &str) {
+        self.instruction("addi sp, sp, -16");
+        self.instruction(&format!("sd {}, 0(sp)", register));
+    }
+
+    fn pop(&mut self, register: &str) {
+        self.instruction(&format!("ld {}, 0(sp)", register));
+        self.instruction("addi sp, sp, 16");
+    }
+
+    /// Leaves the value of `expression` in `a0`, clobbering `t0`.
+    fn emit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                self.emit_expression(lhs);
+                self.push("a0");
+                self.emit_expression(rhs);
+                self.pop("t0");
+                let instruction = match op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Subtract => "sub",
+                    BinaryOp::Multiply => "mul",
+                    BinaryOp::Divide => "div",
+                    BinaryOp::Modulo => "rem",
+                };
+                self.instruction(&format!("{} a0, t0, a0", instruction));
+            }
+            Expression::Unary { op, operand, .. } => {
+                self.emit_primary(operand);
+                if *op == UnaryOp::Minus {
+                    self.instruction("neg a0, a0");
+                }
+            }
+            Expression::Primary(p) => self.emit_primary(p),
+        }
+    }
+
+    fn emit_primary(&mut self, primary: &Primary) {
+        match primary {
+            Primary::LiteralNumber(n, _) => self.instruction(&format!("li a0, {}", n)),
+            Primary::IdentifierExpression(id) => {
+                let variable = self.variable(id);
+                self.instruction(&format!("ld a0, {}", variable));
+            }
+            Primary::Grouped(e, _) => self.emit_expression(e),
+        }
+    }
+}
+
+impl JumpLowering for Riscv64Emitter {
+    type Label = String;
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    fn place_label(&mut self, label: &String) {
+        self.code.push_str(label);
+        self.code.push_str(":\n");
+    }
+
+    fn emit_goto(&mut self, target: &String) {
+        self.instruction(&format!("j {}", target));
+    }
+
+    fn emit_chain_failure(
+        &mut self,
+        lhs: &Expression,
+        rhs: &[(RelationalOp, Expression)],
+        target: &String,
+    ) {
+        self.emit_expression(lhs);
+        for (op, operand) in rhs {
+            // The operand stays in a0 as the left hand side of the next link.
+            self.push("a0");
+            self.emit_expression(operand);
+            self.pop("t0");
+            self.instruction(&format!("{} t0, a0, {}", Self::inverse_branch(op), target));
+        }
+    }
+
+    fn emit_value_jump(&mut self, expression: &Expression, when: bool, target: &String) {
+        self.emit_expression(expression);
+        let branch = if when { "bnez" } else { "beqz" };
+        self.instruction(&format!("{} a0, {}", branch, target));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, between, decode_gas_string, interpret, EDGE_CASES};
+    use std::collections::HashMap;
+
+    fn emit_source(source: &str) -> String {
+        let statements = analyze(source);
+        Riscv64Emitter::new(&statements).emit()
+    }
+
+    /// The instructions of `main` between the prologue and the epilogue, for programs with a
+    /// single variable.
+    fn emit_body(source: &str) -> String {
+        let code = emit_source(source);
+        let start = "\taddi s0, sp, 16\n\taddi sp, sp, -16\n";
+        between(&code, start, "\tli a0, 0\n\taddi sp, s0, -16").to_string()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+while 0 < n {
+    n = n - 1;
+}
+print \"done\\n\";";
+        let expected_code = "\
+\t.section .rodata
+.Lfmt_number:
+\t.string \"%lld\\n\"
+.Lfmt_string:
+\t.string \"%s\"
+.Lfmt_input:
+\t.string \"%lld\"
+.Lfmt_skip:
+\t.string \"%*s\"
+.Lstr0:
+\t.string \"done\\n\"
+\t.text
+\t.globl main
+\t.type main, @function
+main:
+\taddi sp, sp, -16
+\tsd ra, 8(sp)
+\tsd s0, 0(sp)
+\taddi s0, sp, 16
+\taddi sp, sp, -16
+\taddi a1, s0, -24
+\tla a0, .Lfmt_input
+\tcall scanf
+\tli t0, 1
+\tbeq a0, t0, .L1
+\tsd zero, -24(s0)
+\tla a0, .Lfmt_skip
+\tcall scanf
+.L1:
+.L2:
+\tli a0, 0
+\taddi sp, sp, -16
+\tsd a0, 0(sp)
+\tld a0, -24(s0)
+\tld t0, 0(sp)
+\taddi sp, sp, 16
+\tbge t0, a0, .L3
+\tld a0, -24(s0)
+\taddi sp, sp, -16
+\tsd a0, 0(sp)
+\tli a0, 1
+\tld t0, 0(sp)
+\taddi sp, sp, 16
+\tsub a0, t0, a0
+\tsd a0, -24(s0)
+\tj .L2
+.L3:
+\tla a1, .Lstr0
+\tla a0, .Lfmt_string
+\tcall printf
+\tli a0, 0
+\taddi sp, s0, -16
+\tld ra, 8(sp)
+\tld s0, 0(sp)
+\taddi sp, sp, 16
+\tret
+\t.section .note.GNU-stack,\"\",@progbits
+";
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_chained_comparison_and_logical_operators() {
+        // The middle operand is evaluated once and stays in a0 for the next link.
+        let source = "\
+let i = 1;
+if 0 <= i * 2 < 10 || !i {
+    print i % 3;
+}";
+        let expected_code = "\
+\tli a0, 1
+\tsd a0, -24(s0)
+\tli a0, 0
+\taddi sp, sp, -16
+\tsd a0, 0(sp)
+\tld a0, -24(s0)
+\taddi sp, sp, -16
+\tsd a0, 0(sp)
+\tli a0, 2
+\tld t0, 0(sp)
+\taddi sp, sp, 16
+\tmul a0, t0, a0
+\tld t0, 0(sp)
+\taddi sp, sp, 16
+\tbgt t0, a0, .L3
+\taddi sp, sp, -16
+\tsd a0, 0(sp)
+\tli a0, 10
+\tld t0, 0(sp)
+\taddi sp, sp, 16
+\tbge t0, a0, .L3
+\tj .L2
+.L3:
+\tld a0, -24(s0)
+\tbnez a0, .L1
+.L2:
+\tld a0, -24(s0)
+\taddi sp, sp, -16
+\tsd a0, 0(sp)
+\tli a0, 3
+\tld t0, 0(sp)
+\taddi sp, sp, 16
+\trem a0, t0, a0
+\tmv a1, a0
+\tla a0, .Lfmt_number
+\tcall printf
+.L1:
+";
+        assert_eq!(emit_body(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_far_slots() {
+        // 301 variables put the last slot out of reach of a 12 bit offset.
+        let letters: Vec<char> = ('a'..='z').collect();
+        let mut source: String = (0..300)
+            .map(|i| format!("let v{}{} = 0;\n", letters[i % 26], letters[i / 26]))
+            .collect();
+        source.push_str("input n;");
+        let code = emit_source(&source);
+        assert!(code.contains("\taddi s0, sp, 16\n\tli t0, 2416\n\tsub sp, sp, t0\n"));
+        assert!(code.contains("\tli a1, -2424\n\tadd a1, a1, s0\n\tla a0, .Lfmt_input\n"));
+        assert!(code.contains("\tli t2, -2424\n\tadd t2, t2, s0\n\tsd zero, 0(t2)\n"));
+    }
+
+    /// Runs `main` of a program in the subset of RV64 the emitter produces, with `printf` and
+    /// `scanf` for the formats the emitter uses. Addresses of strings are their index.
+    fn evaluate(assembly: &str, input: &str) -> String {
+        let lines: Vec<&str> = assembly.lines().collect();
+        let mut strings = Vec::new();
+        let mut symbols = HashMap::new();
+        let mut labels = HashMap::new();
+        let mut instructions = Vec::new();
+        for (index, line) in lines.iter().enumerate() {
+            if let Some(label) = line.strip_suffix(':') {
+                match lines[index + 1].strip_prefix("\t.string ") {
+                    Some(string) => {
+                        symbols.insert(label, strings.len() as i64);
+                        strings.push(decode_gas_string(string));
+                    }
+                    None => {
+                        labels.insert(label, instructions.len());
+                    }
+                }
+            } else if !line.starts_with("\t.") {
+                instructions.push(line.trim());
+            }
+        }
+
+        let mut words = input.split_whitespace().peekable();
+        let mut output = Vec::new();
+        let mut registers = HashMap::from([("zero", 0), ("sp", 1 << 20), ("s0", 0), ("ra", 0)]);
+        let mut memory = HashMap::new();
+        let mut pc = labels["main"];
+        loop {
+            let instruction = instructions[pc];
+            let (op, operands) = instruction.split_once(' ').unwrap_or((instruction, ""));
+            let operands: Vec<&str> = operands.split(", ").collect();
+            pc += 1;
+            let get = |registers: &HashMap<&str, i64>, register: &str| registers[register];
+            // The address of an `offset(register)` operand.
+            let address = |registers: &HashMap<&str, i64>, operand: &str| {
+                let (offset, register) =
+                    operand.strip_suffix(')').unwrap().split_once('(').unwrap();
+                offset.parse::<i64>().unwrap() + registers[register]
+            };
+            match op {
+                "li" => {
+                    registers.insert(operands[0], operands[1].parse().unwrap());
+                }
+                "la" => {
+                    registers.insert(operands[0], symbols[operands[1]]);
+                }
+                "mv" => {
+                    registers.insert(operands[0], get(&registers, operands[1]));
+                }
+                "neg" => {
+                    registers.insert(operands[0], get(&registers, operands[1]).wrapping_neg());
+                }
+                "addi" => {
+                    let value = get(&registers, operands[1]) + operands[2].parse::<i64>().unwrap();
+                    registers.insert(operands[0], value);
+                }
+                "ld" => {
+                    let value = memory[&address(&registers, operands[1])];
+                    registers.insert(operands[0], value);
+                }
+                "sd" => {
+                    let value = get(&registers, operands[0]);
+                    memory.insert(address(&registers, operands[1]), value);
+                }
+                "j" => pc = labels[operands[0]],
+                "beqz" | "bnez" => {
+                    if (get(&registers, operands[0]) == 0) == (op == "beqz") {
+                        pc = labels[operands[1]];
+                    }
+                }
+                "beq" | "bne" | "blt" | "bge" | "ble" | "bgt" => {
+                    let lhs = get(&registers, operands[0]);
+                    let rhs = get(&registers, operands[1]);
+                    let taken = match op {
+                        "beq" => lhs == rhs,
+                        "bne" => lhs != rhs,
+                        "blt" => lhs < rhs,
+                        "bge" => lhs >= rhs,
+                        "ble" => lhs <= rhs,
+                        _ => lhs > rhs,
+                    };
+                    if taken {
+                        pc = labels[operands[2]];
+                    }
+                }
+                "call" => {
+                    let format = &strings[get(&registers, "a0") as usize][..];
+                    let argument = get(&registers, "a1");
+                    let result = match (operands[0], format) {
+                        ("printf", b"%lld\n") => {
+                            output.extend(format!("{}\n", argument).bytes());
+                            0
+                        }
+                        ("printf", b"%s") => {
+                            output.extend(&strings[argument as usize]);
+                            0
+                        }
+                        ("scanf", b"%lld") => match words.peek().map(|word| word.parse()) {
+                            Some(Ok(value)) => {
+                                words.next();
+                                memory.insert(argument, value);
+                                1
+                            }
+                            Some(Err(_)) => 0,
+                            None => -1,
+                        },
+                        ("scanf", b"%*s") => {
+                            words.next();
+                            0
+                        }
+                        _ => panic!("Unknown call {}", instruction),
+                    };
+                    registers.insert("a0", result);
+                }
+                "ret" => break,
+                _ => {
+                    let lhs = get(&registers, operands[1]);
+                    let rhs = get(&registers, operands[2]);
+                    let value = match op {
+                        "add" => lhs.wrapping_add(rhs),
+                        "sub" => lhs.wrapping_sub(rhs),
+                        "mul" => lhs.wrapping_mul(rhs),
+                        "div" => lhs.checked_div(rhs).expect("Expected no division by zero"),
+                        "rem" => lhs.checked_rem(rhs).expect("Expected no division by zero"),
+                        _ => panic!("Unknown instruction {}", instruction),
+                    };
+                    registers.insert(operands[0], value);
+                }
+            }
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_edge_cases_run_like_interpreter() {
+        for (source, input) in EDGE_CASES {
+            let output = evaluate(&emit_source(source), input);
+            assert_eq!(output, interpret(source, input), "{}", source);
+        }
+    }
+}
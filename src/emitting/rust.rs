@@ -0,0 +1,588 @@
+use std::collections::HashSet;
+
+use super::common::{is_trivial, temporary_name};
+use super::emitter::Indent;
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Comparison, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement,
+    UnaryOp,
+};
+
+/// Emits Rust source with a self-contained `run` function, reading from and printing to the
+/// reader and writer it is given. Arithmetic uses the `wrapping_*` methods so overflow behaves
+/// like the C backend, division by zero panics.
+pub struct RustEmitter {
+    statements: Vec<Statement>,
+    code: String,
+    indentor: Indent,
+    /// Variables assigned after their declaration, which have to be `let mut`.
+    mutable: HashSet<String>,
+}
+
+const PRELUDE: &str = r#"use std::io::{self, BufRead, Write};
+
+#[allow(
+    dead_code,
+    non_snake_case,
+    unused_comparisons,
+    unused_mut,
+    unused_variables,
+    unused_assignments,
+    unreachable_code
+)]
+pub fn run<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    /// Reads a number the way `scanf("%lld")` does, anything else reads as 0 and is skipped.
+    fn read_integer<R: BufRead>(input: &mut R) -> io::Result<i64> {
+        fn peek<R: BufRead>(input: &mut R) -> io::Result<Option<u8>> {
+            Ok(input.fill_buf()?.first().copied())
+        }
+        fn is_space(byte: u8) -> bool {
+            byte.is_ascii_whitespace() || byte == 0x0b
+        }
+        fn skip<R: BufRead>(input: &mut R, predicate: impl Fn(u8) -> bool) -> io::Result<()> {
+            while let Some(byte) = peek(input)? {
+                if !predicate(byte) {
+                    break;
+                }
+                input.consume(1);
+            }
+            Ok(())
+        }
+        skip(input, is_space)?;
+        let mut text = String::new();
+        if let Some(sign @ (b'+' | b'-')) = peek(input)? {
+            text.push(sign as char);
+            input.consume(1);
+        }
+        while let Some(digit) = peek(input)?.filter(u8::is_ascii_digit) {
+            text.push(digit as char);
+            input.consume(1);
+        }
+        if !text.ends_with(|c: char| c.is_ascii_digit()) {
+            skip(input, is_space)?;
+            skip(input, |b| !is_space(b))?;
+            return Ok(0);
+        }
+        Ok(text.parse().unwrap_or(if text.starts_with('-') {
+            i64::MIN
+        } else {
+            i64::MAX
+        }))
+    }
+
+"#;
+
+const EPILOGUE: &str = "    output.flush()
+}
+";
+
+/// Keywords, which can not name a variable, the enum variants of the prelude, which a `let`
+/// would match against instead of binding, and the parameters of `run`.
+const RESERVED: &[&str] = &[
+    "Err", "None", "Ok", "Self", "Some", "abstract", "as", "async", "await", "become", "box",
+    "break", "const", "continue", "crate", "do", "dyn", "else", "enum", "extern", "false", "final",
+    "fn", "for", "gen", "if", "impl", "in", "input", "let", "loop", "macro", "match", "mod",
+    "move", "mut", "output", "override", "priv", "pub", "ref", "return", "self", "static",
+    "struct", "super", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+impl RustEmitter {
+    pub fn new(program: &[Statement]) -> Self {
+        let mut mutable = HashSet::new();
+        Self::collect_assigned(program, &mut mutable);
+        RustEmitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            indentor: Indent::new(),
+            mutable,
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        self.code.push_str(PRELUDE);
+        self.indentor.increase();
+        for statement in &self.statements {
+            self.code.push_str(&Self::emit_statement(
+                statement,
+                None,
+                &self.mutable,
+                &mut self.indentor,
+            ));
+        }
+        self.code.push_str(EPILOGUE);
+        self.code
+    }
+
+    fn collect_assigned(statements: &[Statement], assigned: &mut HashSet<String>) {
+        for statement in statements {
+            match statement {
+                Statement::Assign { identifier, .. } => {
+                    assigned.insert(identifier.name().to_string());
+                }
+                Statement::If {
+                    statements,
+                    else_statements,
+                    ..
+                } => {
+                    Self::collect_assigned(statements, assigned);
+                    Self::collect_assigned(else_statements.as_deref().unwrap_or(&[]), assigned);
+                }
+                Statement::While { statements, .. } => Self::collect_assigned(statements, assigned),
+                Statement::For {
+                    init,
+                    step,
+                    statements,
+                    ..
+                } => {
+                    Self::collect_assigned(std::slice::from_ref(init), assigned);
+                    Self::collect_assigned(std::slice::from_ref(step), assigned);
+                    Self::collect_assigned(statements, assigned);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// `step` is the step of the innermost `for`, which has to run before every `continue` in it.
+    fn emit_statement(
+        statement: &Statement,
+        step: Option<&Statement>,
+        mutable: &HashSet<String>,
+        indent: &mut Indent,
+    ) -> String {
+        let mut emit = indent.current_indent_spaces();
+        match statement {
+            Statement::Print { option, .. } => emit.push_str(&Self::emit_print(option)),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => emit.push_str(&Self::emit_if(
+                comparison,
+                statements,
+                else_statements.as_deref(),
+                step,
+                mutable,
+                indent,
+            )),
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                emit.push_str(&format!("while {} ", &Self::emit_comparison(comparison)));
+                emit.push_str(&Self::emit_block(statements, None, mutable, indent));
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                emit.push_str(Self::emit_statement(init, None, mutable, indent).trim_start());
+                emit.push_str(&indent.current_indent_spaces());
+                emit.push_str(&format!(
+                    "while {} {{\n",
+                    &Self::emit_comparison(comparison)
+                ));
+                indent.increase();
+                for statement in statements {
+                    emit.push_str(&Self::emit_statement(
+                        statement,
+                        Some(step),
+                        mutable,
+                        indent,
+                    ));
+                }
+                emit.push_str(&Self::emit_statement(step, None, mutable, indent));
+                indent.decrease();
+                emit.push_str(&indent.current_indent_spaces());
+                emit.push('}');
+            }
+            Statement::Break { .. } => emit.push_str("break;"),
+            Statement::Continue { .. } => {
+                if let Some(step) = step {
+                    emit.push_str(Self::emit_statement(step, None, mutable, indent).trim_start());
+                    emit.push_str(&indent.current_indent_spaces());
+                }
+                emit.push_str("continue;");
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            } => emit.push_str(&format!(
+                "let {}{}: i64 = {};",
+                Self::emit_mut(identifier, mutable),
+                Self::emit_identifier(identifier),
+                &Self::emit_expression(expression)
+            )),
+            Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => emit.push_str(&format!(
+                "{} = {};",
+                Self::emit_identifier(identifier),
+                &Self::emit_expression(expression)
+            )),
+            Statement::Input { identifier, .. } => {
+                // Prompts printed so far have to be visible before blocking on the input.
+                emit.push_str("output.flush()?;\n");
+                emit.push_str(&indent.current_indent_spaces());
+                emit.push_str(&format!(
+                    "let {}{}: i64 = read_integer(&mut input)?;",
+                    Self::emit_mut(identifier, mutable),
+                    Self::emit_identifier(identifier)
+                ));
+            }
+        };
+        emit.push('\n');
+        emit
+    }
+
+    fn emit_mut(identifier: &Identifier, mutable: &HashSet<String>) -> &'static str {
+        if mutable.contains(identifier.name()) {
+            "mut "
+        } else {
+            ""
+        }
+    }
+
+    fn emit_block(
+        statements: &[Statement],
+        step: Option<&Statement>,
+        mutable: &HashSet<String>,
+        indent: &mut Indent,
+    ) -> String {
+        let mut emit = String::from("{\n");
+        indent.increase();
+        for statement in statements {
+            emit.push_str(&Self::emit_statement(statement, step, mutable, indent));
+        }
+        indent.decrease();
+        emit.push_str(&indent.current_indent_spaces());
+        emit.push('}');
+        emit
+    }
+
+    fn emit_if(
+        comparison: &Comparison,
+        statements: &[Statement],
+        else_statements: Option<&[Statement]>,
+        step: Option<&Statement>,
+        mutable: &HashSet<String>,
+        indent: &mut Indent,
+    ) -> String {
+        let mut emit = format!("if {} ", &Self::emit_comparison(comparison));
+        emit.push_str(&Self::emit_block(statements, step, mutable, indent));
+        match else_statements {
+            Some(
+                [Statement::If {
+                    comparison,
+                    statements,
+                    else_statements,
+                    ..
+                }],
+            ) => {
+                emit.push_str(" else ");
+                emit.push_str(&Self::emit_if(
+                    comparison,
+                    statements,
+                    else_statements.as_deref(),
+                    step,
+                    mutable,
+                    indent,
+                ));
+            }
+            Some(else_statements) => {
+                emit.push_str(" else ");
+                emit.push_str(&Self::emit_block(else_statements, step, mutable, indent));
+            }
+            None => {}
+        }
+        emit
+    }
+
+    fn emit_print(print_option: &PrintOption) -> String {
+        match print_option {
+            PrintOption::PrintLiteral(s) => {
+                format!("output.write_all({})?;", Self::emit_bytes(&unescape(s)))
+            }
+            PrintOption::PrintExpression(e) => {
+                format!(
+                    "writeln!(output, \"{{}}\", {})?;",
+                    &Self::emit_expression(e)
+                )
+            }
+        }
+    }
+
+    fn emit_comparison(comparison: &Comparison) -> String {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                Self::emit_links(Self::emit_expression(lhs), rhs)
+            }
+            Comparison::Value(e) => format!("{} != 0", &Self::emit_expression(e)),
+            Comparison::Not(c) => format!("!({})", &Self::emit_comparison(c)),
+            Comparison::And(lhs, rhs) => format!(
+                "{} && {}",
+                &Self::emit_logical_operand(lhs),
+                &Self::emit_logical_operand(rhs)
+            ),
+            Comparison::Or(lhs, rhs) => format!(
+                "{} || {}",
+                &Self::emit_logical_operand(lhs),
+                &Self::emit_logical_operand(rhs)
+            ),
+        }
+    }
+
+    /// Compares `left` against the links of a chain. A middle operand that is not a number or a
+    /// variable is bound to a temporary in a block, so it is evaluated only once.
+    fn emit_links(left: String, links: &[(RelationalOp, Expression)]) -> String {
+        let ((op, operand), rest) = links.split_first().expect("Expected a comparison");
+        if rest.is_empty() {
+            return format!(
+                "{} {} {}",
+                left,
+                op.symbol(),
+                &Self::emit_expression(operand)
+            );
+        }
+        if is_trivial(operand) {
+            let operand = Self::emit_expression(operand);
+            return format!(
+                "{} {} {} && {}",
+                left,
+                op.symbol(),
+                operand,
+                &Self::emit_links(operand.clone(), rest)
+            );
+        }
+        let temporary = temporary_name(operand);
+        format!(
+            "{{ let {} = {}; {} {} {} && {} }}",
+            temporary,
+            &Self::emit_expression(operand),
+            left,
+            op.symbol(),
+            temporary,
+            &Self::emit_links(temporary.clone(), rest)
+        )
+    }
+
+    fn emit_logical_operand(comparison: &Comparison) -> String {
+        match comparison {
+            Comparison::Relational { rhs, .. } if rhs.len() > 1 => {
+                format!("({})", &Self::emit_comparison(comparison))
+            }
+            Comparison::And(..) | Comparison::Or(..) => {
+                format!("({})", &Self::emit_comparison(comparison))
+            }
+            _ => Self::emit_comparison(comparison),
+        }
+    }
+
+    /// Operations are written as `i64::wrapping_add(lhs, rhs)`, so operands never need
+    /// parentheses.
+    fn emit_expression(expression: &Expression) -> String {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                let method = match op {
+                    BinaryOp::Add => "wrapping_add",
+                    BinaryOp::Subtract => "wrapping_sub",
+                    BinaryOp::Multiply => "wrapping_mul",
+                    BinaryOp::Divide => "wrapping_div",
+                    BinaryOp::Modulo => "wrapping_rem",
+                };
+                format!(
+                    "i64::{}({}, {})",
+                    method,
+                    &Self::emit_expression(lhs),
+                    &Self::emit_expression(rhs)
+                )
+            }
+            Expression::Unary {
+                op: UnaryOp::Minus,
+                operand: Primary::LiteralNumber(n, _),
+                ..
+            } => format!("-{}", n),
+            Expression::Unary { op, operand, .. } => match op {
+                UnaryOp::Plus => Self::emit_primary(operand),
+                UnaryOp::Minus => format!("i64::wrapping_neg({})", &Self::emit_primary(operand)),
+            },
+            Expression::Primary(p) => Self::emit_primary(p),
+        }
+    }
+
+    fn emit_primary(primary: &Primary) -> String {
+        match primary {
+            Primary::LiteralNumber(n, _) => n.to_string(),
+            Primary::IdentifierExpression(id) => Self::emit_identifier(id),
+            Primary::Grouped(e, _) => Self::emit_expression(e),
+        }
+    }
+
+    /// Declarations are named `x_1`, `x_2`, so a trailing `_` can not clash with them.
+    fn emit_identifier(identifier: &Identifier) -> String {
+        let name = identifier.name();
+        if RESERVED.contains(&name) {
+            format!("{}_", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Quotes an already unescaped string as a byte string literal holding its UTF-8 encoding.
+    fn emit_bytes(string: &str) -> String {
+        let mut emit = String::from("b\"");
+        for byte in string.bytes() {
+            match byte {
+                b'"' => emit.push_str("\\\""),
+                b'\\' => emit.push_str("\\\\"),
+                b'\n' => emit.push_str("\\n"),
+                b'\t' => emit.push_str("\\t"),
+                b'\r' => emit.push_str("\\r"),
+                b' '..=b'~' => emit.push(byte as char),
+                _ => emit.push_str(&format!("\\x{:02x}", byte)),
+            }
+        }
+        emit.push('"');
+        emit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, interpret, run, temp_path, EDGE_CASES};
+
+    /// The body of `run` after `read_integer`.
+    fn emit_body(source: &str) -> String {
+        let statements = analyze(source);
+        let code = RustEmitter::new(&statements).emit();
+        code.strip_prefix(PRELUDE).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+for let i = 0; i < n; i = i + 1 {
+    if i == 2 || !n {
+        continue;
+    } else if 0 < i * 2 <= n {
+        break;
+    }
+    print -i * 3 % (2 - n);
+}
+print \"100%\\n\";";
+        let expected_code = "    output.flush()?;
+    let n: i64 = read_integer(&mut input)?;
+    let mut i: i64 = 0;
+    while i < n {
+        if i == 2 || !(n != 0) {
+            i = i64::wrapping_add(i, 1);
+            continue;
+        } else if { let mmc_tmp_101 = i64::wrapping_mul(i, 2); 0 < mmc_tmp_101 && mmc_tmp_101 <= n } {
+            break;
+        }
+        writeln!(output, \"{}\", i64::wrapping_rem(i64::wrapping_mul(i64::wrapping_neg(i), 3), i64::wrapping_sub(2, n)))?;
+        i = i64::wrapping_add(i, 1);
+    }
+    output.write_all(b\"100%\\n\")?;
+    output.flush()
+}
+";
+        assert_eq!(emit_body(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_chained_comparison() {
+        let source = "\
+let i = 1;
+if 0 < i * 2 <= i + 1 < 10 || !(i == 1) {
+    i = -i;
+}";
+        let expected_code = "    let mut i: i64 = 1;
+    if ({ let mmc_tmp_18 = i64::wrapping_mul(i, 2); 0 < mmc_tmp_18 && { let mmc_tmp_27 = i64::wrapping_add(i, 1); mmc_tmp_18 <= mmc_tmp_27 && mmc_tmp_27 < 10 } }) || !(i == 1) {
+        i = i64::wrapping_neg(i);
+    }
+    output.flush()
+}
+";
+        assert_eq!(emit_body(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_reserved_identifiers() {
+        let source = "\
+let None = 1;
+let Some = None;
+Some = Some + 1;
+print Some;";
+        let expected_code = "    let None_: i64 = 1;
+    let mut Some_: i64 = None_;
+    Some_ = i64::wrapping_add(Some_, 1);
+    writeln!(output, \"{}\", Some_)?;
+    output.flush()
+}
+";
+        assert_eq!(emit_body(source), expected_code);
+    }
+
+    /// Compiles the script with a `main` calling `run` under `-D warnings`, like a project
+    /// embedding it would, and checks it prints what the interpreter does. Skipped when rustc is
+    /// not installed.
+    fn assert_runs_like_interpreter(name: &str, source: &str, input: &str) {
+        let path = temp_path(&format!("{}.rs", name));
+        let binary = temp_path(name);
+        let code = RustEmitter::new(&analyze(source)).emit();
+        let main = "\nfn main() {\n    run(io::stdin().lock(), io::stdout()).unwrap();\n}\n";
+        std::fs::write(&path, code + main).unwrap();
+        let (path_arg, binary_arg) = (path.to_str().unwrap(), binary.to_str().unwrap());
+        let compiled = run(
+            "rustc",
+            &[
+                "-D",
+                "warnings",
+                "--edition",
+                "2021",
+                "-o",
+                binary_arg,
+                path_arg,
+            ],
+            "",
+        );
+        std::fs::remove_file(&path).unwrap();
+        if compiled.is_some() {
+            let output = run(binary_arg, &[], input).unwrap();
+            std::fs::remove_file(&binary).unwrap();
+            assert_eq!(output, interpret(source, input), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_emit_comparisons_at_the_limits() {
+        let source = "\
+input x;
+if x <= 9223372036854775807 {
+    print 1;
+}
+if x > 9223372036854775807 || x < -9223372036854775807 - 1 {
+    print 2;
+}";
+        assert_runs_like_interpreter("limits", source, "3");
+    }
+
+    #[test]
+    fn test_edge_cases_run_like_interpreter() {
+        for (index, (source, input)) in EDGE_CASES.iter().enumerate() {
+            assert_runs_like_interpreter(&format!("rust_edge_case_{}", index), source, input);
+        }
+    }
+}
@@ -0,0 +1,609 @@
+use super::common::LoopStack;
+use super::emitter::Indent;
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Comparison, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement,
+    UnaryOp,
+};
+
+/// Emits a WebAssembly text module exporting its `memory` and a `main` function. The host has to
+/// provide three imports from `env`:
+///
+/// - `print_i64(value: i64)` prints a number on a line of its own,
+/// - `print_str(offset: i32, length: i32)` prints UTF-8 bytes from the exported memory,
+/// - `input_i64() -> i64` reads a number. To behave like the C backend it has to parse the
+///   longest prefix that is a number like `scanf("%lld")`, saturating on overflow, and return 0
+///   and skip the next word when there is none.
+pub struct WatEmitter {
+    statements: Vec<Statement>,
+    code: String,
+    indentor: Indent,
+    locals: Vec<String>,
+    /// String literals with their offset into the data segment.
+    strings: Vec<(String, usize)>,
+    data: Vec<u8>,
+    labels: usize,
+    loops: LoopStack<String>,
+}
+
+const PAGE_SIZE: usize = 65536;
+
+impl WatEmitter {
+    pub fn new(program: &[Statement]) -> Self {
+        WatEmitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            indentor: Indent::new(),
+            locals: Vec::new(),
+            strings: Vec::new(),
+            data: Vec::new(),
+            labels: 0,
+            loops: LoopStack::default(),
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        self.indentor.increase();
+        self.indentor.increase();
+        for statement in &self.statements.clone() {
+            self.emit_statement(statement);
+        }
+
+        let mut emit = String::new();
+        emit.push_str("(module\n");
+        emit.push_str("\t(import \"env\" \"print_i64\" (func $print_i64 (param i64)))\n");
+        emit.push_str("\t(import \"env\" \"print_str\" (func $print_str (param i32 i32)))\n");
+        emit.push_str("\t(import \"env\" \"input_i64\" (func $input_i64 (result i64)))\n");
+        let pages = self.data.len().div_ceil(PAGE_SIZE).max(1);
+        emit.push_str(&format!("\t(memory (export \"memory\") {})\n", pages));
+        if !self.data.is_empty() {
+            emit.push_str(&format!(
+                "\t(data (i32.const 0) \"{}\")\n",
+                Self::escape(&self.data)
+            ));
+        }
+        emit.push_str("\t(func (export \"main\")\n");
+        for local in &self.locals {
+            emit.push_str(&format!("\t\t(local ${} i64)\n", local));
+        }
+        emit.push_str(&self.code);
+        emit.push_str("\t)\n");
+        emit.push_str(")\n");
+        emit
+    }
+
+    fn line(&mut self, line: &str) {
+        self.code.push_str(&self.indentor.current_indent());
+        self.code.push_str(line);
+        self.code.push('\n');
+    }
+
+    fn new_label(&mut self, kind: &str) -> String {
+        self.labels += 1;
+        format!("${}{}", kind, self.labels)
+    }
+
+    fn local(&mut self, name: &str) -> String {
+        if !self.locals.iter().any(|local| local == name) {
+            self.locals.push(name.to_string());
+        }
+        format!("${}", name)
+    }
+
+    /// Declarations have unique names after semantic analysis, so they can name the local.
+    fn variable(&mut self, identifier: &Identifier) -> String {
+        self.local(identifier.name())
+    }
+
+    fn emit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print { option, .. } => self.emit_print(option),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                self.emit_comparison(comparison);
+                self.line("if");
+                self.emit_block(statements);
+                if let Some(else_statements) = else_statements {
+                    self.line("else");
+                    self.emit_block(else_statements);
+                }
+                self.line("end");
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                let break_label = self.new_label("break");
+                let loop_label = self.new_label("loop");
+                self.line(&format!("block {}", break_label));
+                self.indentor.increase();
+                self.line(&format!("loop {}", loop_label));
+                self.indentor.increase();
+                self.emit_exit(comparison, &break_label);
+                self.loops.push(loop_label.clone(), break_label);
+                for statement in statements {
+                    self.emit_statement(statement);
+                }
+                self.loops.pop();
+                self.line(&format!("br {}", loop_label));
+                self.indentor.decrease();
+                self.line("end");
+                self.indentor.decrease();
+                self.line("end");
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                let break_label = self.new_label("break");
+                let loop_label = self.new_label("loop");
+                let continue_label = self.new_label("continue");
+                self.emit_statement(init);
+                self.line(&format!("block {}", break_label));
+                self.indentor.increase();
+                self.line(&format!("loop {}", loop_label));
+                self.indentor.increase();
+                self.emit_exit(comparison, &break_label);
+                // Continuing leaves this block and runs the step.
+                self.line(&format!("block {}", continue_label));
+                self.loops.push(continue_label, break_label);
+                self.emit_block(statements);
+                self.loops.pop();
+                self.line("end");
+                self.emit_statement(step);
+                self.line(&format!("br {}", loop_label));
+                self.indentor.decrease();
+                self.line("end");
+                self.indentor.decrease();
+                self.line("end");
+            }
+            Statement::Break { .. } => {
+                let break_label = self.loops.break_target();
+                self.line(&format!("br {}", break_label));
+            }
+            Statement::Continue { .. } => {
+                let continue_label = self.loops.continue_target();
+                self.line(&format!("br {}", continue_label));
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                self.emit_expression(expression);
+                let variable = self.variable(identifier);
+                self.line(&format!("local.set {}", variable));
+            }
+            Statement::Input { identifier, .. } => {
+                self.line("call $input_i64");
+                let variable = self.variable(identifier);
+                self.line(&format!("local.set {}", variable));
+            }
+        }
+    }
+
+    fn emit_block(&mut self, statements: &[Statement]) {
+        self.indentor.increase();
+        for statement in statements {
+            self.emit_statement(statement);
+        }
+        self.indentor.decrease();
+    }
+
+    /// Leaves the loop through `break_label` unless `comparison` holds.
+    fn emit_exit(&mut self, comparison: &Comparison, break_label: &str) {
+        self.emit_comparison(comparison);
+        self.line("i32.eqz");
+        self.line(&format!("br_if {}", break_label));
+    }
+
+    fn emit_print(&mut self, print_option: &PrintOption) {
+        match print_option {
+            PrintOption::PrintLiteral(s) => {
+                let (offset, length) = self.string(&unescape(s));
+                self.line(&format!("i32.const {}", offset));
+                self.line(&format!("i32.const {}", length));
+                self.line("call $print_str");
+            }
+            PrintOption::PrintExpression(e) => {
+                self.emit_expression(e);
+                self.line("call $print_i64");
+            }
+        }
+    }
+
+    /// Places `string` in the data segment, once for every distinct literal.
+    fn string(&mut self, string: &str) -> (usize, usize) {
+        let offset = match self.strings.iter().find(|(s, _)| s == string) {
+            Some((_, offset)) => *offset,
+            None => {
+                let offset = self.data.len();
+                self.data.extend_from_slice(string.as_bytes());
+                self.strings.push((string.to_string(), offset));
+                offset
+            }
+        };
+        (offset, string.len())
+    }
+
+    /// Pushes 1 if `comparison` holds and 0 otherwise, as an `i32`.
+    fn emit_comparison(&mut self, comparison: &Comparison) {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                self.emit_expression(lhs);
+                self.emit_links(rhs);
+            }
+            Comparison::Value(e) => {
+                self.emit_expression(e);
+                self.line("i64.const 0");
+                self.line("i64.ne");
+            }
+            Comparison::Not(c) => {
+                self.emit_comparison(c);
+                self.line("i32.eqz");
+            }
+            Comparison::And(lhs, rhs) => {
+                self.emit_comparison(lhs);
+                self.line("if (result i32)");
+                self.indentor.increase();
+                self.emit_comparison(rhs);
+                self.indentor.decrease();
+                self.line("else");
+                self.indentor.increase();
+                self.line("i32.const 0");
+                self.indentor.decrease();
+                self.line("end");
+            }
+            Comparison::Or(lhs, rhs) => {
+                self.emit_comparison(lhs);
+                self.line("if (result i32)");
+                self.indentor.increase();
+                self.line("i32.const 1");
+                self.indentor.decrease();
+                self.line("else");
+                self.indentor.increase();
+                self.emit_comparison(rhs);
+                self.indentor.decrease();
+                self.line("end");
+            }
+        }
+    }
+
+    /// Compares the value on the stack against the first link of a chain. The operand of every
+    /// link but the last is kept in a temporary, it is the left hand side of the next one.
+    fn emit_links(&mut self, links: &[(RelationalOp, Expression)]) {
+        let ((op, operand), rest) = links.split_first().expect("Expected a comparison");
+        self.emit_expression(operand);
+        if rest.is_empty() {
+            self.line(Self::instruction(op));
+            return;
+        }
+        let temporary = self.local(&format!("mmc_tmp_{}", operand.span().start));
+        self.line(&format!("local.tee {}", temporary));
+        self.line(Self::instruction(op));
+        self.line("if (result i32)");
+        self.indentor.increase();
+        self.line(&format!("local.get {}", temporary));
+        self.emit_links(rest);
+        self.indentor.decrease();
+        self.line("else");
+        self.indentor.increase();
+        self.line("i32.const 0");
+        self.indentor.decrease();
+        self.line("end");
+    }
+
+    fn instruction(op: &RelationalOp) -> &'static str {
+        match op {
+            RelationalOp::GreaterThan => "i64.gt_s",
+            RelationalOp::GreaterThanEquals => "i64.ge_s",
+            RelationalOp::LessThan => "i64.lt_s",
+            RelationalOp::LessThanEquals => "i64.le_s",
+            RelationalOp::EqualsEquals => "i64.eq",
+            RelationalOp::NotEquals => "i64.ne",
+        }
+    }
+
+    fn emit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                self.emit_expression(lhs);
+                self.emit_expression(rhs);
+                self.line(match op {
+                    BinaryOp::Add => "i64.add",
+                    BinaryOp::Subtract => "i64.sub",
+                    BinaryOp::Multiply => "i64.mul",
+                    BinaryOp::Divide => "i64.div_s",
+                    BinaryOp::Modulo => "i64.rem_s",
+                });
+            }
+            Expression::Unary { op, operand, .. } => match op {
+                UnaryOp::Plus => self.emit_primary(operand),
+                UnaryOp::Minus => {
+                    self.line("i64.const 0");
+                    self.emit_primary(operand);
+                    self.line("i64.sub");
+                }
+            },
+            Expression::Primary(p) => self.emit_primary(p),
+        }
+    }
+
+    fn emit_primary(&mut self, primary: &Primary) {
+        match primary {
+            Primary::LiteralNumber(n, _) => self.line(&format!("i64.const {}", n)),
+            Primary::IdentifierExpression(id) => {
+                let variable = self.variable(id);
+                self.line(&format!("local.get {}", variable));
+            }
+            Primary::Grouped(e, _) => self.emit_expression(e),
+        }
+    }
+
+    /// Escapes bytes for a WAT string, anything but printable ASCII is written as `\hh`.
+    fn escape(bytes: &[u8]) -> String {
+        let mut escaped = String::new();
+        for &byte in bytes {
+            if (b' '..=b'~').contains(&byte) && byte != b'"' && byte != b'\\' {
+                escaped.push(byte as char);
+            } else {
+                escaped.push_str(&format!("\\{:02x}", byte));
+            }
+        }
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, interpret, EDGE_CASES};
+    use std::collections::HashMap;
+
+    fn emit_source(source: &str) -> String {
+        let statements = analyze(source);
+        WatEmitter::new(&statements).emit()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+for let i = 0; 0 <= i < n; i = i + 1 {
+    if i == 2 || !n {
+        break;
+    }
+    print -i * 3 % 2;
+}
+print \"100%\\n\";";
+        let expected_code = "\
+(module
+\t(import \"env\" \"print_i64\" (func $print_i64 (param i64)))
+\t(import \"env\" \"print_str\" (func $print_str (param i32 i32)))
+\t(import \"env\" \"input_i64\" (func $input_i64 (result i64)))
+\t(memory (export \"memory\") 1)
+\t(data (i32.const 0) \"100%\\0a\")
+\t(func (export \"main\")
+\t\t(local $n i64)
+\t\t(local $i i64)
+\t\t(local $mmc_tmp_29 i64)
+\t\tcall $input_i64
+\t\tlocal.set $n
+\t\ti64.const 0
+\t\tlocal.set $i
+\t\tblock $break1
+\t\t\tloop $loop2
+\t\t\t\ti64.const 0
+\t\t\t\tlocal.get $i
+\t\t\t\tlocal.tee $mmc_tmp_29
+\t\t\t\ti64.le_s
+\t\t\t\tif (result i32)
+\t\t\t\t\tlocal.get $mmc_tmp_29
+\t\t\t\t\tlocal.get $n
+\t\t\t\t\ti64.lt_s
+\t\t\t\telse
+\t\t\t\t\ti32.const 0
+\t\t\t\tend
+\t\t\t\ti32.eqz
+\t\t\t\tbr_if $break1
+\t\t\t\tblock $continue3
+\t\t\t\t\tlocal.get $i
+\t\t\t\t\ti64.const 2
+\t\t\t\t\ti64.eq
+\t\t\t\t\tif (result i32)
+\t\t\t\t\t\ti32.const 1
+\t\t\t\t\telse
+\t\t\t\t\t\tlocal.get $n
+\t\t\t\t\t\ti64.const 0
+\t\t\t\t\t\ti64.ne
+\t\t\t\t\t\ti32.eqz
+\t\t\t\t\tend
+\t\t\t\t\tif
+\t\t\t\t\t\tbr $break1
+\t\t\t\t\tend
+\t\t\t\t\ti64.const 0
+\t\t\t\t\tlocal.get $i
+\t\t\t\t\ti64.sub
+\t\t\t\t\ti64.const 3
+\t\t\t\t\ti64.mul
+\t\t\t\t\ti64.const 2
+\t\t\t\t\ti64.rem_s
+\t\t\t\t\tcall $print_i64
+\t\t\t\tend
+\t\t\t\tlocal.get $i
+\t\t\t\ti64.const 1
+\t\t\t\ti64.add
+\t\t\t\tlocal.set $i
+\t\t\t\tbr $loop2
+\t\t\tend
+\t\tend
+\t\ti32.const 0
+\t\ti32.const 5
+\t\tcall $print_str
+\t)
+)
+";
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_data_segment() {
+        let source = "\
+print \"a\\tb\\n\";
+print \"\\\\ok\";
+print \"a\\tb\\n\";";
+        let code = emit_source(source);
+        assert!(code.contains("\t(data (i32.const 0) \"a\\09b\\0a\\5cok\")\n"));
+        assert!(code.contains("\t\ti32.const 4\n\t\ti32.const 3\n\t\tcall $print_str\n"));
+        assert_eq!(code.matches("i32.const 0\n\t\ti32.const 4\n").count(), 2);
+    }
+
+    /// Runs `main` of a module in the subset of WebAssembly the emitter produces, one
+    /// instruction per line, with imports that behave the way the host's have to.
+    fn evaluate(module: &str, input: &str) -> String {
+        let mut memory = Vec::new();
+        let mut locals = HashMap::new();
+        let mut body = Vec::new();
+        for line in module.lines().map(str::trim) {
+            if let Some(data) = line.strip_prefix("(data (i32.const 0) \"") {
+                let mut bytes = data.strip_suffix("\")").unwrap().bytes();
+                while let Some(byte) = bytes.next() {
+                    if byte == b'\\' {
+                        let hex = [bytes.next().unwrap(), bytes.next().unwrap()];
+                        let hex = std::str::from_utf8(&hex).unwrap();
+                        memory.push(u8::from_str_radix(hex, 16).unwrap());
+                    } else {
+                        memory.push(byte);
+                    }
+                }
+            } else if let Some(local) = line.strip_prefix("(local ") {
+                locals.insert(local.split(' ').next().unwrap(), 0);
+            } else if !line.starts_with('(') && line != ")" {
+                body.push(line);
+            }
+        }
+        // The `end` of every `block`, `loop` and `if` and the `else` of every `if`, by the line
+        // that opens them.
+        let mut ends = HashMap::new();
+        let mut elses = HashMap::new();
+        let mut open = Vec::new();
+        for (index, line) in body.iter().enumerate() {
+            if line.starts_with("block") || line.starts_with("loop") || line.starts_with("if") {
+                open.push(index);
+            } else if *line == "else" {
+                elses.insert(*open.last().unwrap(), index);
+            } else if *line == "end" {
+                ends.insert(open.pop().unwrap(), index);
+            }
+        }
+
+        let mut words = input.split_whitespace();
+        let mut output = Vec::new();
+        let mut stack: Vec<i64> = Vec::new();
+        let mut blocks: Vec<usize> = Vec::new();
+        let mut pc = 0;
+        while pc < body.len() {
+            let line = body[pc];
+            let (op, arg) = line.split_once(' ').unwrap_or((line, ""));
+            pc += 1;
+            match op {
+                "block" | "loop" => blocks.push(pc - 1),
+                "if" => {
+                    if stack.pop().unwrap() != 0 {
+                        blocks.push(pc - 1);
+                    } else if let Some(&else_index) = elses.get(&(pc - 1)) {
+                        blocks.push(pc - 1);
+                        pc = else_index + 1;
+                    } else {
+                        pc = ends[&(pc - 1)] + 1;
+                    }
+                }
+                "else" => pc = ends[&blocks.pop().unwrap()] + 1,
+                "end" => {
+                    blocks.pop();
+                }
+                "br" | "br_if" => {
+                    if op == "br_if" && stack.pop().unwrap() == 0 {
+                        continue;
+                    }
+                    let depth = blocks
+                        .iter()
+                        .rposition(|&start| body[start].split(' ').nth(1) == Some(arg))
+                        .unwrap();
+                    let start = blocks[depth];
+                    if body[start].starts_with("loop") {
+                        blocks.truncate(depth + 1);
+                        pc = start + 1;
+                    } else {
+                        blocks.truncate(depth);
+                        pc = ends[&start] + 1;
+                    }
+                }
+                "i64.const" | "i32.const" => stack.push(arg.parse().unwrap()),
+                "local.get" => stack.push(locals[arg]),
+                "local.set" => {
+                    locals.insert(arg, stack.pop().unwrap());
+                }
+                "local.tee" => {
+                    locals.insert(arg, *stack.last().unwrap());
+                }
+                "i32.eqz" => {
+                    let value = stack.pop().unwrap();
+                    stack.push((value == 0) as i64);
+                }
+                "call" => match arg {
+                    "$print_i64" => output.extend(format!("{}\n", stack.pop().unwrap()).bytes()),
+                    "$print_str" => {
+                        let length = stack.pop().unwrap() as usize;
+                        let offset = stack.pop().unwrap() as usize;
+                        output.extend(&memory[offset..offset + length]);
+                    }
+                    "$input_i64" => {
+                        stack.push(words.next().map_or(0, |word| word.parse().unwrap_or(0)))
+                    }
+                    _ => panic!("Unknown function {}", arg),
+                },
+                _ => {
+                    let rhs = stack.pop().unwrap();
+                    let lhs = stack.pop().unwrap();
+                    stack.push(match op {
+                        "i64.add" => lhs.wrapping_add(rhs),
+                        "i64.sub" => lhs.wrapping_sub(rhs),
+                        "i64.mul" => lhs.wrapping_mul(rhs),
+                        "i64.div_s" => lhs.checked_div(rhs).expect("Expected no trap"),
+                        "i64.rem_s" => lhs.checked_rem(rhs).expect("Expected no trap"),
+                        "i64.eq" => (lhs == rhs) as i64,
+                        "i64.ne" => (lhs != rhs) as i64,
+                        "i64.lt_s" => (lhs < rhs) as i64,
+                        "i64.gt_s" => (lhs > rhs) as i64,
+                        "i64.le_s" => (lhs <= rhs) as i64,
+                        "i64.ge_s" => (lhs >= rhs) as i64,
+                        _ => panic!("Unknown instruction {}", line),
+                    });
+                }
+            }
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_edge_cases_run_like_interpreter() {
+        for (source, input) in EDGE_CASES {
+            let output = evaluate(&emit_source(source), input);
+            assert_eq!(output, interpret(source, input), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+
+use super::common::{escape_gas_string, JumpLowering, LoopStack, FORMATS};
+use crate::lexing::lexer::unescape;
+use crate::parsing::parser::{
+    BinaryOp, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement, UnaryOp,
+};
+
+/// Emits x86-64 assembly in GNU as syntax for the System V ABI, to be linked against libc with
+/// `cc file.s`. Every variable lives in a stack slot below `%rbp`, expressions are evaluated into
+/// `%rax` with intermediate values pushed on the stack.
+pub struct X86Emitter {
+    statements: Vec<Statement>,
+    code: String,
+    /// Stack slot of every variable, as an offset from `%rbp`.
+    slots: HashMap<String, i64>,
+    strings: Vec<String>,
+    labels: usize,
+    loops: LoopStack<String>,
+}
+
+impl X86Emitter {
+    pub fn new(program: &[Statement]) -> Self {
+        X86Emitter {
+            statements: program.to_vec(),
+            code: String::new(),
+            slots: HashMap::new(),
+            strings: Vec::new(),
+            labels: 0,
+            loops: LoopStack::default(),
+        }
+    }
+
+    pub fn emit(mut self) -> String {
+        for statement in &self.statements.clone() {
+            self.emit_statement(statement);
+        }
+        // Keep the stack 16 byte aligned for the calls into libc.
+        let frame_size = (self.slots.len() as i64 * 8 + 15) / 16 * 16;
+
+        let mut emit = String::new();
+        emit.push_str("\t.section .rodata\n");
+        for (name, format) in FORMATS {
+            emit.push_str(&format!(
+                ".Lfmt_{}:\n\t.string \"{}\"\n",
+                name,
+                escape_gas_string(format)
+            ));
+        }
+        for (index, string) in self.strings.iter().enumerate() {
+            emit.push_str(&format!(
+                ".Lstr{}:\n\t.string \"{}\"\n",
+                index,
+                escape_gas_string(string)
+            ));
+        }
+        emit.push_str("\t.text\n");
+        emit.push_str("\t.globl main\n");
+        emit.push_str("\t.type main, @function\n");
+        emit.push_str("main:\n");
+        emit.push_str("\tpushq %rbp\n");
+        emit.push_str("\tmovq %rsp, %rbp\n");
+        if frame_size > 0 {
+            emit.push_str(&format!("\tsubq ${}, %rsp\n", frame_size));
+        }
+        emit.push_str(&self.code);
+        emit.push_str("\tmovl $0, %eax\n");
+        emit.push_str("\tleave\n");
+        emit.push_str("\tret\n");
+        emit.push_str("\t.section .note.GNU-stack,\"\",@progbits\n");
+        emit
+    }
+
+    fn instruction(&mut self, instruction: &str) {
+        self.code.push('\t');
+        self.code.push_str(instruction);
+        self.code.push('\n');
+    }
+
+    /// Declarations have unique names after semantic analysis, so a name is all a slot needs.
+    fn slot(&mut self, name: &str) -> String {
+        let next = -8 * (self.slots.len() as i64 + 1);
+        let offset = *self.slots.entry(name.to_string()).or_insert(next);
+        format!("{}(%rbp)", offset)
+    }
+
+    fn variable(&mut self, identifier: &Identifier) -> String {
+        self.slot(identifier.name())
+    }
+
+    fn emit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print { option, .. } => self.emit_print(option),
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                let else_label = self.new_label();
+                self.emit_jump(comparison, false, &else_label);
+                self.emit_block(statements);
+                match else_statements {
+                    Some(else_statements) => {
+                        let end_label = self.new_label();
+                        self.instruction(&format!("jmp {}", end_label));
+                        self.place_label(&else_label);
+                        self.emit_block(else_statements);
+                        self.place_label(&end_label);
+                    }
+                    None => self.place_label(&else_label),
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                let start_label = self.new_label();
+                let end_label = self.new_label();
+                self.place_label(&start_label);
+                self.emit_jump(comparison, false, &end_label);
+                self.emit_loop_body(statements, &start_label, &end_label);
+                self.instruction(&format!("jmp {}", start_label));
+                self.place_label(&end_label);
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                let start_label = self.new_label();
+                let step_label = self.new_label();
+                let end_label = self.new_label();
+                self.emit_statement(init);
+                self.place_label(&start_label);
+                self.emit_jump(comparison, false, &end_label);
+                self.emit_loop_body(statements, &step_label, &end_label);
+                self.place_label(&step_label);
+                self.emit_statement(step);
+                self.instruction(&format!("jmp {}", start_label));
+                self.place_label(&end_label);
+            }
+            Statement::Break { .. } => {
+                let break_label = self.loops.break_target();
+                self.emit_goto(&break_label);
+            }
+            Statement::Continue { .. } => {
+                let continue_label = self.loops.continue_target();
+                self.emit_goto(&continue_label);
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                self.emit_expression(expression);
+                let variable = self.variable(identifier);
+                self.instruction(&format!("movq %rax, {}", variable));
+            }
+            Statement::Input { identifier, .. } => self.emit_input(identifier),
+        }
+    }
+
+    fn emit_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.emit_statement(statement);
+        }
+    }
+
+    fn emit_loop_body(&mut self, statements: &[Statement], continue_label: &str, end_label: &str) {
+        self.loops
+            .push(continue_label.to_string(), end_label.to_string());
+        self.emit_block(statements);
+        self.loops.pop();
+    }
+
+    fn emit_print(&mut self, print_option: &PrintOption) {
+        match print_option {
+            PrintOption::PrintLiteral(s) => {
+                self.strings.push(unescape(s));
+                let string = format!(".Lstr{}(%rip)", self.strings.len() - 1);
+                self.instruction(&format!("leaq {}, %rsi", string));
+                self.instruction("leaq .Lfmt_string(%rip), %rdi");
+            }
+            PrintOption::PrintExpression(e) => {
+                self.emit_expression(e);
+                self.instruction("movq %rax, %rsi");
+                self.instruction("leaq .Lfmt_number(%rip), %rdi");
+            }
+        }
+        self.instruction("movl $0, %eax");
+        self.instruction("call printf@PLT");
+    }
+
+    fn emit_input(&mut self, identifier: &Identifier) {
+        let variable = self.variable(identifier);
+        let done_label = self.new_label();
+        self.instruction(&format!("leaq {}, %rsi", variable));
+        self.instruction("leaq .Lfmt_input(%rip), %rdi");
+        self.instruction("movl $0, %eax");
+        self.instruction("call scanf@PLT");
+        self.instruction("cmpl $1, %eax");
+        self.instruction(&format!("je {}", done_label));
+        self.instruction(&format!("movq $0, {}", variable));
+        self.instruction("leaq .Lfmt_skip(%rip), %rdi");
+        self.instruction("movl $0, %eax");
+        self.instruction("call scanf@PLT");
+        self.place_label(&done_label);
+    }
+
+    /// The conditional jump taken when `lhs op rhs` does not hold after `cmpq rhs, lhs`.
+    fn inverse_jump(op: &RelationalOp) -> &'static str {
+        match op {
+            RelationalOp::GreaterThan => "jle",
+            RelationalOp::GreaterThanEquals => "jl",
+            RelationalOp::LessThan => "jge",
+            RelationalOp::LessThanEquals => "jg",
+            RelationalOp::EqualsEquals => "jne",
+            RelationalOp::NotEquals => "je",
+        }
+    }
+
+    /// Leaves the value of `expression` in `%rax`, clobbering `%rcx` and `%rdx`.
+    fn emit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                self.emit_expression(lhs);
+                self.instruction("pushq %rax");
+                self.emit_expression(rhs);
+                self.instruction("movq %rax, %rcx");
+                self.instruction("popq %rax");
+                match op {
+                    BinaryOp::Add => self.instruction("addq %rcx, %rax"),
+                    BinaryOp::Subtract => self.instruction("subq %rcx, %rax"),
+                    BinaryOp::Multiply => self.instruction("imulq %rcx, %rax"),
+                    BinaryOp::Divide | BinaryOp::Modulo => {
+                        self.instruction("cqto");
+                        self.instruction("idivq %rcx");
+                        if *op == BinaryOp::Modulo {
+                            self.instruction("movq %rdx, %rax");
+                        }
+                    }
+                }
+            }
+            Expression::Unary { op, operand, .. } => {
+                self.emit_primary(operand);
+                if *op == UnaryOp::Minus {
+                    self.instruction("negq %rax");
+                }
+            }
+            Expression::Primary(p) => self.emit_primary(p),
+        }
+    }
+
+    fn emit_primary(&mut self, primary: &Primary) {
+        match primary {
+            Primary::LiteralNumber(n, _) => {
+                if i32::try_from(*n).is_ok() {
+                    self.instruction(&format!("movq ${}, %rax", n));
+                } else {
+                    self.instruction(&format!("movabsq ${}, %rax", n));
+                }
+            }
+            Primary::IdentifierExpression(id) => {
+                let variable = self.variable(id);
+                self.instruction(&format!("movq {}, %rax", variable));
+            }
+            Primary::Grouped(e, _) => self.emit_expression(e),
+        }
+    }
+}
+
+impl JumpLowering for X86Emitter {
+    type Label = String;
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    fn place_label(&mut self, label: &String) {
+        self.code.push_str(label);
+        self.code.push_str(":\n");
+    }
+
+    fn emit_goto(&mut self, target: &String) {
+        self.instruction(&format!("jmp {}", target));
+    }
+
+    fn emit_chain_failure(
+        &mut self,
+        lhs: &Expression,
+        rhs: &[(RelationalOp, Expression)],
+        target: &String,
+    ) {
+        self.emit_expression(lhs);
+        for (i, (op, operand)) in rhs.iter().enumerate() {
+            self.instruction("pushq %rax");
+            self.emit_expression(operand);
+            self.instruction("movq %rax, %rcx");
+            self.instruction("popq %rax");
+            self.instruction("cmpq %rcx, %rax");
+            self.instruction(&format!("{} {}", Self::inverse_jump(op), target));
+            if i + 1 < rhs.len() {
+                self.instruction("movq %rcx, %rax");
+            }
+        }
+    }
+
+    fn emit_value_jump(&mut self, expression: &Expression, when: bool, target: &String) {
+        self.emit_expression(expression);
+        self.instruction("testq %rax, %rax");
+        let jump = if when { "jne" } else { "je" };
+        self.instruction(&format!("{} {}", jump, target));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{analyze, between};
+
+    fn emit_source(source: &str) -> String {
+        let statements = analyze(source);
+        X86Emitter::new(&statements).emit()
+    }
+
+    /// The instructions of `main` between the prologue and the epilogue, for programs with a
+    /// single variable.
+    fn emit_body(source: &str) -> String {
+        let code = emit_source(source);
+        between(&code, "\tsubq $16, %rsp\n", "\tmovl $0, %eax\n\tleave").to_string()
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let source = "\
+input n;
+while 0 < n {
+    n = n - 1;
+}
+print \"done\\n\";";
+        let expected_code = "\
+\t.section .rodata
+.Lfmt_number:
+\t.string \"%lld\\n\"
+.Lfmt_string:
+\t.string \"%s\"
+.Lfmt_input:
+\t.string \"%lld\"
+.Lfmt_skip:
+\t.string \"%*s\"
+.Lstr0:
+\t.string \"done\\n\"
+\t.text
+\t.globl main
+\t.type main, @function
+main:
+\tpushq %rbp
+\tmovq %rsp, %rbp
+\tsubq $16, %rsp
+\tleaq -8(%rbp), %rsi
+\tleaq .Lfmt_input(%rip), %rdi
+\tmovl $0, %eax
+\tcall scanf@PLT
+\tcmpl $1, %eax
+\tje .L1
+\tmovq $0, -8(%rbp)
+\tleaq .Lfmt_skip(%rip), %rdi
+\tmovl $0, %eax
+\tcall scanf@PLT
+.L1:
+.L2:
+\tmovq $0, %rax
+\tpushq %rax
+\tmovq -8(%rbp), %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\tcmpq %rcx, %rax
+\tjge .L3
+\tmovq -8(%rbp), %rax
+\tpushq %rax
+\tmovq $1, %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\tsubq %rcx, %rax
+\tmovq %rax, -8(%rbp)
+\tjmp .L2
+.L3:
+\tleaq .Lstr0(%rip), %rsi
+\tleaq .Lfmt_string(%rip), %rdi
+\tmovl $0, %eax
+\tcall printf@PLT
+\tmovl $0, %eax
+\tleave
+\tret
+\t.section .note.GNU-stack,\"\",@progbits
+";
+        assert_eq!(emit_source(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_for_with_break_and_continue() {
+        let source = "\
+for let i = 0; i < 3; i = i + 1 {
+    if i == 1 {
+        continue;
+    } else {
+        break;
+    }
+}";
+        let expected_code = "\
+\tmovq $0, %rax
+\tmovq %rax, -8(%rbp)
+.L1:
+\tmovq -8(%rbp), %rax
+\tpushq %rax
+\tmovq $3, %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\tcmpq %rcx, %rax
+\tjge .L3
+\tmovq -8(%rbp), %rax
+\tpushq %rax
+\tmovq $1, %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\tcmpq %rcx, %rax
+\tjne .L4
+\tjmp .L2
+\tjmp .L5
+.L4:
+\tjmp .L3
+.L5:
+.L2:
+\tmovq -8(%rbp), %rax
+\tpushq %rax
+\tmovq $1, %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\taddq %rcx, %rax
+\tmovq %rax, -8(%rbp)
+\tjmp .L1
+.L3:
+";
+        assert_eq!(emit_body(source), expected_code);
+    }
+
+    #[test]
+    fn test_emit_chained_comparison_and_logical_operators() {
+        // The middle operand is evaluated once and carried over in %rcx.
+        let source = "\
+let i = 1;
+if 0 <= i * 2 < 10 || !i {
+    print i % 3;
+}";
+        let expected_code = "\
+\tmovq $1, %rax
+\tmovq %rax, -8(%rbp)
+\tmovq $0, %rax
+\tpushq %rax
+\tmovq -8(%rbp), %rax
+\tpushq %rax
+\tmovq $2, %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\timulq %rcx, %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\tcmpq %rcx, %rax
+\tjg .L3
+\tmovq %rcx, %rax
+\tpushq %rax
+\tmovq $10, %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\tcmpq %rcx, %rax
+\tjge .L3
+\tjmp .L2
+.L3:
+\tmovq -8(%rbp), %rax
+\ttestq %rax, %rax
+\tjne .L1
+.L2:
+\tmovq -8(%rbp), %rax
+\tpushq %rax
+\tmovq $3, %rax
+\tmovq %rax, %rcx
+\tpopq %rax
+\tcqto
+\tidivq %rcx
+\tmovq %rdx, %rax
+\tmovq %rax, %rsi
+\tleaq .Lfmt_number(%rip), %rdi
+\tmovl $0, %eax
+\tcall printf@PLT
+.L1:
+";
+        assert_eq!(emit_body(source), expected_code);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::lexing::lexer::Span;
+
+/// Instructions of the stack machine. Operands index into the constant pool, the string table
+/// or the locals of a `Chunk`, jump targets are absolute instruction indices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    /// Pushes `constants[index]`.
+    Push(u32),
+    Load(u32),
+    /// Pops the top of the stack into a local.
+    Store(u32),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Negate,
+    /// Comparisons pop two values and push 1 if the comparison holds, 0 otherwise.
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    /// Pushes 1 for 0 and 0 for anything else.
+    Not,
+    Jump(u32),
+    /// Pops the condition and jumps if it is 0.
+    JumpIfFalse(u32),
+    /// Pops a value and prints it on a line of its own.
+    Print,
+    PrintString(u32),
+    /// Reads an integer the way the C backend does and pushes it.
+    Input,
+}
+
+impl Instruction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Push(_) => "PUSH",
+            Instruction::Load(_) => "LOAD",
+            Instruction::Store(_) => "STORE",
+            Instruction::Add => "ADD",
+            Instruction::Subtract => "SUB",
+            Instruction::Multiply => "MUL",
+            Instruction::Divide => "DIV",
+            Instruction::Modulo => "MOD",
+            Instruction::Negate => "NEG",
+            Instruction::Equal => "EQ",
+            Instruction::NotEqual => "NE",
+            Instruction::Greater => "GT",
+            Instruction::GreaterEqual => "GE",
+            Instruction::Less => "LT",
+            Instruction::LessEqual => "LE",
+            Instruction::Not => "NOT",
+            Instruction::Jump(_) => "JUMP",
+            Instruction::JumpIfFalse(_) => "JUMP_IF_FALSE",
+            Instruction::Print => "PRINT",
+            Instruction::PrintString(_) => "PRINT_STRING",
+            Instruction::Input => "INPUT",
+        }
+    }
+}
+
+/// A compiled program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    /// Source location of every instruction, used for runtime errors and disassembly.
+    pub spans: Vec<Span>,
+    pub constants: Vec<i64>,
+    /// String literals with their escapes already resolved.
+    pub strings: Vec<String>,
+    /// Names of the locals, one slot per declaration plus the compiler's temporaries.
+    pub locals: Vec<String>,
+}
+
+impl Chunk {
+    pub fn push(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.code.push(instruction);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+}
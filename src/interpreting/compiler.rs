@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+
+use crate::analyzing::symbol_table::SymbolId;
+use crate::lexing::lexer::{unescape, Span};
+use crate::parsing::parser::{
+    BinaryOp, Comparison, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement,
+    UnaryOp,
+};
+
+use super::bytecode::{Chunk, Instruction};
+
+/// Jumps out of the loop being compiled, patched once their targets are known.
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// Compiles a program that went through semantic analysis to bytecode for the `Vm`.
+/// Conditions leave 1 or 0 on the stack, `&&`, `||` and chains short-circuit with jumps.
+pub struct Compiler {
+    chunk: Chunk,
+    slots: HashMap<SymbolId, u32>,
+    loops: Vec<Loop>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            chunk: Chunk::default(),
+            slots: HashMap::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    pub fn compile(mut self, statements: &[Statement]) -> Chunk {
+        for statement in statements {
+            self.compile_statement(statement);
+        }
+        self.chunk
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) {
+        let span = statement.span();
+        match statement {
+            Statement::Print { option, .. } => match option {
+                PrintOption::PrintLiteral(s) => {
+                    let index = self.string(unescape(s));
+                    self.chunk.push(Instruction::PrintString(index), span);
+                }
+                PrintOption::PrintExpression(e) => {
+                    self.compile_expression(e);
+                    self.chunk.push(Instruction::Print, span);
+                }
+            },
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                self.compile_comparison(comparison, span);
+                let to_else = self.chunk.push(Instruction::JumpIfFalse(0), span);
+                self.compile_block(statements);
+                match else_statements {
+                    Some(else_statements) => {
+                        let to_end = self.chunk.push(Instruction::Jump(0), span);
+                        self.patch(to_else);
+                        self.compile_block(else_statements);
+                        self.patch(to_end);
+                    }
+                    None => self.patch(to_else),
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                let start = self.chunk.code.len();
+                self.compile_comparison(comparison, span);
+                let to_end = self.chunk.push(Instruction::JumpIfFalse(0), span);
+                self.loops.push(Loop::default());
+                self.compile_block(statements);
+                self.chunk.push(Instruction::Jump(index(start)), span);
+                self.patch(to_end);
+                let labels = self.loops.pop().expect("Expected loop");
+                self.patch_all(&labels.breaks, self.chunk.code.len());
+                self.patch_all(&labels.continues, start);
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                self.compile_statement(init);
+                let start = self.chunk.code.len();
+                self.compile_comparison(comparison, span);
+                let to_end = self.chunk.push(Instruction::JumpIfFalse(0), span);
+                self.loops.push(Loop::default());
+                self.compile_block(statements);
+                let step_start = self.chunk.code.len();
+                self.compile_statement(step);
+                self.chunk.push(Instruction::Jump(index(start)), span);
+                self.patch(to_end);
+                let labels = self.loops.pop().expect("Expected loop");
+                self.patch_all(&labels.breaks, self.chunk.code.len());
+                self.patch_all(&labels.continues, step_start);
+            }
+            Statement::Break { .. } => {
+                let jump = self.chunk.push(Instruction::Jump(0), span);
+                self.current_loop().breaks.push(jump);
+            }
+            Statement::Continue { .. } => {
+                let jump = self.chunk.push(Instruction::Jump(0), span);
+                self.current_loop().continues.push(jump);
+            }
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                self.compile_expression(expression);
+                let slot = self.slot(identifier);
+                self.chunk.push(Instruction::Store(slot), span);
+            }
+            Statement::Input { identifier, .. } => {
+                self.chunk.push(Instruction::Input, span);
+                let slot = self.slot(identifier);
+                self.chunk.push(Instruction::Store(slot), span);
+            }
+        }
+    }
+
+    fn compile_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.compile_statement(statement);
+        }
+    }
+
+    /// `span` is the span of the statement the condition belongs to.
+    fn compile_comparison(&mut self, comparison: &Comparison, span: Span) {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                self.compile_expression(lhs);
+                let mut to_false = Vec::new();
+                for (position, (op, operand)) in rhs.iter().enumerate() {
+                    self.compile_expression(operand);
+                    if position + 1 == rhs.len() {
+                        self.chunk.push(relational_instruction(*op), span);
+                        break;
+                    }
+                    // The operand is the left hand side of the next link as well.
+                    let temporary = self.temporary(operand);
+                    self.chunk.push(Instruction::Store(temporary), span);
+                    self.chunk.push(Instruction::Load(temporary), span);
+                    self.chunk.push(relational_instruction(*op), span);
+                    to_false.push(self.chunk.push(Instruction::JumpIfFalse(0), span));
+                    self.chunk.push(Instruction::Load(temporary), span);
+                }
+                if !to_false.is_empty() {
+                    let to_end = self.chunk.push(Instruction::Jump(0), span);
+                    self.patch_all(&to_false, self.chunk.code.len());
+                    let zero = self.constant(0);
+                    self.chunk.push(Instruction::Push(zero), span);
+                    self.patch(to_end);
+                }
+            }
+            Comparison::Value(expression) => {
+                self.compile_expression(expression);
+                let zero = self.constant(0);
+                self.chunk.push(Instruction::Push(zero), span);
+                self.chunk.push(Instruction::NotEqual, span);
+            }
+            Comparison::Not(comparison) => {
+                self.compile_comparison(comparison, span);
+                self.chunk.push(Instruction::Not, span);
+            }
+            Comparison::And(lhs, rhs) => {
+                self.compile_comparison(lhs, span);
+                let to_false = self.chunk.push(Instruction::JumpIfFalse(0), span);
+                self.compile_comparison(rhs, span);
+                let to_end = self.chunk.push(Instruction::Jump(0), span);
+                self.patch(to_false);
+                let zero = self.constant(0);
+                self.chunk.push(Instruction::Push(zero), span);
+                self.patch(to_end);
+            }
+            Comparison::Or(lhs, rhs) => {
+                self.compile_comparison(lhs, span);
+                let to_rhs = self.chunk.push(Instruction::JumpIfFalse(0), span);
+                let one = self.constant(1);
+                self.chunk.push(Instruction::Push(one), span);
+                let to_end = self.chunk.push(Instruction::Jump(0), span);
+                self.patch(to_rhs);
+                self.compile_comparison(rhs, span);
+                self.patch(to_end);
+            }
+        }
+    }
+
+    fn compile_expression(&mut self, expression: &Expression) {
+        let span = expression.span();
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                self.compile_expression(lhs);
+                self.compile_expression(rhs);
+                let instruction = match op {
+                    BinaryOp::Add => Instruction::Add,
+                    BinaryOp::Subtract => Instruction::Subtract,
+                    BinaryOp::Multiply => Instruction::Multiply,
+                    BinaryOp::Divide => Instruction::Divide,
+                    BinaryOp::Modulo => Instruction::Modulo,
+                };
+                self.chunk.push(instruction, span);
+            }
+            Expression::Unary { op, operand, .. } => {
+                self.compile_primary(operand);
+                if *op == UnaryOp::Minus {
+                    self.chunk.push(Instruction::Negate, span);
+                }
+            }
+            Expression::Primary(primary) => self.compile_primary(primary),
+        }
+    }
+
+    fn compile_primary(&mut self, primary: &Primary) {
+        let span = primary.span();
+        match primary {
+            Primary::LiteralNumber(value, _) => {
+                let index = self.constant(*value);
+                self.chunk.push(Instruction::Push(index), span);
+            }
+            Primary::IdentifierExpression(identifier) => {
+                let slot = self.slot(identifier);
+                self.chunk.push(Instruction::Load(slot), span);
+            }
+            Primary::Grouped(expression, _) => self.compile_expression(expression),
+        }
+    }
+
+    fn current_loop(&mut self) -> &mut Loop {
+        self.loops
+            .last_mut()
+            .expect("Expected break and continue to be inside a loop")
+    }
+
+    fn patch(&mut self, jump: usize) {
+        let here = self.chunk.code.len();
+        self.patch_all(&[jump], here);
+    }
+
+    fn patch_all(&mut self, jumps: &[usize], destination: usize) {
+        for &jump in jumps {
+            self.chunk.code[jump] = match self.chunk.code[jump] {
+                Instruction::Jump(_) => Instruction::Jump(index(destination)),
+                Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(index(destination)),
+                instruction => panic!("Expected a jump to patch, got {:?}", instruction),
+            };
+        }
+    }
+
+    fn slot(&mut self, identifier: &Identifier) -> u32 {
+        let id = identifier
+            .symbol
+            .as_ref()
+            .expect("Expected identifier to be resolved by semantic analysis")
+            .id;
+        if let Some(slot) = self.slots.get(&id) {
+            return *slot;
+        }
+        let slot = self.local(identifier.name().to_string());
+        self.slots.insert(id, slot);
+        slot
+    }
+
+    /// Source identifiers never contain `_`, so these can not clash with user variables.
+    fn temporary(&mut self, expression: &Expression) -> u32 {
+        self.local(format!("mmc_tmp_{}", expression.span().start))
+    }
+
+    fn local(&mut self, name: String) -> u32 {
+        self.chunk.locals.push(name);
+        index(self.chunk.locals.len() - 1)
+    }
+
+    fn constant(&mut self, value: i64) -> u32 {
+        match self.chunk.constants.iter().position(|c| *c == value) {
+            Some(position) => index(position),
+            None => {
+                self.chunk.constants.push(value);
+                index(self.chunk.constants.len() - 1)
+            }
+        }
+    }
+
+    fn string(&mut self, value: String) -> u32 {
+        match self.chunk.strings.iter().position(|s| *s == value) {
+            Some(position) => index(position),
+            None => {
+                self.chunk.strings.push(value);
+                index(self.chunk.strings.len() - 1)
+            }
+        }
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn relational_instruction(op: RelationalOp) -> Instruction {
+    match op {
+        RelationalOp::GreaterThan => Instruction::Greater,
+        RelationalOp::GreaterThanEquals => Instruction::GreaterEqual,
+        RelationalOp::LessThan => Instruction::Less,
+        RelationalOp::LessThanEquals => Instruction::LessEqual,
+        RelationalOp::EqualsEquals => Instruction::Equal,
+        RelationalOp::NotEquals => Instruction::NotEqual,
+    }
+}
+
+fn index(position: usize) -> u32 {
+    u32::try_from(position).expect("Expected program to fit in 2^32 instructions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::analyze;
+
+    fn compile_source(source: &str) -> Chunk {
+        let statements = analyze(source);
+        Compiler::new().compile(&statements)
+    }
+
+    #[test]
+    fn test_compile_while_with_break() {
+        use Instruction::*;
+        let source = "let i = 0;\nwhile i < 3 {\n  if i == 1 {\n    break;\n  }\n  i = i + 1;\n}";
+        let chunk = compile_source(source);
+        assert_eq!(
+            chunk.code,
+            vec![
+                Push(0),
+                Store(0),
+                Load(0),
+                Push(1),
+                Less,
+                JumpIfFalse(16),
+                Load(0),
+                Push(2),
+                Equal,
+                JumpIfFalse(11),
+                Jump(16),
+                Load(0),
+                Push(2),
+                Add,
+                Store(0),
+                Jump(2),
+            ]
+        );
+        assert_eq!(chunk.constants, vec![0, 3, 1]);
+        assert_eq!(chunk.locals, vec!["i"]);
+        assert_eq!(chunk.spans.len(), chunk.code.len());
+    }
+
+    #[test]
+    fn test_compile_chained_comparison() {
+        use Instruction::*;
+        let chunk = compile_source("let i = 1;\nif 0 < i + 1 < 3 {\n  print i;\n}");
+        assert_eq!(
+            chunk.code[2..15],
+            [
+                Push(1),
+                Load(0),
+                Push(0),
+                Add,
+                Store(1),
+                Load(1),
+                Less,
+                JumpIfFalse(14),
+                Load(1),
+                Push(2),
+                Less,
+                Jump(15),
+                Push(1),
+            ]
+        );
+        assert_eq!(chunk.locals, vec!["i", "mmc_tmp_18"]);
+    }
+}
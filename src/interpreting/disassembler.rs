@@ -0,0 +1,91 @@
+use super::bytecode::{Chunk, Instruction};
+
+/// Renders `chunk` as text for debugging: the constant pool, string table and locals followed
+/// by one instruction per line with the source line it was compiled from. A `|` means the same
+/// line as the instruction before.
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut text = String::new();
+    text.push_str("== constants ==\n");
+    for (index, constant) in chunk.constants.iter().enumerate() {
+        text.push_str(&format!("{:04} {}\n", index, constant));
+    }
+    text.push_str("== strings ==\n");
+    for (index, string) in chunk.strings.iter().enumerate() {
+        text.push_str(&format!("{:04} {:?}\n", index, string));
+    }
+    text.push_str("== locals ==\n");
+    for (index, name) in chunk.locals.iter().enumerate() {
+        text.push_str(&format!("{:04} {}\n", index, name));
+    }
+    text.push_str("== code ==\n");
+    let mut previous_line = None;
+    for (offset, (instruction, span)) in chunk.code.iter().zip(&chunk.spans).enumerate() {
+        let line = if previous_line == Some(span.line) {
+            "|".to_string()
+        } else {
+            span.line.to_string()
+        };
+        previous_line = Some(span.line);
+        let operand = match instruction {
+            Instruction::Push(index) => {
+                format!("{} ({})", index, chunk.constants[*index as usize])
+            }
+            Instruction::Load(slot) | Instruction::Store(slot) => {
+                format!("{} ({})", slot, chunk.locals[*slot as usize])
+            }
+            Instruction::PrintString(index) => {
+                format!("{} ({:?})", index, chunk.strings[*index as usize])
+            }
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => {
+                format!("-> {:04}", target)
+            }
+            _ => String::new(),
+        };
+        let instruction = format!("{:<13} {}", instruction.name(), operand);
+        text.push_str(&format!(
+            "{:04} {:>4} {}\n",
+            offset,
+            line,
+            instruction.trim_end()
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreting::compiler::Compiler;
+    use crate::test_utils::analyze;
+
+    #[test]
+    fn test_disassemble() {
+        let source = "input n;\nwhile n > 0 {\n  print \"tick\\n\";\n  n = n - 1;\n}";
+        let statements = analyze(source);
+        let chunk = Compiler::new().compile(&statements);
+
+        let expected = "\
+== constants ==
+0000 0
+0001 1
+== strings ==
+0000 \"tick\\n\"
+== locals ==
+0000 n
+== code ==
+0000    1 INPUT
+0001    | STORE         0 (n)
+0002    2 LOAD          0 (n)
+0003    | PUSH          0 (0)
+0004    | GT
+0005    | JUMP_IF_FALSE -> 0012
+0006    3 PRINT_STRING  0 (\"tick\\n\")
+0007    4 LOAD          0 (n)
+0008    | PUSH          1 (1)
+0009    | SUB
+0010    | STORE         0 (n)
+0011    2 JUMP          -> 0002
+";
+        assert_eq!(disassemble(&chunk), expected);
+    }
+}
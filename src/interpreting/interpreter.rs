@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::analyzing::symbol_table::SymbolId;
+use crate::lexing::lexer::{unescape, Span};
+use crate::parsing::parser::{
+    BinaryOp, Comparison, Expression, Identifier, Primary, PrintOption, RelationalOp, Statement,
+    UnaryOp,
+};
+use crate::reporting::diagnostic::Diagnostic;
+
+pub type RuntimeResult<T> = std::result::Result<T, RuntimeError>;
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub reason: String,
+    pub help: Option<String>,
+    pub span: Span,
+}
+
+impl RuntimeError {
+    pub fn division_by_zero(operator: &str, span: Span) -> Self {
+        RuntimeError {
+            reason: "Attempt to divide by zero".to_string(),
+            help: Some(format!(
+                "the right hand side of this `{}` evaluated to 0",
+                operator
+            )),
+            span,
+        }
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        let diagnostic = Diagnostic::error(error.reason.clone(), error.span);
+        match &error.help {
+            Some(help) => diagnostic.with_help(help.clone()),
+            None => diagnostic,
+        }
+    }
+}
+
+/// How a statement finished, `break` and `continue` unwind to the innermost loop.
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+}
+
+/// Runs a program that went through semantic analysis directly. Integers are 64 bit and wrap
+/// around on overflow, `input` reads like `scanf("%lld")` and falls back to 0, which is what
+/// the C backend emits.
+pub struct Interpreter<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    variables: HashMap<SymbolId, i64>,
+}
+
+impl<R: BufRead, W: Write> Interpreter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            variables: HashMap::new(),
+        }
+    }
+
+    /// Output is flushed even when the program stops with an error.
+    pub fn run(&mut self, statements: &[Statement]) -> RuntimeResult<()> {
+        let result = self.execute_block(statements);
+        let flushed = self.output.flush();
+        result?;
+        let end = statements.last().map_or(Span::default(), Statement::span);
+        flushed.map_err(|e| io_error(e, end))
+    }
+
+    fn execute_block(&mut self, statements: &[Statement]) -> RuntimeResult<Flow> {
+        for statement in statements {
+            match self.execute(statement)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn execute(&mut self, statement: &Statement) -> RuntimeResult<Flow> {
+        match statement {
+            Statement::Print { option, span } => {
+                let written = match option {
+                    PrintOption::PrintLiteral(s) => write!(self.output, "{}", unescape(s)),
+                    PrintOption::PrintExpression(e) => {
+                        let value = self.evaluate(e)?;
+                        writeln!(self.output, "{}", value)
+                    }
+                };
+                written.map_err(|e| io_error(e, *span))?;
+            }
+            Statement::If {
+                comparison,
+                statements,
+                else_statements,
+                ..
+            } => {
+                if self.evaluate_comparison(comparison)? {
+                    return self.execute_block(statements);
+                } else if let Some(else_statements) = else_statements {
+                    return self.execute_block(else_statements);
+                }
+            }
+            Statement::While {
+                comparison,
+                statements,
+                ..
+            } => {
+                while self.evaluate_comparison(comparison)? {
+                    if let Flow::Break = self.execute_block(statements)? {
+                        break;
+                    }
+                }
+            }
+            Statement::For {
+                init,
+                comparison,
+                step,
+                statements,
+                ..
+            } => {
+                self.execute(init)?;
+                while self.evaluate_comparison(comparison)? {
+                    if let Flow::Break = self.execute_block(statements)? {
+                        break;
+                    }
+                    self.execute(step)?;
+                }
+            }
+            Statement::Break { .. } => return Ok(Flow::Break),
+            Statement::Continue { .. } => return Ok(Flow::Continue),
+            Statement::Let {
+                identifier,
+                expression,
+                ..
+            }
+            | Statement::Assign {
+                identifier,
+                expression,
+                ..
+            } => {
+                let value = self.evaluate(expression)?;
+                self.variables.insert(symbol_id(identifier), value);
+            }
+            Statement::Input { identifier, span } => {
+                // Prompts printed so far have to be visible before blocking on stdin.
+                self.output.flush().map_err(|e| io_error(e, *span))?;
+                let value = read_integer(&mut self.input).map_err(|e| io_error(e, *span))?;
+                self.variables.insert(symbol_id(identifier), value);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Chains stop at the first link that does not hold, `&&` and `||` short-circuit.
+    fn evaluate_comparison(&mut self, comparison: &Comparison) -> RuntimeResult<bool> {
+        match comparison {
+            Comparison::Relational { lhs, rhs } => {
+                let mut lhs = self.evaluate(lhs)?;
+                for (op, expression) in rhs {
+                    let rhs = self.evaluate(expression)?;
+                    if !compare(*op, lhs, rhs) {
+                        return Ok(false);
+                    }
+                    lhs = rhs;
+                }
+                Ok(true)
+            }
+            Comparison::Value(expression) => Ok(self.evaluate(expression)? != 0),
+            Comparison::Not(comparison) => Ok(!self.evaluate_comparison(comparison)?),
+            Comparison::And(lhs, rhs) => {
+                Ok(self.evaluate_comparison(lhs)? && self.evaluate_comparison(rhs)?)
+            }
+            Comparison::Or(lhs, rhs) => {
+                Ok(self.evaluate_comparison(lhs)? || self.evaluate_comparison(rhs)?)
+            }
+        }
+    }
+
+    fn evaluate(&mut self, expression: &Expression) -> RuntimeResult<i64> {
+        match expression {
+            Expression::Binary { op, lhs, rhs, span } => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                match op {
+                    BinaryOp::Add => Ok(lhs.wrapping_add(rhs)),
+                    BinaryOp::Subtract => Ok(lhs.wrapping_sub(rhs)),
+                    BinaryOp::Multiply => Ok(lhs.wrapping_mul(rhs)),
+                    BinaryOp::Divide | BinaryOp::Modulo if rhs == 0 => {
+                        Err(RuntimeError::division_by_zero(op.symbol(), *span))
+                    }
+                    BinaryOp::Divide => Ok(lhs.wrapping_div(rhs)),
+                    BinaryOp::Modulo => Ok(lhs.wrapping_rem(rhs)),
+                }
+            }
+            Expression::Unary { op, operand, .. } => {
+                let value = self.evaluate_primary(operand)?;
+                match op {
+                    UnaryOp::Plus => Ok(value),
+                    UnaryOp::Minus => Ok(value.wrapping_neg()),
+                }
+            }
+            Expression::Primary(primary) => self.evaluate_primary(primary),
+        }
+    }
+
+    fn evaluate_primary(&mut self, primary: &Primary) -> RuntimeResult<i64> {
+        match primary {
+            Primary::LiteralNumber(value, _) => Ok(*value),
+            Primary::IdentifierExpression(identifier) => Ok(*self
+                .variables
+                .get(&symbol_id(identifier))
+                .expect("Expected variable to be assigned before use")),
+            Primary::Grouped(expression, _) => self.evaluate(expression),
+        }
+    }
+}
+
+/// Reads the next integer like `scanf("%lld")`: leading whitespace is skipped and the number
+/// ends at the first non digit. Without a number the value is 0 and the next word is skipped,
+/// like `scanf("%*s")` does in the C backend. Numbers that do not fit saturate.
+pub fn read_integer<R: BufRead>(input: &mut R) -> io::Result<i64> {
+    skip_while(input, is_space)?;
+    let mut text = String::new();
+    if let Some(sign @ (b'+' | b'-')) = peek_byte(input)? {
+        text.push(sign as char);
+        input.consume(1);
+    }
+    while let Some(digit) = peek_byte(input)?.filter(u8::is_ascii_digit) {
+        text.push(digit as char);
+        input.consume(1);
+    }
+    if !text.ends_with(|c: char| c.is_ascii_digit()) {
+        skip_while(input, is_space)?;
+        skip_while(input, |b| !is_space(b))?;
+        return Ok(0);
+    }
+    Ok(text.parse().unwrap_or(if text.starts_with('-') {
+        i64::MIN
+    } else {
+        i64::MAX
+    }))
+}
+
+/// `isspace` in the C locale, which unlike `u8::is_ascii_whitespace` includes the vertical tab.
+fn is_space(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || byte == 0x0b
+}
+
+fn peek_byte<R: BufRead>(input: &mut R) -> io::Result<Option<u8>> {
+    Ok(input.fill_buf()?.first().copied())
+}
+
+fn skip_while<R: BufRead>(input: &mut R, predicate: impl Fn(u8) -> bool) -> io::Result<()> {
+    while let Some(byte) = peek_byte(input)? {
+        if !predicate(byte) {
+            break;
+        }
+        input.consume(1);
+    }
+    Ok(())
+}
+
+fn compare(op: RelationalOp, lhs: i64, rhs: i64) -> bool {
+    match op {
+        RelationalOp::GreaterThan => lhs > rhs,
+        RelationalOp::GreaterThanEquals => lhs >= rhs,
+        RelationalOp::LessThan => lhs < rhs,
+        RelationalOp::LessThanEquals => lhs <= rhs,
+        RelationalOp::EqualsEquals => lhs == rhs,
+        RelationalOp::NotEquals => lhs != rhs,
+    }
+}
+
+fn symbol_id(identifier: &Identifier) -> SymbolId {
+    identifier
+        .symbol
+        .as_ref()
+        .expect("Expected identifier to be resolved by semantic analysis")
+        .id
+}
+
+pub fn io_error(error: io::Error, span: Span) -> RuntimeError {
+    RuntimeError {
+        reason: format!("Input/output failed: {}", error),
+        help: None,
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::analyze;
+
+    fn run_source(source: &str, input: &str) -> RuntimeResult<String> {
+        let statements = analyze(source);
+        let mut output = Vec::new();
+        Interpreter::new(input.as_bytes(), &mut output).run(&statements)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_arithmetic_matches_c() {
+        let source = "\
+print 7 / -2;
+print -7 % 3;
+print 2 - 3 - 4;
+print 9223372036854775807 + 1;
+print \"done\\n\";";
+        let expected = "-3\n-1\n-5\n-9223372036854775808\ndone\n";
+        assert_eq!(run_source(source, "").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_loops_and_scopes() {
+        let source = "\
+let x = 0;
+for let i = 0; i < 10; i = i + 1 {
+    if i % 2 == 0 {
+        continue;
+    }
+    let x = i * 10;
+    if x > 50 {
+        break;
+    }
+    print x;
+}
+while 0 <= x < 3 {
+    x = x + 1;
+}
+print x;";
+        assert_eq!(run_source(source, "").unwrap(), "10\n30\n50\n3\n");
+    }
+
+    #[test]
+    fn test_input_behaves_like_scanf() {
+        let source =
+            "input a;\ninput b;\ninput c;\ninput d;\nprint a;\nprint b;\nprint c;\nprint d;";
+        assert_eq!(run_source(source, " 12abc -3").unwrap(), "12\n0\n-3\n0\n");
+        assert_eq!(
+            run_source(source, "x 99999999999999999999 +4").unwrap(),
+            "0\n9223372036854775807\n4\n0\n"
+        );
+        assert_eq!(
+            run_source(source, "\x0b1\x0bx\x0b2").unwrap(),
+            "1\n0\n2\n0\n"
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero_is_reported() {
+        let source = "let a = 0;\nprint \"before\";\nprint 1 + 10 / a;";
+        let error = run_source(source, "").unwrap_err();
+        assert_eq!(error.reason, "Attempt to divide by zero");
+        assert_eq!(error.span, Span::new(37, 43, 3, 11));
+
+        // Short-circuiting keeps the division from being evaluated.
+        assert_eq!(run_source("if 0 && 1 / 0 {\n}", "").unwrap(), "");
+    }
+
+    #[test]
+    fn test_runs_data_3() {
+        let output = run_source(include_str!("../../data/3.scrpt"), "5\n").unwrap();
+        assert!(output.starts_with("Complex script execution starts\n"));
+    }
+}
@@ -0,0 +1,466 @@
+//! The `.mmcb` file format for compiled bytecode. All integers are little endian.
+//!
+//! ```text
+//! magic       "MMCB"
+//! version     u16, currently 1
+//! flags       u16, bit 0 set when the debug section is present
+//! constants   u32 count, then an i64 per constant
+//! strings     u32 count, then per string a u32 byte length and UTF-8 bytes
+//! locals      u32 count
+//! code        u32 count, then per instruction an opcode byte, followed by a u32 operand
+//!             for PUSH, LOAD, STORE, JUMP, JUMP_IF_FALSE and PRINT_STRING
+//! debug       per instruction its span as u32 start, end, line and column,
+//!             then per local a u32 byte length and its UTF-8 name
+//! ```
+
+use std::fmt;
+
+use crate::lexing::lexer::Span;
+
+use super::bytecode::{Chunk, Instruction};
+
+pub const MAGIC: &[u8; 4] = b"MMCB";
+pub const VERSION: u16 = 1;
+const FLAG_DEBUG: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadError {
+    pub reason: String,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+pub type LoadResult<T> = std::result::Result<T, LoadError>;
+
+fn error<T>(reason: impl Into<String>) -> LoadResult<T> {
+    Err(LoadError {
+        reason: reason.into(),
+    })
+}
+
+/// Serializes `chunk`, spans and local names are only written when `debug` is set.
+pub fn write_chunk(chunk: &Chunk, debug: bool) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    let flags = if debug { FLAG_DEBUG } else { 0 };
+    bytes.extend_from_slice(&flags.to_le_bytes());
+
+    write_u32(&mut bytes, chunk.constants.len());
+    for constant in &chunk.constants {
+        bytes.extend_from_slice(&constant.to_le_bytes());
+    }
+    write_u32(&mut bytes, chunk.strings.len());
+    for string in &chunk.strings {
+        write_string(&mut bytes, string);
+    }
+    write_u32(&mut bytes, chunk.locals.len());
+
+    write_u32(&mut bytes, chunk.code.len());
+    for instruction in &chunk.code {
+        let (opcode, operand) = encode(instruction);
+        bytes.push(opcode);
+        if let Some(operand) = operand {
+            bytes.extend_from_slice(&operand.to_le_bytes());
+        }
+    }
+
+    if debug {
+        for span in &chunk.spans {
+            for value in [span.start, span.end, span.line, span.column] {
+                write_u32(&mut bytes, value);
+            }
+        }
+        for name in &chunk.locals {
+            write_string(&mut bytes, name);
+        }
+    }
+    bytes
+}
+
+/// Deserializes and validates a chunk. Anything the `Vm` could trip over is rejected: unknown
+/// opcodes, operands out of range, jumps outside the code and code that could pop from an empty
+/// stack or reach an instruction with differing stack depths.
+pub fn read_chunk(bytes: &[u8]) -> LoadResult<Chunk> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len(), "header")? != MAGIC {
+        return error("Not an mmcb file, the magic bytes are missing");
+    }
+    let version = reader.u16("header")?;
+    if version != VERSION {
+        return error(format!(
+            "Unsupported mmcb version {}, this build reads version {}",
+            version, VERSION
+        ));
+    }
+    let flags = reader.u16("header")?;
+    if flags & !FLAG_DEBUG != 0 {
+        return error(format!("Unknown flags {:#06x} in header", flags));
+    }
+
+    let mut chunk = Chunk::default();
+    let count = reader.u32("constant pool")?;
+    for _ in 0..count {
+        chunk.constants.push(reader.i64("constant pool")?);
+    }
+    let count = reader.u32("string table")?;
+    for _ in 0..count {
+        chunk.strings.push(reader.string("string table")?);
+    }
+    let local_count = reader.u32("locals")?;
+
+    let count = reader.u32("code")?;
+    for _ in 0..count {
+        let offset = chunk.code.len();
+        let opcode = reader.u8("code")?;
+        let instruction = decode(opcode, &mut reader)?.ok_or_else(|| LoadError {
+            reason: format!("Unknown opcode {:#04x} at instruction {}", opcode, offset),
+        })?;
+        chunk.code.push(instruction);
+    }
+
+    // Every local takes a `STORE` to be of any use, this bounds what a corrupt count allocates.
+    if local_count as usize > chunk.code.len() {
+        return error(format!(
+            "Declares {} locals but has only {} instructions",
+            local_count,
+            chunk.code.len()
+        ));
+    }
+
+    if flags & FLAG_DEBUG != 0 {
+        for _ in 0..chunk.code.len() {
+            let start = reader.u32("debug section")? as usize;
+            let end = reader.u32("debug section")? as usize;
+            let line = reader.u32("debug section")? as usize;
+            let column = reader.u32("debug section")? as usize;
+            chunk.spans.push(Span::new(start, end, line, column));
+        }
+        for _ in 0..local_count {
+            chunk.locals.push(reader.string("debug section")?);
+        }
+    } else {
+        chunk.spans = vec![Span::default(); chunk.code.len()];
+        chunk.locals = (0..local_count)
+            .map(|slot| format!("local_{}", slot))
+            .collect();
+    }
+
+    if reader.position != bytes.len() {
+        return error(format!(
+            "Unexpected {} trailing bytes after the last section",
+            bytes.len() - reader.position
+        ));
+    }
+    verify(&chunk)?;
+    Ok(chunk)
+}
+
+fn verify(chunk: &Chunk) -> LoadResult<()> {
+    let len = chunk.code.len();
+    for (offset, instruction) in chunk.code.iter().enumerate() {
+        let (index, limit, what) = match *instruction {
+            Instruction::Push(index) => (index, chunk.constants.len(), "constant"),
+            Instruction::Load(slot) | Instruction::Store(slot) => {
+                (slot, chunk.locals.len(), "local")
+            }
+            Instruction::PrintString(index) => (index, chunk.strings.len(), "string"),
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => {
+                (target, len + 1, "jump target")
+            }
+            _ => continue,
+        };
+        if index as usize >= limit {
+            return error(format!(
+                "Instruction {} refers to {} {}, which does not exist",
+                offset, what, index
+            ));
+        }
+    }
+
+    // Walk every path through the code, tracking how many values are on the stack.
+    let mut depths: Vec<Option<usize>> = vec![None; len];
+    let mut pending = vec![(0, 0)];
+    while let Some((offset, depth)) = pending.pop() {
+        if offset == len {
+            continue;
+        }
+        match depths[offset] {
+            Some(known) if known == depth => continue,
+            Some(known) => {
+                return error(format!(
+                    "Instruction {} is reached with {} and with {} values on the stack",
+                    offset, known, depth
+                ))
+            }
+            None => depths[offset] = Some(depth),
+        }
+        let instruction = chunk.code[offset];
+        let (pops, pushes) = stack_effect(&instruction);
+        if depth < pops {
+            return error(format!(
+                "Instruction {} ({}) pops from an empty stack",
+                offset,
+                instruction.name()
+            ));
+        }
+        let depth = depth - pops + pushes;
+        match instruction {
+            Instruction::Jump(target) => pending.push((target as usize, depth)),
+            Instruction::JumpIfFalse(target) => {
+                pending.push((target as usize, depth));
+                pending.push((offset + 1, depth));
+            }
+            _ => pending.push((offset + 1, depth)),
+        }
+    }
+    Ok(())
+}
+
+fn stack_effect(instruction: &Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::Push(_) | Instruction::Load(_) | Instruction::Input => (0, 1),
+        Instruction::Store(_) | Instruction::JumpIfFalse(_) | Instruction::Print => (1, 0),
+        Instruction::Add
+        | Instruction::Subtract
+        | Instruction::Multiply
+        | Instruction::Divide
+        | Instruction::Modulo
+        | Instruction::Equal
+        | Instruction::NotEqual
+        | Instruction::Greater
+        | Instruction::GreaterEqual
+        | Instruction::Less
+        | Instruction::LessEqual => (2, 1),
+        Instruction::Negate | Instruction::Not => (1, 1),
+        Instruction::Jump(_) | Instruction::PrintString(_) => (0, 0),
+    }
+}
+
+fn encode(instruction: &Instruction) -> (u8, Option<u32>) {
+    match *instruction {
+        Instruction::Push(index) => (0x01, Some(index)),
+        Instruction::Load(slot) => (0x02, Some(slot)),
+        Instruction::Store(slot) => (0x03, Some(slot)),
+        Instruction::Add => (0x10, None),
+        Instruction::Subtract => (0x11, None),
+        Instruction::Multiply => (0x12, None),
+        Instruction::Divide => (0x13, None),
+        Instruction::Modulo => (0x14, None),
+        Instruction::Negate => (0x15, None),
+        Instruction::Equal => (0x20, None),
+        Instruction::NotEqual => (0x21, None),
+        Instruction::Greater => (0x22, None),
+        Instruction::GreaterEqual => (0x23, None),
+        Instruction::Less => (0x24, None),
+        Instruction::LessEqual => (0x25, None),
+        Instruction::Not => (0x26, None),
+        Instruction::Jump(target) => (0x30, Some(target)),
+        Instruction::JumpIfFalse(target) => (0x31, Some(target)),
+        Instruction::Print => (0x40, None),
+        Instruction::PrintString(index) => (0x41, Some(index)),
+        Instruction::Input => (0x42, None),
+    }
+}
+
+fn decode(opcode: u8, reader: &mut Reader) -> LoadResult<Option<Instruction>> {
+    let instruction = match opcode {
+        0x01 => Instruction::Push(reader.u32("code")?),
+        0x02 => Instruction::Load(reader.u32("code")?),
+        0x03 => Instruction::Store(reader.u32("code")?),
+        0x10 => Instruction::Add,
+        0x11 => Instruction::Subtract,
+        0x12 => Instruction::Multiply,
+        0x13 => Instruction::Divide,
+        0x14 => Instruction::Modulo,
+        0x15 => Instruction::Negate,
+        0x20 => Instruction::Equal,
+        0x21 => Instruction::NotEqual,
+        0x22 => Instruction::Greater,
+        0x23 => Instruction::GreaterEqual,
+        0x24 => Instruction::Less,
+        0x25 => Instruction::LessEqual,
+        0x26 => Instruction::Not,
+        0x30 => Instruction::Jump(reader.u32("code")?),
+        0x31 => Instruction::JumpIfFalse(reader.u32("code")?),
+        0x40 => Instruction::Print,
+        0x41 => Instruction::PrintString(reader.u32("code")?),
+        0x42 => Instruction::Input,
+        _ => return Ok(None),
+    };
+    Ok(Some(instruction))
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("Expected value to fit in 32 bits");
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    write_u32(bytes, string.len());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// `section` names the part of the file being read, for the error message.
+    fn take(&mut self, count: usize, section: &str) -> LoadResult<&'a [u8]> {
+        match self.bytes.get(self.position..self.position + count) {
+            Some(bytes) => {
+                self.position += count;
+                Ok(bytes)
+            }
+            None => error(format!(
+                "File is truncated, it ends in the {} at byte {}",
+                section,
+                self.bytes.len()
+            )),
+        }
+    }
+
+    fn u8(&mut self, section: &str) -> LoadResult<u8> {
+        Ok(self.take(1, section)?[0])
+    }
+
+    fn u16(&mut self, section: &str) -> LoadResult<u16> {
+        let bytes = self.take(2, section)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self, section: &str) -> LoadResult<u32> {
+        let bytes = self.take(4, section)?;
+        Ok(u32::from_le_bytes(
+            bytes.try_into().expect("Expected 4 bytes"),
+        ))
+    }
+
+    fn i64(&mut self, section: &str) -> LoadResult<i64> {
+        let bytes = self.take(8, section)?;
+        Ok(i64::from_le_bytes(
+            bytes.try_into().expect("Expected 8 bytes"),
+        ))
+    }
+
+    fn string(&mut self, section: &str) -> LoadResult<String> {
+        let len = self.u32(section)? as usize;
+        let bytes = self.take(len, section)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError {
+            reason: format!("Invalid UTF-8 in the {}", section),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreting::compiler::Compiler;
+    use crate::test_utils::analyze;
+
+    fn compile_source(source: &str) -> Chunk {
+        let statements = analyze(source);
+        Compiler::new().compile(&statements)
+    }
+
+    fn reason(bytes: &[u8]) -> String {
+        read_chunk(bytes).unwrap_err().reason
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = compile_source(include_str!("../../data/3.scrpt"));
+        assert_eq!(read_chunk(&write_chunk(&chunk, true)), Ok(chunk.clone()));
+
+        let stripped = read_chunk(&write_chunk(&chunk, false)).unwrap();
+        assert_eq!(stripped.code, chunk.code);
+        assert_eq!(stripped.locals.len(), chunk.locals.len());
+        assert_eq!(stripped.spans, vec![Span::default(); chunk.code.len()]);
+    }
+
+    #[test]
+    fn test_rejects_corrupt_files() {
+        let chunk = compile_source("let a = 1;\nwhile a < 3 {\n  print \"a\";\n  a = a + 1;\n}");
+        let bytes = write_chunk(&chunk, true);
+
+        assert_eq!(
+            reason(b"MMC"),
+            "File is truncated, it ends in the header at byte 3"
+        );
+        assert_eq!(
+            reason(b"\x7fELF...."),
+            "Not an mmcb file, the magic bytes are missing"
+        );
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(
+            reason(&newer),
+            "Unsupported mmcb version 2, this build reads version 1"
+        );
+        for len in 8..bytes.len() {
+            assert!(
+                reason(&bytes[..len]).starts_with("File is truncated"),
+                "Expected truncation at {} to be detected",
+                len
+            );
+        }
+        let mut locals = write_chunk(&Chunk::default(), false);
+        locals[16] = 0xff;
+        assert_eq!(
+            reason(&locals),
+            "Declares 255 locals but has only 0 instructions"
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            reason(&trailing),
+            "Unexpected 1 trailing bytes after the last section"
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_code() {
+        let chunk = |code: Vec<Instruction>| Chunk {
+            spans: vec![Span::default(); code.len()],
+            code,
+            constants: vec![7],
+            strings: Vec::new(),
+            locals: vec!["x".to_string()],
+        };
+        let load = |code| reason(&write_chunk(&chunk(code), false));
+
+        assert_eq!(
+            load(vec![Instruction::Push(1)]),
+            "Instruction 0 refers to constant 1, which does not exist"
+        );
+        assert_eq!(
+            load(vec![Instruction::Jump(5)]),
+            "Instruction 0 refers to jump target 5, which does not exist"
+        );
+        assert_eq!(
+            load(vec![Instruction::Push(0), Instruction::Add]),
+            "Instruction 1 (ADD) pops from an empty stack"
+        );
+        assert_eq!(
+            load(vec![
+                Instruction::Push(0),
+                Instruction::JumpIfFalse(3),
+                Instruction::Push(0),
+                Instruction::Print,
+            ]),
+            "Instruction 3 is reached with 1 and with 0 values on the stack"
+        );
+
+        let mut bytes = write_chunk(&chunk(vec![Instruction::Print]), false);
+        let last = bytes.len() - 1;
+        bytes[last] = 0xff;
+        assert_eq!(reason(&bytes), "Unknown opcode 0xff at instruction 0");
+    }
+}
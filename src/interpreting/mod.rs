@@ -0,0 +1,6 @@
+pub mod bytecode;
+pub mod compiler;
+pub mod disassembler;
+pub mod interpreter;
+pub mod mmcb;
+pub mod vm;
@@ -0,0 +1,169 @@
+use std::io::{BufRead, Write};
+
+use crate::lexing::lexer::Span;
+
+use super::bytecode::{Chunk, Instruction};
+use super::interpreter::{io_error, read_integer, RuntimeError, RuntimeResult};
+
+/// Runs compiled bytecode with the same semantics as the `Interpreter`.
+pub struct Vm<'a, R: BufRead, W: Write> {
+    chunk: &'a Chunk,
+    input: R,
+    output: W,
+    stack: Vec<i64>,
+    locals: Vec<i64>,
+}
+
+impl<'a, R: BufRead, W: Write> Vm<'a, R, W> {
+    pub fn new(chunk: &'a Chunk, input: R, output: W) -> Self {
+        Vm {
+            chunk,
+            input,
+            output,
+            stack: Vec::new(),
+            locals: vec![0; chunk.locals.len()],
+        }
+    }
+
+    /// Output is flushed even when the program stops with an error.
+    pub fn run(&mut self) -> RuntimeResult<()> {
+        let result = self.execute();
+        let flushed = self.output.flush();
+        result?;
+        let end = self.chunk.spans.last().copied().unwrap_or_default();
+        flushed.map_err(|e| io_error(e, end))
+    }
+
+    fn execute(&mut self) -> RuntimeResult<()> {
+        let mut ip = 0;
+        while let Some(&instruction) = self.chunk.code.get(ip) {
+            let span = self.chunk.spans[ip];
+            ip += 1;
+            match instruction {
+                Instruction::Push(index) => self.stack.push(self.chunk.constants[index as usize]),
+                Instruction::Load(slot) => self.stack.push(self.locals[slot as usize]),
+                Instruction::Store(slot) => self.locals[slot as usize] = self.pop(),
+                Instruction::Add => self.binary(|lhs, rhs| lhs.wrapping_add(rhs)),
+                Instruction::Subtract => self.binary(|lhs, rhs| lhs.wrapping_sub(rhs)),
+                Instruction::Multiply => self.binary(|lhs, rhs| lhs.wrapping_mul(rhs)),
+                Instruction::Divide => {
+                    self.check_divisor("/", span)?;
+                    self.binary(|lhs, rhs| lhs.wrapping_div(rhs))
+                }
+                Instruction::Modulo => {
+                    self.check_divisor("%", span)?;
+                    self.binary(|lhs, rhs| lhs.wrapping_rem(rhs))
+                }
+                Instruction::Negate => {
+                    let value = self.pop();
+                    self.stack.push(value.wrapping_neg())
+                }
+                Instruction::Equal => self.binary(|lhs, rhs| (lhs == rhs) as i64),
+                Instruction::NotEqual => self.binary(|lhs, rhs| (lhs != rhs) as i64),
+                Instruction::Greater => self.binary(|lhs, rhs| (lhs > rhs) as i64),
+                Instruction::GreaterEqual => self.binary(|lhs, rhs| (lhs >= rhs) as i64),
+                Instruction::Less => self.binary(|lhs, rhs| (lhs < rhs) as i64),
+                Instruction::LessEqual => self.binary(|lhs, rhs| (lhs <= rhs) as i64),
+                Instruction::Not => {
+                    let value = self.pop();
+                    self.stack.push((value == 0) as i64)
+                }
+                Instruction::Jump(target) => ip = target as usize,
+                Instruction::JumpIfFalse(target) => {
+                    if self.pop() == 0 {
+                        ip = target as usize;
+                    }
+                }
+                Instruction::Print => {
+                    let value = self.pop();
+                    writeln!(self.output, "{}", value).map_err(|e| io_error(e, span))?
+                }
+                Instruction::PrintString(index) => {
+                    let string = &self.chunk.strings[index as usize];
+                    write!(self.output, "{}", string).map_err(|e| io_error(e, span))?
+                }
+                Instruction::Input => {
+                    // Prompts printed so far have to be visible before blocking on stdin.
+                    self.output.flush().map_err(|e| io_error(e, span))?;
+                    let value = read_integer(&mut self.input).map_err(|e| io_error(e, span))?;
+                    self.stack.push(value)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> i64 {
+        self.stack.pop().expect("Expected a value on the stack")
+    }
+
+    fn binary(&mut self, op: impl Fn(i64, i64) -> i64) {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        self.stack.push(op(lhs, rhs));
+    }
+
+    fn check_divisor(&self, operator: &str, span: Span) -> RuntimeResult<()> {
+        match self.stack.last() {
+            Some(0) => Err(RuntimeError::division_by_zero(operator, span)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreting::compiler::Compiler;
+    use crate::interpreting::interpreter::Interpreter;
+    use crate::test_utils::analyze;
+
+    /// Runs `source` on both the VM and the tree-walking interpreter, which have to agree.
+    fn run_source(source: &str, input: &str) -> RuntimeResult<String> {
+        let statements = analyze(source);
+
+        let mut expected = Vec::new();
+        let expected_result = Interpreter::new(input.as_bytes(), &mut expected).run(&statements);
+
+        let chunk = Compiler::new().compile(&statements);
+        let mut output = Vec::new();
+        let result = Vm::new(&chunk, input.as_bytes(), &mut output).run();
+        assert_eq!(output, expected);
+        assert_eq!(
+            result.as_ref().map_err(|e| e.span),
+            expected_result.as_ref().map_err(|e| e.span)
+        );
+        result.map(|_| String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_vm_runs_control_flow() {
+        let source = "\
+let x = 0;
+for let i = 0; i < 10; i = i + 1 {
+    if i % 2 == 0 || i == 5 {
+        continue;
+    } else if !(i < 8) && 0 <= x < 100 {
+        break;
+    }
+    let x = i * -10;
+    print x;
+}
+print \"done\\n\";";
+        assert_eq!(run_source(source, "").unwrap(), "-10\n-30\n-70\ndone\n");
+    }
+
+    #[test]
+    fn test_vm_input_and_errors() {
+        let source = "input a;\ninput b;\nprint a / b;\nprint a % b;";
+        assert_eq!(run_source(source, "-7 2").unwrap(), "-3\n-1\n");
+        let error = run_source(source, "7 x").unwrap_err();
+        assert_eq!(error.reason, "Attempt to divide by zero");
+    }
+
+    #[test]
+    fn test_vm_runs_data() {
+        run_source(include_str!("../../data/1.scrpt"), "10").unwrap();
+        run_source(include_str!("../../data/3.scrpt"), "4").unwrap();
+    }
+}
@@ -1,16 +1,31 @@
 use std::collections::HashMap;
+use std::fmt;
 
 pub type LexerResult<T> = std::result::Result<T, LexerError>;
 
 #[derive(Debug, Clone)]
-pub struct LexerError;
+pub struct LexerError {
+    pub span: Span,
+}
 
 pub struct Lexer<'a> {
     input: &'a str,
     current_pos: usize,
+    line: usize,
+    column: usize,
     keywords: HashMap<String, TokenKind>,
 }
 
+/// Location of a piece of source text.
+/// `start` and `end` are byte offsets, `line` and `column` are 1-based and point at `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
@@ -20,6 +35,7 @@ pub struct Token {
 #[derive(Debug, Clone)]
 pub struct TokenData {
     pub raw: String,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -34,6 +50,9 @@ pub enum TokenKind {
     Equals,
     EqualsEquals,
     NotEquals,
+    Not,
+    AndAnd,
+    OrOr,
     GreaterThan,
     LessThan,
     GreaterThanEquals,
@@ -42,8 +61,13 @@ pub enum TokenKind {
     If,
     Else,
     While,
+    For,
+    Break,
+    Continue,
     OpenCurly,
     CloseCurly,
+    OpenParen,
+    CloseParen,
     SemiColon,
     Print,
     Input,
@@ -54,13 +78,85 @@ pub enum TokenKind {
     TokenizationError,
 }
 
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Span covering both `self` and `other`, `self` is expected to come first.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            TokenKind::LiteralNumber(_) => "number",
+            TokenKind::LiteralString => "string literal",
+            TokenKind::Plus => "`+`",
+            TokenKind::Minus => "`-`",
+            TokenKind::Asterisk => "`*`",
+            TokenKind::Slash => "`/`",
+            TokenKind::Modulo => "`%`",
+            TokenKind::Equals => "`=`",
+            TokenKind::EqualsEquals => "`==`",
+            TokenKind::NotEquals => "`!=`",
+            TokenKind::Not => "`!`",
+            TokenKind::AndAnd => "`&&`",
+            TokenKind::OrOr => "`||`",
+            TokenKind::GreaterThan => "`>`",
+            TokenKind::LessThan => "`<`",
+            TokenKind::GreaterThanEquals => "`>=`",
+            TokenKind::LessThanEquals => "`<=`",
+            TokenKind::Let => "`let`",
+            TokenKind::If => "`if`",
+            TokenKind::Else => "`else`",
+            TokenKind::While => "`while`",
+            TokenKind::For => "`for`",
+            TokenKind::Break => "`break`",
+            TokenKind::Continue => "`continue`",
+            TokenKind::OpenCurly => "`{`",
+            TokenKind::CloseCurly => "`}`",
+            TokenKind::OpenParen => "`(`",
+            TokenKind::CloseParen => "`)`",
+            TokenKind::SemiColon => "`;`",
+            TokenKind::Print => "`print`",
+            TokenKind::Input => "`input`",
+            TokenKind::Invalid => "invalid token",
+            TokenKind::Whitespace => "whitespace",
+            TokenKind::Identifier => "identifier",
+            TokenKind::Eof => "end of file",
+            TokenKind::TokenizationError => "unreadable token",
+        };
+        write!(f, "{}", description)
+    }
+}
+
 impl Token {
     fn new(kind: TokenKind, data: String) -> Self {
         Token {
             kind,
-            data: TokenData { raw: data },
+            data: TokenData {
+                raw: data,
+                span: Span::default(),
+            },
         }
     }
+
+    pub fn span(&self) -> Span {
+        self.data.span
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -69,7 +165,13 @@ impl<'a> Iterator for Lexer<'a> {
         match self.next_token() {
             Ok(t) if t.kind == TokenKind::Eof => None,
             Ok(t) => Some(t),
-            Err(_) => Some(Token::new(TokenKind::TokenizationError, "".into())),
+            Err(e) => Some(Token {
+                kind: TokenKind::TokenizationError,
+                data: TokenData {
+                    raw: "".into(),
+                    span: e.span,
+                },
+            }),
         }
     }
 }
@@ -79,19 +181,27 @@ impl<'a> Lexer<'a> {
         let mut lexer = Lexer {
             input,
             current_pos: 0,
+            line: 1,
+            column: 1,
             keywords: HashMap::new(),
         };
         lexer.keywords.insert("let".to_string(), TokenKind::Let);
         lexer.keywords.insert("if".to_string(), TokenKind::If);
         lexer.keywords.insert("else".to_string(), TokenKind::Else);
         lexer.keywords.insert("while".to_string(), TokenKind::While);
+        lexer.keywords.insert("for".to_string(), TokenKind::For);
+        lexer.keywords.insert("break".to_string(), TokenKind::Break);
+        lexer
+            .keywords
+            .insert("continue".to_string(), TokenKind::Continue);
         lexer.keywords.insert("print".to_string(), TokenKind::Print);
         lexer.keywords.insert("input".to_string(), TokenKind::Input);
         lexer
     }
 
     pub fn next_token(&mut self) -> LexerResult<Token> {
-        let token = match self.current_char() {
+        let start = Span::new(self.current_pos, self.current_pos, self.line, self.column);
+        let mut token = match self.current_char() {
             None => Token::new(TokenKind::Eof, '\0'.into()),
             Some(c) if c.is_ascii_digit() => {
                 let mut str = String::new();
@@ -103,10 +213,14 @@ impl<'a> Lexer<'a> {
                             str.push(c);
                             self.next_char();
                         }
-                        _ => {
-                            let int: i64 = str.parse::<i64>().map_err(|_| LexerError)?;
-                            break Token::new(TokenKind::LiteralNumber(int), str);
-                        }
+                        _ => match str.parse::<i64>() {
+                            Ok(int) => break Token::new(TokenKind::LiteralNumber(int), str),
+                            Err(_) => {
+                                let span = start.to(self.span_through_current());
+                                self.next_char();
+                                return Err(LexerError { span });
+                            }
+                        },
                     };
                 }
             }
@@ -142,33 +256,49 @@ impl<'a> Lexer<'a> {
                 '%' => Token::new(TokenKind::Modulo, c.into()),
                 '{' => Token::new(TokenKind::OpenCurly, c.into()),
                 '}' => Token::new(TokenKind::CloseCurly, c.into()),
+                '(' => Token::new(TokenKind::OpenParen, c.into()),
+                ')' => Token::new(TokenKind::CloseParen, c.into()),
                 ';' => Token::new(TokenKind::SemiColon, c.into()),
                 '<' => match self.peek_next_char() {
                     Some('=') => {
-                        self.next_char().ok_or(LexerError)?;
+                        self.next_char();
                         Token::new(TokenKind::LessThanEquals, format!("{}=", c))
                     }
                     _ => Token::new(TokenKind::LessThan, c.into()),
                 },
                 '>' => match self.peek_next_char() {
                     Some('=') => {
-                        self.next_char().ok_or(LexerError)?;
+                        self.next_char();
                         Token::new(TokenKind::GreaterThanEquals, format!("{}=", c))
                     }
                     _ => Token::new(TokenKind::GreaterThan, c.into()),
                 },
                 '=' => match self.peek_next_char() {
                     Some('=') => {
-                        self.next_char().ok_or(LexerError)?;
+                        self.next_char();
                         Token::new(TokenKind::EqualsEquals, format!("{}=", c))
                     }
                     _ => Token::new(TokenKind::Equals, c.into()),
                 },
                 '!' => match self.peek_next_char() {
                     Some('=') => {
-                        self.next_char().ok_or(LexerError)?;
+                        self.next_char();
                         Token::new(TokenKind::NotEquals, format!("{}=", c))
                     }
+                    _ => Token::new(TokenKind::Not, c.into()),
+                },
+                '&' => match self.peek_next_char() {
+                    Some('&') => {
+                        self.next_char();
+                        Token::new(TokenKind::AndAnd, format!("{}&", c))
+                    }
+                    _ => Token::new(TokenKind::Invalid, c.into()),
+                },
+                '|' => match self.peek_next_char() {
+                    Some('|') => {
+                        self.next_char();
+                        Token::new(TokenKind::OrOr, format!("{}|", c))
+                    }
                     _ => Token::new(TokenKind::Invalid, c.into()),
                 },
                 '"' => {
@@ -195,6 +325,10 @@ impl<'a> Lexer<'a> {
             },
         };
         self.next_char();
+        token.data.span = Span {
+            end: self.current_pos,
+            ..start
+        };
         Ok(token)
     }
 
@@ -202,15 +336,28 @@ impl<'a> Lexer<'a> {
         if self.is_done() {
             return None;
         }
-        self.input.chars().nth(self.current_pos)
+        self.input[self.current_pos..].chars().next()
     }
     fn next_char(&mut self) -> Option<char> {
-        self.current_pos += 1;
+        if let Some(c) = self.current_char() {
+            self.current_pos += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
         self.current_char()
     }
 
     fn peek_next_char(&self) -> Option<char> {
-        self.input.chars().nth(self.current_pos + 1)
+        self.input[self.current_pos..].chars().nth(1)
+    }
+
+    fn span_through_current(&self) -> Span {
+        let end = self.current_pos + self.current_char().map_or(0, char::len_utf8);
+        Span::new(self.current_pos, end, self.line, self.column)
     }
 
     fn is_done(&self) -> bool {
@@ -218,6 +365,32 @@ impl<'a> Lexer<'a> {
     }
 }
 
+/// Resolves the C style escapes (`\n`, `\t`, `\r`, `\\`, `\"`) of a string literal's content.
+/// Any other escape is kept as written.
+pub fn unescape(raw: &str) -> String {
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            Some('"') => unescaped.push('"'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -242,7 +415,7 @@ mod tests {
             lexer.next_token().unwrap().kind,
             TokenKind::GreaterThanEquals
         );
-        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Invalid);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Not);
         assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Whitespace);
         assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Whitespace);
         assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Whitespace);
@@ -250,6 +423,30 @@ mod tests {
         assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Eof);
     }
 
+    #[test]
+    fn test_logical_operators() {
+        let mut lexer = Lexer::from("&&||!!=&|");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::AndAnd);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::OrOr);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Not);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::NotEquals);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Invalid);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Invalid);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Eof);
+    }
+
+    #[test]
+    fn test_parens() {
+        let mut lexer = Lexer::from("(1)");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::OpenParen);
+        assert_eq!(
+            lexer.next_token().unwrap().kind,
+            TokenKind::LiteralNumber(1)
+        );
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::CloseParen);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Eof);
+    }
+
     #[test]
     fn test_basic_string() {
         let mut lexer = Lexer::from("\"Hello World\"");
@@ -331,6 +528,19 @@ mod tests {
         assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Eof);
     }
 
+    #[test]
+    fn test_loop_keywords() {
+        let mut lexer = Lexer::from("for break;continue breaking");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::For);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Whitespace);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Break);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::SemiColon);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Continue);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Whitespace);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Identifier);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Eof);
+    }
+
     #[test]
     fn test_identifier() {
         let mut lexer = Lexer::from("Hello World");
@@ -344,6 +554,46 @@ mod tests {
         assert_eq!(token.data.raw, "World");
     }
 
+    #[test]
+    fn test_spans() {
+        let mut lexer = Lexer::from("let x\n  = 42;");
+        let token = lexer.next_token().unwrap();
+        assert_eq!(token.span(), Span::new(0, 3, 1, 1));
+        lexer.next_token().unwrap();
+        let token = lexer.next_token().unwrap();
+        assert_eq!(token.span(), Span::new(4, 5, 1, 5));
+        lexer.next_token().unwrap();
+        lexer.next_token().unwrap();
+        lexer.next_token().unwrap();
+        let token = lexer.next_token().unwrap();
+        assert_eq!(token.kind, TokenKind::Equals);
+        assert_eq!(token.span(), Span::new(8, 9, 2, 3));
+        lexer.next_token().unwrap();
+        let token = lexer.next_token().unwrap();
+        assert_eq!(token.kind, TokenKind::LiteralNumber(42));
+        assert_eq!(token.span(), Span::new(10, 12, 2, 5));
+    }
+
+    #[test]
+    fn test_spans_use_byte_offsets() {
+        let mut lexer = Lexer::from("\"\u{e9}\" >=");
+        let token = lexer.next_token().unwrap();
+        assert_eq!(token.kind, TokenKind::LiteralString);
+        assert_eq!(token.span(), Span::new(0, 4, 1, 1));
+        lexer.next_token().unwrap();
+        let token = lexer.next_token().unwrap();
+        assert_eq!(token.kind, TokenKind::GreaterThanEquals);
+        assert_eq!(token.span(), Span::new(5, 7, 1, 5));
+    }
+
+    #[test]
+    fn test_too_big_nr() {
+        let mut lexer = Lexer::from("99999999999999999999 1");
+        let error = lexer.next_token().unwrap_err();
+        assert_eq!(error.span, Span::new(0, 20, 1, 1));
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Whitespace);
+    }
+
     #[test]
     fn test_interator() {
         let lexer = Lexer::from("let if else while while= whileif hello\nprint;input");
@@ -368,4 +618,10 @@ mod tests {
         assert_eq!(iter.next().unwrap().kind, TokenKind::Input);
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("a\\nb\\t\\\\"), "a\nb\t\\");
+        assert_eq!(unescape("100%\\q\\"), "100%\\q\\");
+    }
 }
@@ -1,16 +1,40 @@
 use std::fs;
+use std::io::{self, BufWriter};
+use std::path::Path;
 
 use crate::{
-    emitting::emitter::CEmitter,
+    analyzing::semantic::SemanticAnalyzer,
+    emitting::{
+        cil::CilEmitter, emitter::CEmitter, js::JsEmitter, jvm::JvmEmitter, llvm::LlvmEmitter,
+        python::PythonEmitter, qbe::QbeEmitter, riscv64::Riscv64Emitter, rust::RustEmitter,
+        wat::WatEmitter, x86_64::X86Emitter,
+    },
+    interpreting::{
+        compiler::Compiler,
+        disassembler::disassemble,
+        interpreter::Interpreter,
+        mmcb::{read_chunk, write_chunk, MAGIC},
+        vm::Vm,
+    },
     lexing::lexer::{Lexer, Token, TokenKind},
-    parsing::parser::Parser,
+    parsing::parser::{Parser, Statement},
+    reporting::diagnostic::{stderr_supports_color, Diagnostic, Renderer},
 };
+mod analyzing;
 mod emitting;
+mod interpreting;
 mod lexing;
 mod parsing;
-use clap::{Arg, Command};
+mod reporting;
+#[cfg(test)]
+mod test_utils;
+use clap::{Arg, ArgAction, Command};
 use std::process;
 
+const TARGETS: &[&str] = &[
+    "C", "bytecode", "x86_64", "llvm", "wat", "js", "py", "rust", "il", "jvm", "riscv64", "qbe",
+];
+
 fn main() {
     let matches = Command::new("mmc")
         .version("1.0")
@@ -28,7 +52,7 @@ fn main() {
                 .long("target")
                 .value_name("TARGET")
                 .default_value("C")
-                .help("Sets the target language for the output, defaults to C"),
+                .help("Sets the target language for the output (C, bytecode, x86_64, llvm, wat, js, py, rust, il, jvm, riscv64 or qbe), defaults to C"),
         )
         .arg(
             Arg::new("input")
@@ -37,11 +61,79 @@ fn main() {
                 .required(true)
                 .index(1),
         )
+        .subcommand(
+            Command::new("run")
+                .about("Runs a script directly, without compiling it first")
+                .arg(
+                    Arg::new("input")
+                        .help("Sets the script or compiled .mmcb file to run")
+                        .value_name("INPUT")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("vm")
+                        .long("vm")
+                        .action(ArgAction::SetTrue)
+                        .help("Runs on the bytecode VM instead of walking the syntax tree"),
+                ),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .get_matches();
 
+    if let Some(run_matches) = matches.subcommand_matches("run") {
+        let input_file = run_matches.get_one::<String>("input").unwrap();
+        let bytes = fs::read(input_file).unwrap_or_else(|e| {
+            eprintln!("error: Unable to read {}: {}", input_file, e);
+            process::exit(1);
+        });
+        let input = io::stdin().lock();
+        let output = BufWriter::new(io::stdout());
+
+        if bytes.starts_with(MAGIC) {
+            // Compiled scripts come without their source, errors only point at line and column.
+            let renderer = Renderer::without_source(input_file, stderr_supports_color());
+            let chunk = read_chunk(&bytes).unwrap_or_else(|e| {
+                eprintln!("error: Unable to load {}: {}", input_file, e);
+                process::exit(1);
+            });
+            if let Err(error) = Vm::new(&chunk, input, output).run() {
+                abort(&renderer, &[Diagnostic::from(&error)]);
+            }
+            return;
+        }
+
+        let source = String::from_utf8(bytes).unwrap_or_else(|_| {
+            eprintln!(
+                "error: Unable to read {}: it is not valid UTF-8",
+                input_file
+            );
+            process::exit(1);
+        });
+        let renderer = Renderer::new(input_file, &source, stderr_supports_color());
+        let statements = analyze(&renderer, &source);
+        let result = if run_matches.get_flag("vm") {
+            let chunk = Compiler::new().compile(&statements);
+            Vm::new(&chunk, input, output).run()
+        } else {
+            Interpreter::new(input, output).run(&statements)
+        };
+        if let Err(error) = result {
+            abort(&renderer, &[Diagnostic::from(&error)]);
+        }
+        return;
+    }
+
     let target = matches.get_one::<String>("target").unwrap();
-    if target != "C" {
-        eprintln!("Currently, only 'C' target is supported.");
+    if !TARGETS.contains(&target.as_str()) {
+        let (last, rest) = TARGETS.split_last().unwrap();
+        eprintln!(
+            "Unknown target '{}', supported are '{}' and '{}'.",
+            target,
+            rest.join("', '"),
+            last
+        );
         process::exit(1);
     }
 
@@ -49,41 +141,96 @@ fn main() {
 
     let input = fs::read_to_string(input_file).expect("Unable to read input file");
 
-    let lex = Lexer::from(&input);
+    let renderer = Renderer::new(input_file, &input, stderr_supports_color());
+    let statements = analyze(&renderer, &input);
+
+    let output_file = matches.get_one::<String>("output");
+    let code = match target.as_str() {
+        "bytecode" => {
+            let chunk = Compiler::new().compile(&statements);
+            // Bytecode is written as an .mmcb file, only the disassembly is printed.
+            if let Some(output_file) = output_file {
+                fs::write(output_file, write_chunk(&chunk, true))
+                    .expect("Unable to write output file");
+                println!("Bytecode written to {}", output_file);
+                return;
+            }
+            disassemble(&chunk)
+        }
+        "jvm" => {
+            // A class file is binary and named after its class, so it has to go to a file.
+            let Some(output_file) = output_file else {
+                eprintln!("The jvm target writes a class file, pass its path with -o.");
+                process::exit(1);
+            };
+            let class_name = Path::new(output_file)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("Main");
+            fs::write(output_file, JvmEmitter::new(&statements, class_name).emit())
+                .expect("Unable to write output file");
+            println!("Class file written to {}", output_file);
+            return;
+        }
+        "x86_64" => X86Emitter::new(&statements).emit(),
+        "llvm" => LlvmEmitter::new(&statements).emit(),
+        "wat" => WatEmitter::new(&statements).emit(),
+        "js" => JsEmitter::new(&statements).emit(),
+        "py" => PythonEmitter::new(&statements).emit(),
+        "rust" => RustEmitter::new(&statements).emit(),
+        "il" => CilEmitter::new(&statements).emit(),
+        "riscv64" => Riscv64Emitter::new(&statements).emit(),
+        "qbe" => QbeEmitter::new(&statements).emit(),
+        _ => CEmitter::new(&statements).emit(),
+    };
+
+    if let Some(output_file) = output_file {
+        fs::write(output_file, code).expect("Unable to write output file");
+        println!("Code written to {}", output_file);
+    } else {
+        println!("{}", code);
+    }
+}
+
+/// Lexes, parses and analyzes `input`, reporting every problem found and exiting if there are any.
+fn analyze(renderer: &Renderer, input: &str) -> Vec<Statement> {
+    let lex = Lexer::from(input);
     let tokens: Vec<Token> = lex.into_iter().collect();
 
-    let invalid_tokens: Vec<&Token> = tokens
+    let invalid_tokens: Vec<Diagnostic> = tokens
         .iter()
-        .filter(|t| t.kind == TokenKind::Invalid)
+        .filter(|t| matches!(t.kind, TokenKind::Invalid | TokenKind::TokenizationError))
+        .map(Diagnostic::from)
         .collect();
 
     if !invalid_tokens.is_empty() {
-        eprintln!("Found invalid tokens during lexing.");
-        eprintln!("{:?}", invalid_tokens);
-        process::exit(1);
+        abort(renderer, &invalid_tokens);
     }
 
     let parser = Parser::new(&tokens);
-    let parse_result = parser.parse();
-
-    match parse_result {
-        Ok(statements) => {
-            let emitter = CEmitter::new(&statements);
-            let code = emitter.emit();
-
-            if let Some(output_file) = matches.get_one::<String>("output") {
-                fs::write(output_file, code).expect("Unable to write output file");
-                println!("Code written to {}", output_file);
-            } else {
-                println!("{}", code);
-            }
-        }
-        Err(e) => {
-            eprintln!(
-                "Parsing error: Unexpected token:{:?}; Reason: {:?}",
-                e.token, e.reason
-            );
-            process::exit(1);
+    let mut statements = match parser.parse() {
+        Ok(statements) => statements,
+        Err(errors) => {
+            let diagnostics: Vec<Diagnostic> = errors.iter().map(Diagnostic::from).collect();
+            abort(renderer, &diagnostics);
         }
+    };
+
+    if let Err(diagnostics) = SemanticAnalyzer::new().analyze(&mut statements) {
+        abort(renderer, &diagnostics);
+    }
+    statements
+}
+
+fn abort(renderer: &Renderer, diagnostics: &[Diagnostic]) -> ! {
+    for diagnostic in diagnostics {
+        eprintln!("{}", renderer.render(diagnostic));
+    }
+    if diagnostics.len() > 1 {
+        eprintln!(
+            "error: aborting due to {} previous errors",
+            diagnostics.len()
+        );
     }
+    process::exit(1);
 }
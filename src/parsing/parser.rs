@@ -1,30 +1,38 @@
-use core::panic;
-use std::collections::HashSet;
-
-use crate::lexing::lexer::{Token, TokenKind};
+use crate::analyzing::symbol_table::Symbol;
+use crate::lexing::lexer::{Span, Token, TokenKind};
 
 pub type ParserResult<T> = std::result::Result<T, ParserError>;
 
 #[derive(Debug, Clone)]
 pub struct ParserError {
-    pub token: Option<Token>,
+    pub token: Option<Box<Token>>,
     pub expected: Option<TokenKind>,
     pub reason: Option<String>,
+    pub help: Option<String>,
+    pub span: Span,
 }
 /*
 
 program ::= {statement}
 statement ::= "print" (expression | string) sc
-    | "if" comparison openCurly {statement} closeCurly
+    | ifStatement
     | "while" comparison openCurly {statement} closeCurly
+    | "for" ("let" ident | ident) "=" expression sc comparison sc ident "=" expression
+        openCurly {statement} closeCurly
+    | ("break" | "continue") sc
     | "let" ident "=" expression sc
     | "input" ident sc
     | ident "=" expression sc
-comparison ::= expression (("==" | "!=" | ">" | ">=" | "<" | "<=") expression)+
+ifStatement ::= "if" comparison openCurly {statement} closeCurly
+    ["else" (ifStatement | openCurly {statement} closeCurly)]
+comparison ::= andComparison {"||" andComparison}
+andComparison ::= notComparison {"&&" notComparison}
+notComparison ::= "!" notComparison | "(" comparison ")" | relational
+relational ::= expression {("==" | "!=" | ">" | ">=" | "<" | "<=") expression}
 expression ::= term {( "-" | "+" ) term}
 term ::= unary {( "/" | "*" | "%") unary}
 unary ::= ["+" | "-"] primary
-primary ::= number | ident
+primary ::= number | ident | "(" expression ")"
 sc ::= ';'+
 openCurly ::= '{'
 closeCurly ::= '}'
@@ -35,80 +43,124 @@ closeCurly ::= '}'
 pub enum Statement {
     Print {
         option: PrintOption,
+        span: Span,
     },
     If {
         comparison: Comparison,
         statements: Vec<Statement>,
+        /// An `else if` is stored as an else block holding a single `If`.
+        else_statements: Option<Vec<Statement>>,
+        span: Span,
     },
 
     While {
         comparison: Comparison,
         statements: Vec<Statement>,
+        span: Span,
     },
 
     Let {
         identifier: Identifier,
         expression: Expression,
+        span: Span,
+    },
+    For {
+        /// Either a `Let` or an `Assign`.
+        init: Box<Statement>,
+        comparison: Comparison,
+        /// Always an `Assign`.
+        step: Box<Statement>,
+        statements: Vec<Statement>,
+        span: Span,
+    },
+    Break {
+        span: Span,
+    },
+    Continue {
+        span: Span,
     },
     Input {
         identifier: Identifier,
+        span: Span,
     },
     Assign {
         identifier: Identifier,
         expression: Expression,
+        span: Span,
     },
 }
+/// Condition of an `if`, `while` or `for`. `&&` and `||` short-circuit.
 #[derive(Debug, Clone)]
 pub enum Comparison {
-    GreaterThan { lhs: Expression, rhs: Expression },
-    GreaterThanEquals { lhs: Expression, rhs: Expression },
-    LessThan { lhs: Expression, rhs: Expression },
-    LessThanEquals { lhs: Expression, rhs: Expression },
-    EqualsEquals { lhs: Expression, rhs: Expression },
-    NotEquals { lhs: Expression, rhs: Expression },
+    /// `0 <= i < n` is stored as `0` followed by `[(<=, i), (<, n)]` and means
+    /// `0 <= i && i < n`, with `i` evaluated only once.
+    Relational {
+        lhs: Expression,
+        rhs: Vec<(RelationalOp, Expression)>,
+    },
+    /// A bare expression, true when it is not zero.
+    Value(Expression),
+    Not(Box<Comparison>),
+    And(Box<Comparison>, Box<Comparison>),
+    Or(Box<Comparison>, Box<Comparison>),
 }
 
-#[derive(Debug, Clone)]
-pub struct Expression {
-    pub lhs: Term,
-    pub rhs: Box<Option<ExpressionOp>>,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelationalOp {
+    GreaterThan,
+    GreaterThanEquals,
+    LessThan,
+    LessThanEquals,
+    EqualsEquals,
+    NotEquals,
 }
 
+/// Binary operators are left-associative, `a - b - c` is stored as `(a - b) - c`.
 #[derive(Debug, Clone)]
-pub enum ExpressionOp {
-    Plus(Expression),
-    Minus(Expression),
-}
-
-#[derive(Debug, Clone)]
-pub struct Term {
-    pub lhs: Unary,
-    pub rhs: Box<Option<TermOp>>,
+pub enum Expression {
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expression>,
+        rhs: Box<Expression>,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Primary,
+        span: Span,
+    },
+    Primary(Primary),
 }
 
-#[derive(Debug, Clone)]
-pub enum TermOp {
-    Multiply(Term),
-    Divide(Term),
-    Modulo(Term),
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
 }
 
-#[derive(Debug, Clone)]
-pub enum Unary {
-    Positive(Primary),
-    Negative(Primary),
-    UnSigned(Primary),
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
 }
 
 #[derive(Debug, Clone)]
 pub enum Primary {
-    LiteralNumber(i64),
+    LiteralNumber(i64, Span),
     IdentifierExpression(Identifier),
+    /// A parenthesised expression, the span includes the parentheses.
+    Grouped(Box<Expression>, Span),
 }
 
 #[derive(Debug, Clone)]
 pub struct Identifier {
     pub id: String,
+    pub span: Span,
+    /// The declaration this identifier resolves to, filled in by semantic analysis.
+    pub symbol: Option<Symbol>,
 }
 
 #[derive(Debug, Clone)]
@@ -117,11 +169,95 @@ pub enum PrintOption {
     PrintExpression(Expression),
 }
 
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Print { span, .. }
+            | Statement::If { span, .. }
+            | Statement::While { span, .. }
+            | Statement::For { span, .. }
+            | Statement::Break { span }
+            | Statement::Continue { span }
+            | Statement::Let { span, .. }
+            | Statement::Input { span, .. }
+            | Statement::Assign { span, .. } => *span,
+        }
+    }
+}
+
+impl RelationalOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            RelationalOp::GreaterThan => ">",
+            RelationalOp::GreaterThanEquals => ">=",
+            RelationalOp::LessThan => "<",
+            RelationalOp::LessThanEquals => "<=",
+            RelationalOp::EqualsEquals => "==",
+            RelationalOp::NotEquals => "!=",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulo => "%",
+        }
+    }
+
+    /// Higher binds tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Subtract => 1,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo => 2,
+        }
+    }
+}
+
+impl UnaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+        }
+    }
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Binary { span, .. } | Expression::Unary { span, .. } => *span,
+            Expression::Primary(primary) => primary.span(),
+        }
+    }
+}
+
+impl Identifier {
+    /// Name unique to the declaration, backends should use this rather than `id`.
+    pub fn name(&self) -> &str {
+        self.symbol.as_ref().map_or(&self.id, |symbol| &symbol.name)
+    }
+}
+
+impl Primary {
+    pub fn span(&self) -> Span {
+        match self {
+            Primary::LiteralNumber(_, span) | Primary::Grouped(_, span) => *span,
+            Primary::IdentifierExpression(identifier) => identifier.span,
+        }
+    }
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
-    identifiers: HashSet<String>,
     statements: Vec<Statement>,
+    errors: Vec<ParserError>,
+    loop_depth: usize,
 }
 
 impl Parser {
@@ -132,31 +268,111 @@ impl Parser {
             .filter(|t| t.kind != TokenKind::Whitespace)
             .cloned()
             .collect();
-        let identifiers: HashSet<String> = HashSet::new();
         let statements: Vec<Statement> = Vec::new();
         Self {
             tokens,
             current: 0,
-            identifiers,
             statements,
+            errors: Vec::new(),
+            loop_depth: 0,
         }
     }
 
-    pub fn parse(mut self) -> ParserResult<Vec<Statement>> {
+    pub fn parse(self) -> Result<Vec<Statement>, Vec<ParserError>> {
+        let (statements, errors) = self.parse_partial();
+        if errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses the whole program, recovering from syntax errors instead of stopping at the first.
+    /// Statements that could not be parsed are left out of the returned (partial) program.
+    pub fn parse_partial(mut self) -> (Vec<Statement>, Vec<ParserError>) {
         while let Some(token) = self.current_token() {
             match token.kind {
                 TokenKind::Eof => break,
                 _ => {
-                    let statement = self.match_statement()?;
-                    self.statements.push(statement)
+                    if let Some(statement) = self.match_statement_or_recover() {
+                        self.statements.push(statement)
+                    }
+                }
+            }
+        }
+
+        (self.statements, self.errors)
+    }
+
+    fn match_statement_or_recover(&mut self) -> Option<Statement> {
+        let start = self.current;
+        match self.match_statement() {
+            Ok(statement) => Some(statement),
+            Err(error) => {
+                self.errors.push(error);
+                if self.current == start {
+                    self.advance_token();
+                }
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    /// Skips tokens until the start of the next statement: right after a `;`, before a statement
+    /// keyword or before the `}` closing the current block. Nested blocks are skipped as a whole.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.current_token() {
+            match token.kind {
+                TokenKind::SemiColon if depth == 0 => {
+                    self.advance_token();
+                    return;
+                }
+                TokenKind::CloseCurly if depth == 0 => return,
+                TokenKind::Print
+                | TokenKind::If
+                | TokenKind::While
+                | TokenKind::For
+                | TokenKind::Break
+                | TokenKind::Continue
+                | TokenKind::Let
+                | TokenKind::Input
+                    if depth == 0 =>
+                {
+                    return
+                }
+                TokenKind::OpenCurly => depth += 1,
+                TokenKind::CloseCurly => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance_token();
+                        // A skipped if block drags its else branches along.
+                        if !self.is_current_token(TokenKind::Else) {
+                            return;
+                        }
+                    }
                 }
+                _ => {}
             }
+            self.advance_token();
         }
+    }
 
-        Ok(self.statements)
+    fn match_block(&mut self) -> ParserResult<Vec<Statement>> {
+        let mut statements = Vec::new();
+        self.match_token(TokenKind::OpenCurly)?;
+        while !self.is_current_token(TokenKind::CloseCurly) && self.current_token().is_some() {
+            if let Some(statement) = self.match_statement_or_recover() {
+                statements.push(statement);
+            }
+        }
+        self.match_token(TokenKind::CloseCurly)?;
+        Ok(statements)
     }
 
     fn match_statement(&mut self) -> ParserResult<Statement> {
+        let start = self.current_span();
         if self.is_current_token(TokenKind::Print) {
             self.advance_token();
             let option = {
@@ -176,154 +392,192 @@ impl Parser {
                 }
             };
             self.match_token(TokenKind::SemiColon)?;
-            Ok(Statement::Print { option })
+            Ok(Statement::Print {
+                option,
+                span: start.to(self.previous_span()),
+            })
         } else if self.is_current_token(TokenKind::If) {
             self.advance_token();
-            let mut statements = Vec::new();
             let comparison = self.match_comparison()?;
-            self.match_token(TokenKind::OpenCurly)?;
-            while !self.is_current_token(TokenKind::CloseCurly) {
-                let statement = self.match_statement()?;
-                statements.push(statement);
-            }
-            self.match_token(TokenKind::CloseCurly)?;
+            let statements = self.match_block()?;
+            let else_statements = if self.is_current_token(TokenKind::Else) {
+                self.advance_token();
+                if self.is_current_token(TokenKind::If) {
+                    Some(vec![self.match_statement()?])
+                } else {
+                    Some(self.match_block()?)
+                }
+            } else {
+                None
+            };
 
-            return Ok(Statement::If {
+            Ok(Statement::If {
                 comparison,
                 statements,
-            });
+                else_statements,
+                span: start.to(self.previous_span()),
+            })
         } else if self.is_current_token(TokenKind::While) {
             self.advance_token();
 
-            let mut statements = Vec::new();
             let comparison = self.match_comparison()?;
-            self.match_token(TokenKind::OpenCurly)?;
-            while !self.is_current_token(TokenKind::CloseCurly) {
-                let statement = self.match_statement()?;
-                statements.push(statement);
-            }
-            self.match_token(TokenKind::CloseCurly)?;
+            self.loop_depth += 1;
+            let statements = self.match_block();
+            self.loop_depth -= 1;
+            let statements = statements?;
 
-            return Ok(Statement::While {
+            Ok(Statement::While {
                 comparison,
                 statements,
-            });
-        } else if self.is_current_token(TokenKind::Let) {
+                span: start.to(self.previous_span()),
+            })
+        } else if self.is_current_token(TokenKind::For) {
             self.advance_token();
-            let identifier = self.match_identifier()?;
-            if self.identifiers.contains(&identifier.id) {
+            let init = if self.is_current_token(TokenKind::Let)
+                || self.is_current_token(TokenKind::Identifier)
+            {
+                self.match_statement()?
+            } else {
+                return Err(ParserError {
+                    token: self.current_token().cloned().map(Box::new),
+                    expected: None,
+                    reason: Some("Expected `let` or an assignment".to_string()),
+                    help: Some(
+                        "for loops look like `for let i = 0; i < n; i = i + 1 { ... }`".to_string(),
+                    ),
+                    span: self.current_span(),
+                });
+            };
+            let comparison = self.match_comparison()?;
+            self.match_token(TokenKind::SemiColon)?;
+            let step_start = self.current_span();
+            let (identifier, expression) = self.match_assignment()?;
+            let step = Statement::Assign {
+                identifier,
+                expression,
+                span: step_start.to(self.previous_span()),
+            };
+            self.loop_depth += 1;
+            let statements = self.match_block();
+            self.loop_depth -= 1;
+            let statements = statements?;
+
+            Ok(Statement::For {
+                init: Box::new(init),
+                comparison,
+                step: Box::new(step),
+                statements,
+                span: start.to(self.previous_span()),
+            })
+        } else if self.is_current_token(TokenKind::Break)
+            || self.is_current_token(TokenKind::Continue)
+        {
+            let token = self
+                .current_token()
+                .expect("Expected current token")
+                .clone();
+            let kind = token.kind;
+            self.advance_token();
+            self.match_token(TokenKind::SemiColon)?;
+            let span = start.to(self.previous_span());
+            if self.loop_depth == 0 {
                 return Err(ParserError {
-                    token: self.current_token().cloned(),
+                    token: Some(Box::new(token)),
                     expected: None,
-                    reason: Some(format!("Identifier {} is already declared", identifier.id)),
+                    reason: Some(format!("{} outside of a loop", kind)),
+                    help: Some(format!(
+                        "{} can only be used inside a `while` or `for` loop",
+                        kind
+                    )),
+                    span,
                 });
+            }
+            if kind == TokenKind::Break {
+                Ok(Statement::Break { span })
             } else {
-                self.identifiers.insert(identifier.id.clone());
+                Ok(Statement::Continue { span })
             }
+        } else if self.is_current_token(TokenKind::Let) {
+            self.advance_token();
+            let identifier = self.match_identifier()?;
             self.match_token(TokenKind::Equals)?;
             let expression = self.match_expression()?;
             self.match_token(TokenKind::SemiColon)?;
             Ok(Statement::Let {
                 identifier,
                 expression,
+                span: start.to(self.previous_span()),
             })
         } else if self.is_current_token(TokenKind::Input) {
             self.advance_token();
             let identifier = self.match_identifier()?;
-            if self.identifiers.contains(&identifier.id) {
-                return Err(ParserError {
-                    token: self.current_token().cloned(),
-                    expected: None,
-                    reason: Some(format!("Identifier {} is already declared", identifier.id)),
-                });
-            } else {
-                self.identifiers.insert(identifier.id.clone());
-            }
             self.match_token(TokenKind::SemiColon)?;
-            Ok(Statement::Input { identifier })
+            Ok(Statement::Input {
+                identifier,
+                span: start.to(self.previous_span()),
+            })
         } else if self.is_current_token(TokenKind::Identifier) {
-            let identifier = self.match_identifier()?;
-            if !self.identifiers.contains(&identifier.id) {
-                return Err(ParserError {
-                    token: self.current_token().cloned(),
-                    expected: None,
-                    reason: Some(format!("Identifier {} is never declared", identifier.id)),
-                });
-            }
-            self.match_token(TokenKind::Equals)?;
-            let expression = self.match_expression()?;
+            let (identifier, expression) = self.match_assignment()?;
             self.match_token(TokenKind::SemiColon)?;
             Ok(Statement::Assign {
                 identifier,
                 expression,
+                span: start.to(self.previous_span()),
+            })
+        } else if self.is_current_token(TokenKind::Else) {
+            Err(ParserError {
+                token: self.current_token().cloned().map(Box::new),
+                expected: None,
+                reason: Some("Found `else` without a matching `if`".to_string()),
+                help: None,
+                span: self.current_span(),
             })
         } else {
-            return Err(ParserError {
-                token: self.current_token().cloned(),
+            Err(ParserError {
+                token: self.current_token().cloned().map(Box::new),
                 expected: None,
                 reason: Some("Unknown statement".to_string()),
-            });
+                help: None,
+                span: self.current_span(),
+            })
         }
     }
 
+    fn match_assignment(&mut self) -> ParserResult<(Identifier, Expression)> {
+        let identifier = self.match_identifier()?;
+        self.match_token(TokenKind::Equals)?;
+        let expression = self.match_expression()?;
+        Ok((identifier, expression))
+    }
+
     fn match_expression(&mut self) -> ParserResult<Expression> {
-        let lhs = self.match_term()?;
-        let token = {
-            if self.is_current_plus_minus_token() {
-                Some(self.match_plus_minus_token()?)
-            } else {
-                None
-            }
-        };
-        let rhs = match token {
-            Some(TokenKind::Plus) => {
-                let exp = self.match_expression()?;
-                Some(ExpressionOp::Plus(exp))
-            }
-            Some(TokenKind::Minus) => {
-                let exp = self.match_expression()?;
-                Some(ExpressionOp::Minus(exp))
-            }
-            Some(_) => panic!("Should not come here"),
-            None => None,
-        };
-        Ok(Expression {
-            lhs,
-            rhs: Box::new(rhs),
-        })
+        self.match_binary(0)
     }
 
-    fn match_term(&mut self) -> ParserResult<Term> {
-        let lhs = self.match_unary()?;
-        let token = {
-            if self.is_current_multiplicative_token() {
-                Some(self.match_multiplicative_token()?)
-            } else {
-                None
+    /// Precedence climbing: only operators binding at least as tight as `min_precedence` are
+    /// taken, the right hand side only takes strictly tighter ones, which makes every operator
+    /// left-associative.
+    fn match_binary(&mut self, min_precedence: u8) -> ParserResult<Expression> {
+        let mut lhs = self.match_unary()?;
+        while let Some(op) = self.current_binary_op() {
+            if op.precedence() < min_precedence {
+                break;
             }
-        };
-        let rhs = match token {
-            Some(TokenKind::Asterisk) => {
-                let term = self.match_term()?;
-                Some(TermOp::Multiply(term))
-            }
-            Some(TokenKind::Slash) => {
-                let term = self.match_term()?;
-                Some(TermOp::Divide(term))
-            }
-            Some(TokenKind::Modulo) => {
-                let term = self.match_term()?;
-                Some(TermOp::Modulo(term))
-            }
-            Some(_) => panic!("Should not come here"),
-            None => None,
-        };
-        Ok(Term {
-            lhs,
-            rhs: Box::new(rhs),
-        })
+            self.advance_token();
+            let rhs = self.match_binary(op.precedence() + 1)?;
+            let span = lhs.span().to(rhs.span());
+            lhs = Expression::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+                span,
+            };
+        }
+        Ok(lhs)
     }
-    fn match_unary(&mut self) -> ParserResult<Unary> {
+
+    fn match_unary(&mut self) -> ParserResult<Expression> {
+        let start = self.current_span();
         let token = {
             if self.is_current_plus_minus_token() {
                 Some(self.match_plus_minus_token()?)
@@ -332,83 +586,145 @@ impl Parser {
             }
         };
         let primary = self.match_primary()?;
+        let span = start.to(primary.span());
         match token {
-            Some(TokenKind::Plus) => Ok(Unary::Positive(primary)),
-            Some(TokenKind::Minus) => Ok(Unary::Negative(primary)),
+            Some(TokenKind::Plus) => Ok(Expression::Unary {
+                op: UnaryOp::Plus,
+                operand: primary,
+                span,
+            }),
+            Some(TokenKind::Minus) => Ok(Expression::Unary {
+                op: UnaryOp::Minus,
+                operand: primary,
+                span,
+            }),
             Some(_) => panic!("Should not come here"),
-            None => Ok(Unary::UnSigned(primary)),
+            None => Ok(Expression::Primary(primary)),
         }
     }
     fn match_primary(&mut self) -> ParserResult<Primary> {
         if self.is_current_token(TokenKind::Identifier) {
             let identifier = self.match_identifier()?;
-            if !self.identifiers.contains(&identifier.id) {
-                return Err(ParserError {
-                    token: self.current_token().cloned(),
-                    expected: None,
-                    reason: Some(format!("Identifier {} is never declared", &identifier.id)),
-                });
-            } else {
-                Ok(Primary::IdentifierExpression(identifier))
-            }
+            Ok(Primary::IdentifierExpression(identifier))
+        } else if self.is_current_token(TokenKind::OpenParen) {
+            let start = self.current_span();
+            self.advance_token();
+            let expression = self.match_expression()?;
+            self.match_token(TokenKind::CloseParen)?;
+            Ok(Primary::Grouped(
+                Box::new(expression),
+                start.to(self.previous_span()),
+            ))
         } else if self.is_current_literal_number() {
             if let TokenKind::LiteralNumber(nr) =
                 self.current_token().expect("Expected current token").kind
             {
+                let span = self.current_span();
                 self.advance_token();
-                return Ok(Primary::LiteralNumber(nr));
+                Ok(Primary::LiteralNumber(nr, span))
             } else {
                 panic!("Should not come here");
             }
         } else {
-            return Err(ParserError {
-                token: self.current_token().cloned(),
+            Err(ParserError {
+                token: self.current_token().cloned().map(Box::new),
                 expected: Some(TokenKind::Identifier),
-                reason: Some("Expected Identifier or literal nr".to_string()),
-            });
+                reason: Some("Expected Identifier, literal nr or `(`".to_string()),
+                help: None,
+                span: self.current_span(),
+            })
         }
     }
 
     fn match_comparison(&mut self) -> ParserResult<Comparison> {
+        let mut lhs = self.match_and_comparison()?;
+        while self.is_current_token(TokenKind::OrOr) {
+            self.advance_token();
+            let rhs = self.match_and_comparison()?;
+            lhs = Comparison::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn match_and_comparison(&mut self) -> ParserResult<Comparison> {
+        let mut lhs = self.match_not_comparison()?;
+        while self.is_current_token(TokenKind::AndAnd) {
+            self.advance_token();
+            let rhs = self.match_not_comparison()?;
+            lhs = Comparison::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn match_not_comparison(&mut self) -> ParserResult<Comparison> {
+        if self.is_current_token(TokenKind::Not) {
+            self.advance_token();
+            let comparison = self.match_not_comparison()?;
+            return Ok(Comparison::Not(Box::new(comparison)));
+        }
+        if self.is_current_token(TokenKind::OpenParen) {
+            // `(` opens either a nested condition or a grouped expression, try the condition first.
+            let start = self.current;
+            if let Ok(comparison) = self.match_grouped_comparison() {
+                if !self.is_current_comparison_token() && self.current_binary_op().is_none() {
+                    return Ok(comparison);
+                }
+            }
+            self.current = start;
+        }
+        self.match_relational()
+    }
+
+    fn match_grouped_comparison(&mut self) -> ParserResult<Comparison> {
+        self.match_token(TokenKind::OpenParen)?;
+        let comparison = self.match_comparison()?;
+        self.match_token(TokenKind::CloseParen)?;
+        Ok(comparison)
+    }
+
+    fn match_relational(&mut self) -> ParserResult<Comparison> {
         let lhs = self.match_expression()?;
-        if self.is_current_comparison_token() {
+        let mut rhs = Vec::new();
+        while self.is_current_comparison_token() {
             let token = self.match_comparison_token()?;
-            let rhs = self.match_expression()?;
-            match token {
-                TokenKind::GreaterThan => Ok(Comparison::GreaterThan { lhs, rhs }),
-                TokenKind::GreaterThanEquals => Ok(Comparison::GreaterThanEquals { lhs, rhs }),
-                TokenKind::LessThan => Ok(Comparison::LessThan { lhs, rhs }),
-                TokenKind::LessThanEquals => Ok(Comparison::LessThanEquals { lhs, rhs }),
-                TokenKind::EqualsEquals => Ok(Comparison::EqualsEquals { lhs, rhs }),
-                TokenKind::NotEquals => Ok(Comparison::NotEquals { lhs, rhs }),
+            let operand = self.match_expression()?;
+            let op = match token {
+                TokenKind::GreaterThan => RelationalOp::GreaterThan,
+                TokenKind::GreaterThanEquals => RelationalOp::GreaterThanEquals,
+                TokenKind::LessThan => RelationalOp::LessThan,
+                TokenKind::LessThanEquals => RelationalOp::LessThanEquals,
+                TokenKind::EqualsEquals => RelationalOp::EqualsEquals,
+                TokenKind::NotEquals => RelationalOp::NotEquals,
                 _ => panic!("Should not come here"),
-            }
+            };
+            rhs.push((op, operand));
+        }
+        if rhs.is_empty() {
+            Ok(Comparison::Value(lhs))
         } else {
-            return Err(ParserError {
-                token: self.current_token().cloned(),
-                expected: None,
-                reason: Some("Expected comparison operator".to_string()),
-            });
+            Ok(Comparison::Relational { lhs, rhs })
         }
-
-        // TODO comparisons can be chained.
-        // while self.is_current_comparison_token() {
-        //     self.advance_token();
-        //     self.match_expression()?;
-        // }
     }
 
     fn match_identifier(&mut self) -> ParserResult<Identifier> {
         if self.is_current_token(TokenKind::Identifier) {
-            let id = self.current_token().unwrap().data.raw.clone();
+            let token = self.current_token().unwrap();
+            let id = token.data.raw.clone();
+            let span = token.span();
             self.advance_token();
-            return Ok(Identifier { id });
+            return Ok(Identifier {
+                id,
+                span,
+                symbol: None,
+            });
         }
 
         Err(ParserError {
-            token: self.current_token().cloned(),
+            token: self.current_token().cloned().map(Box::new),
             expected: Some(TokenKind::Identifier),
             reason: Some("Expected Identifier".to_string()),
+            help: None,
+            span: self.current_span(),
         })
     }
 
@@ -418,9 +734,11 @@ impl Parser {
             Ok(())
         } else {
             Err(ParserError {
-                token: self.current_token().cloned(),
+                token: self.current_token().cloned().map(Box::new),
                 expected: Some(token_kind),
-                reason: Some(format!("Expected {:?}", token_kind)),
+                reason: Some(format!("Expected {}", token_kind)),
+                help: None,
+                span: self.current_span(),
             })
         }
     }
@@ -435,6 +753,8 @@ impl Parser {
                 token: None,
                 expected: None,
                 reason: Some("Expected comparison token".to_string()),
+                help: None,
+                span: self.current_span(),
             })
         }
     }
@@ -449,30 +769,23 @@ impl Parser {
                 token: None,
                 expected: None,
                 reason: Some("Expected signed token".to_string()),
+                help: None,
+                span: self.current_span(),
             })
         }
     }
 
-    fn match_multiplicative_token(&mut self) -> ParserResult<TokenKind> {
-        if self.is_current_multiplicative_token() {
-            let kind = self.current_token().unwrap().kind;
-            self.advance_token();
-            Ok(kind)
-        } else {
-            Err(ParserError {
-                token: None,
-                expected: None,
-                reason: Some("Expected *,/ or % token".to_string()),
-            })
+    fn current_binary_op(&self) -> Option<BinaryOp> {
+        match self.current_token()?.kind {
+            TokenKind::Plus => Some(BinaryOp::Add),
+            TokenKind::Minus => Some(BinaryOp::Subtract),
+            TokenKind::Asterisk => Some(BinaryOp::Multiply),
+            TokenKind::Slash => Some(BinaryOp::Divide),
+            TokenKind::Modulo => Some(BinaryOp::Modulo),
+            _ => None,
         }
     }
 
-    fn is_current_multiplicative_token(&self) -> bool {
-        self.is_current_token(TokenKind::Asterisk)
-            || self.is_current_token(TokenKind::Slash)
-            || self.is_current_token(TokenKind::Modulo)
-    }
-
     fn is_current_plus_minus_token(&self) -> bool {
         self.is_current_token(TokenKind::Plus) || self.is_current_token(TokenKind::Minus)
     }
@@ -503,6 +816,28 @@ impl Parser {
         self.tokens.get(self.current)
     }
 
+    /// Span of the current token, or an empty span right after the last token once we ran out.
+    fn current_span(&self) -> Span {
+        match self.current_token() {
+            Some(t) => t.span(),
+            None => {
+                let end = self.previous_span();
+                Span {
+                    start: end.end,
+                    column: end.column + (end.end - end.start),
+                    ..end
+                }
+            }
+        }
+    }
+
+    fn previous_span(&self) -> Span {
+        match self.current.checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some(t) => t.span(),
+            None => Span::default(),
+        }
+    }
+
     fn advance_token(&mut self) {
         self.current += 1;
     }
@@ -510,7 +845,7 @@ impl Parser {
 
 #[cfg(test)]
 mod tests {
-    use crate::lexing::lexer::TokenData;
+    use crate::lexing::lexer::{Lexer, TokenData};
 
     use super::*;
 
@@ -521,13 +856,18 @@ mod tests {
             $(
                 Token {
                     kind: $kind,
-                    data: TokenData { raw: "".into() },
+                    data: TokenData { raw: "".into(), span: Span::default() },
                 },
             )*
         ]
     };
 }
 
+    fn parse_source(source: &str) -> Result<Vec<Statement>, Vec<ParserError>> {
+        let tokens: Vec<Token> = Lexer::from(source).collect();
+        Parser::new(&tokens).parse()
+    }
+
     //TODO:
     //- Add support for passing Identifier name
     //- Test expression
@@ -676,4 +1016,322 @@ mod tests {
         let res = parser.parse();
         assert!(res.is_ok(), "Test failed with error: {:?}", res);
     }
+
+    #[test]
+    fn check_if_nodes_carry_spans() {
+        let statements = parse_source("let a = 1;\nwhile a < 10 {\n  a = a + 2 * a;\n}").unwrap();
+        assert_eq!(statements[0].span(), Span::new(0, 10, 1, 1));
+        match &statements[1] {
+            Statement::While {
+                statements, span, ..
+            } => {
+                assert_eq!(*span, Span::new(11, 44, 2, 1));
+                match &statements[0] {
+                    Statement::Assign {
+                        identifier,
+                        expression,
+                        span,
+                    } => {
+                        assert_eq!(*span, Span::new(28, 42, 3, 3));
+                        assert_eq!(identifier.span, Span::new(28, 29, 3, 3));
+                        assert_eq!(expression.span(), Span::new(32, 41, 3, 7));
+                        match expression {
+                            Expression::Binary { lhs, rhs, .. } => {
+                                assert_eq!(lhs.span(), Span::new(32, 33, 3, 7));
+                                assert_eq!(rhs.span(), Span::new(36, 41, 3, 11));
+                            }
+                            e => panic!("Expected binary expression, got {:?}", e),
+                        }
+                    }
+                    s => panic!("Expected assignment, got {:?}", s),
+                }
+            }
+            s => panic!("Expected while, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_errors_carry_spans() {
+        let errors = parse_source("let a = 1;\nprint a +;").unwrap_err();
+        assert_eq!(errors[0].span, Span::new(20, 21, 2, 10));
+
+        let errors = parse_source("let a = 1").unwrap_err();
+        assert_eq!(errors[0].expected, Some(TokenKind::SemiColon));
+        assert_eq!(errors[0].span, Span::new(9, 9, 1, 10));
+    }
+
+    #[test]
+    fn check_if_reports_all_errors() {
+        let source =
+            "let a = 1;\nprint a\nlet b = ;\nwhile a < 3 {\n  a = a + ;\n  print a;\n}\nprint a;";
+        let tokens: Vec<Token> = Lexer::from(source).collect();
+        let (statements, errors) = Parser::new(&tokens).parse_partial();
+
+        let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+        assert_eq!(lines, vec![3, 3, 5]);
+        assert_eq!(errors[0].expected, Some(TokenKind::SemiColon));
+
+        // The broken statements are dropped, everything else is kept.
+        assert_eq!(statements.len(), 3);
+        match &statements[1] {
+            Statement::While { statements, .. } => assert_eq!(statements.len(), 1),
+            s => panic!("Expected while, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_recovery_skips_broken_blocks() {
+        let source = "if 1 > { print 1; }\n}\nprint 2;\nwhile 1 < 2 { print 3;";
+        let tokens: Vec<Token> = Lexer::from(source).collect();
+        let (statements, errors) = Parser::new(&tokens).parse_partial();
+
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].span.line, 1);
+        assert_eq!(errors[1].reason, Some("Unknown statement".to_string()));
+        assert_eq!(errors[2].expected, Some(TokenKind::CloseCurly));
+        assert_eq!(statements.len(), 1);
+    }
+
+    #[test]
+    fn check_if_handles_else_branches() {
+        let source = "let y = 8;\nif y % 4 == 0 {\n  print \"multiple of 4\";\n} else if y == 10 {\n  print \"ten\";\n} else {\n  print \"other\";\n}";
+        let statements = parse_source(source).unwrap();
+        match &statements[1] {
+            Statement::If {
+                statements,
+                else_statements: Some(else_statements),
+                span,
+                ..
+            } => {
+                assert_eq!(statements.len(), 1);
+                assert_eq!(span.end, source.len());
+                match else_statements.as_slice() {
+                    [Statement::If {
+                        else_statements: Some(last),
+                        ..
+                    }] => assert_eq!(last.len(), 1),
+                    s => panic!("Expected else if, got {:?}", s),
+                }
+            }
+            s => panic!("Expected if with else, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_rejects_dangling_else() {
+        let errors = parse_source("let x = 1;\nelse {\n  print x;\n}\nprint x;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].reason,
+            Some("Found `else` without a matching `if`".to_string())
+        );
+    }
+
+    #[test]
+    fn check_if_handles_break_and_continue_in_loops() {
+        let source = "let y = 20;\nwhile y > 0 {\n  y = y - 1;\n  if y == 5 {\n    break;\n  }\n  continue;\n}";
+        let statements = parse_source(source).unwrap();
+        match &statements[1] {
+            Statement::While { statements, .. } => {
+                assert!(matches!(statements[2], Statement::Continue { .. }));
+                match &statements[1] {
+                    Statement::If { statements, .. } => {
+                        assert!(matches!(statements[0], Statement::Break { .. }))
+                    }
+                    s => panic!("Expected if, got {:?}", s),
+                }
+            }
+            s => panic!("Expected while, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_rejects_break_outside_loop() {
+        let source = "let y = 1;\nif y > 0 {\n  break;\n}\ncontinue;\nwhile y > 0 { y = 0; }";
+        let errors = parse_source(source).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].reason,
+            Some("`break` outside of a loop".to_string())
+        );
+        assert_eq!(errors[0].span, Span::new(24, 30, 3, 3));
+        assert_eq!(
+            errors[1].reason,
+            Some("`continue` outside of a loop".to_string())
+        );
+    }
+
+    #[test]
+    fn check_if_handles_for() {
+        let source = "input depth;\nfor let i = 0; i < depth; i = i + 1 {\n  print i;\n  break;\n}";
+        let statements = parse_source(source).unwrap();
+        match &statements[1] {
+            Statement::For {
+                init,
+                step,
+                statements,
+                span,
+                ..
+            } => {
+                assert!(matches!(**init, Statement::Let { .. }));
+                assert!(matches!(**step, Statement::Assign { .. }));
+                assert_eq!(step.span(), Span::new(39, 48, 2, 27));
+                assert_eq!(statements.len(), 2);
+                assert_eq!(span.end, source.len());
+            }
+            s => panic!("Expected for, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_parses_undeclared_identifiers() {
+        // Name resolution is left to semantic analysis.
+        let statements = parse_source("print a;\nb = a;\nlet a = 1;\nlet a = 2;").unwrap();
+        assert_eq!(statements.len(), 4);
+        match &statements[1] {
+            Statement::Assign { identifier, .. } => assert!(identifier.symbol.is_none()),
+            s => panic!("Expected assignment, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_parses_data_3() {
+        let res = parse_source(include_str!("../../data/3.scrpt"));
+        assert!(res.is_ok(), "Test failed with error: {:?}", res);
+    }
+
+    /// Renders an expression in prefix notation to make the tree shape easy to assert on.
+    fn prefix(expression: &Expression) -> String {
+        let primary = |p: &Primary| match p {
+            Primary::LiteralNumber(n, _) => n.to_string(),
+            Primary::IdentifierExpression(id) => id.id.clone(),
+            Primary::Grouped(e, _) => prefix(e),
+        };
+        match expression {
+            Expression::Binary { op, lhs, rhs, .. } => {
+                format!("({} {} {})", op.symbol(), prefix(lhs), prefix(rhs))
+            }
+            Expression::Unary { op, operand, .. } => {
+                format!("({} {})", op.symbol(), primary(operand))
+            }
+            Expression::Primary(p) => primary(p),
+        }
+    }
+
+    fn parse_printed_expression(source: &str) -> Expression {
+        let statements = parse_source(source).unwrap();
+        match statements.last() {
+            Some(Statement::Print {
+                option: PrintOption::PrintExpression(expression),
+                ..
+            }) => expression.clone(),
+            s => panic!("Expected print, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_handles_grouped_expressions() {
+        let expression = parse_printed_expression("let a = 1;\nprint (a + 2) * -(3);");
+        assert_eq!(prefix(&expression), "(* (+ a 2) (- 3))");
+        match expression {
+            Expression::Binary { lhs, .. } => {
+                assert!(matches!(*lhs, Expression::Primary(Primary::Grouped(..))));
+                assert_eq!(lhs.span(), Span::new(17, 24, 2, 7));
+            }
+            e => panic!("Expected binary expression, got {:?}", e),
+        }
+
+        let errors = parse_source("print (1 + 2;").unwrap_err();
+        assert_eq!(errors[0].expected, Some(TokenKind::CloseParen));
+    }
+
+    #[test]
+    fn check_if_binary_operators_are_left_associative() {
+        let expression = parse_printed_expression("print 10 - 4 - 3;");
+        assert_eq!(prefix(&expression), "(- (- 10 4) 3)");
+
+        let expression = parse_printed_expression("print 64 / 8 / 2 % 3;");
+        assert_eq!(prefix(&expression), "(% (/ (/ 64 8) 2) 3)");
+
+        let expression = parse_printed_expression("print 1 + 2 * 3 - -4 / 5 + 6;");
+        assert_eq!(prefix(&expression), "(+ (- (+ 1 (* 2 3)) (/ (- 4) 5)) 6)");
+
+        let expression = parse_printed_expression("print 1 - (2 - 3);");
+        assert_eq!(prefix(&expression), "(- 1 (- 2 3))");
+    }
+
+    #[test]
+    fn check_if_handles_logical_operators() {
+        let source = "let x = 1;\nlet y = 2;\nlet done = 0;\nif x > 0 && y < 10 || !done {\n}";
+        let statements = parse_source(source).unwrap();
+        match &statements[3] {
+            Statement::If { comparison, .. } => match comparison {
+                Comparison::Or(lhs, rhs) => {
+                    match lhs.as_ref() {
+                        Comparison::And(a, b) => {
+                            assert!(matches!(
+                                a.as_ref(),
+                                Comparison::Relational { rhs, .. }
+                                    if rhs[0].0 == RelationalOp::GreaterThan
+                            ));
+                            assert!(matches!(
+                                b.as_ref(),
+                                Comparison::Relational { rhs, .. }
+                                    if rhs[0].0 == RelationalOp::LessThan
+                            ));
+                        }
+                        c => panic!("Expected &&, got {:?}", c),
+                    }
+                    match rhs.as_ref() {
+                        Comparison::Not(c) => assert!(matches!(c.as_ref(), Comparison::Value(_))),
+                        c => panic!("Expected !, got {:?}", c),
+                    }
+                }
+                c => panic!("Expected ||, got {:?}", c),
+            },
+            s => panic!("Expected if, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_handles_grouped_conditions() {
+        let source = "let x = 1;\nwhile (x > 0 || x < -5) && (x + 1) * 2 != 4 {\n  x = x - 1;\n}";
+        let statements = parse_source(source).unwrap();
+        match &statements[1] {
+            Statement::While {
+                comparison: Comparison::And(lhs, rhs),
+                ..
+            } => {
+                assert!(matches!(lhs.as_ref(), Comparison::Or(..)));
+                assert!(matches!(
+                    rhs.as_ref(),
+                    Comparison::Relational { rhs, .. } if rhs[0].0 == RelationalOp::NotEquals
+                ));
+            }
+            s => panic!("Expected while with &&, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn check_if_handles_chained_comparisons() {
+        let source = "let i = 1;\nlet n = 3;\nif 0 <= i < n != 0 {\n}";
+        let statements = parse_source(source).unwrap();
+        match &statements[2] {
+            Statement::If {
+                comparison: Comparison::Relational { rhs, .. },
+                ..
+            } => {
+                let ops: Vec<RelationalOp> = rhs.iter().map(|(op, _)| *op).collect();
+                assert_eq!(
+                    ops,
+                    vec![
+                        RelationalOp::LessThanEquals,
+                        RelationalOp::LessThan,
+                        RelationalOp::NotEquals
+                    ]
+                );
+            }
+            s => panic!("Expected chained comparison, got {:?}", s),
+        }
+    }
 }
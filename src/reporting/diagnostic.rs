@@ -0,0 +1,300 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+use crate::lexing::lexer::{Span, Token, TokenKind};
+use crate::parsing::parser::ParserError;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub label: Option<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            message: message.into(),
+            span,
+            label: None,
+            help: None,
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl From<&Token> for Diagnostic {
+    fn from(token: &Token) -> Self {
+        let raw = token.data.raw.as_str();
+        match token.kind {
+            TokenKind::TokenizationError => {
+                Diagnostic::error("Number literal is too large", token.span())
+                    .with_label("does not fit in a 64 bit integer")
+            }
+            TokenKind::Invalid if raw.starts_with('"') => {
+                Diagnostic::error("Unterminated string literal", token.span())
+                    .with_label("string starts here")
+                    .with_help("string literals must be closed with `\"` on the same line")
+            }
+            TokenKind::Invalid if raw == "&" || raw == "|" => {
+                Diagnostic::error(format!("Invalid token `{}`", raw), token.span())
+                    .with_help(format!("did you mean `{}{}`?", raw, raw))
+            }
+            _ => Diagnostic::error(format!("Invalid token `{}`", raw), token.span()),
+        }
+    }
+}
+
+impl From<&ParserError> for Diagnostic {
+    fn from(error: &ParserError) -> Self {
+        let message = match (&error.reason, error.expected) {
+            (Some(reason), _) => reason.clone(),
+            (None, Some(expected)) => format!("Expected {}", expected),
+            (None, None) => "Unexpected token".to_string(),
+        };
+        let mut diagnostic = Diagnostic::error(message, error.span);
+        match &error.token {
+            Some(token) if token.span() == error.span => {
+                diagnostic = diagnostic.with_label(format!("found {}", token.kind))
+            }
+            Some(_) => {}
+            None => diagnostic = diagnostic.with_label("found end of file"),
+        };
+        match &error.help {
+            Some(help) => diagnostic.with_help(help.clone()),
+            None => diagnostic,
+        }
+    }
+}
+
+/// Renders diagnostics in the style of rustc, pointing into the source they were raised for.
+pub struct Renderer<'a> {
+    file_name: &'a str,
+    source: Option<&'a str>,
+    colored: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(file_name: &'a str, source: &'a str, colored: bool) -> Self {
+        Renderer {
+            file_name,
+            source: Some(source),
+            colored,
+        }
+    }
+
+    /// For files that do not carry their source, like compiled scripts. Diagnostics only point
+    /// at line and column then, without showing a snippet.
+    pub fn without_source(file_name: &'a str, colored: bool) -> Self {
+        Renderer {
+            file_name,
+            source: None,
+            colored,
+        }
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let span = diagnostic.span;
+        let line_nr = span.line.max(1);
+        let gutter = " ".repeat(line_nr.to_string().len());
+
+        let mut emit = String::new();
+        emit.push_str(&format!(
+            "{}: {}\n",
+            self.paint(RED, "error"),
+            self.paint(BOLD, &diagnostic.message)
+        ));
+        emit.push_str(&format!(
+            "{}{} {}:{}:{}\n",
+            gutter,
+            self.paint(BLUE, "-->"),
+            self.file_name,
+            line_nr,
+            span.column.max(1)
+        ));
+        if let Some(source) = self.source {
+            self.render_snippet(&mut emit, diagnostic, source, &gutter);
+        }
+
+        if let Some(help) = &diagnostic.help {
+            if self.source.is_some() {
+                emit.push_str(&format!("{} {}\n", gutter, self.paint(BLUE, "|")));
+            }
+            emit.push_str(&format!(
+                "{} {} {}: {}\n",
+                gutter,
+                self.paint(BLUE, "="),
+                self.paint(BOLD, "help"),
+                help
+            ));
+        }
+        emit
+    }
+
+    fn render_snippet(
+        &self,
+        emit: &mut String,
+        diagnostic: &Diagnostic,
+        source: &str,
+        gutter: &str,
+    ) {
+        let span = diagnostic.span;
+        let line_nr = span.line.max(1);
+        let source_line = source.lines().nth(line_nr - 1).unwrap_or("");
+        emit.push_str(&format!("{} {}\n", gutter, self.paint(BLUE, "|")));
+        emit.push_str(&format!(
+            "{} {} {}\n",
+            self.paint(BLUE, &line_nr.to_string()),
+            self.paint(BLUE, "|"),
+            source_line
+        ));
+
+        // Keep tabs from the source line so the carets line up with what the user sees.
+        let padding: String = source_line
+            .chars()
+            .take(span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // Multi line spans are only underlined up to the end of their first line.
+        let underlined = source
+            .get(span.start..span.end)
+            .and_then(|s| s.lines().next())
+            .map_or(0, |s| s.chars().count())
+            .max(1);
+        let mut marker = "^".repeat(underlined);
+        if let Some(label) = &diagnostic.label {
+            marker.push(' ');
+            marker.push_str(label);
+        }
+        emit.push_str(&format!(
+            "{} {} {}{}\n",
+            gutter,
+            self.paint(BLUE, "|"),
+            padding,
+            self.paint(RED, &marker)
+        ));
+    }
+
+    fn paint(&self, colour: &str, text: &str) -> String {
+        if self.colored {
+            format!("{}{}{}", colour, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Colour is only used when stderr is a terminal and the user did not opt out with `NO_COLOR`.
+pub fn stderr_supports_color() -> bool {
+    io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzing::semantic::SemanticAnalyzer;
+    use crate::lexing::lexer::Lexer;
+    use crate::parsing::parser::Parser;
+
+    fn parse_error(source: &str) -> Diagnostic {
+        let tokens: Vec<Token> = Lexer::from(source).collect();
+        let errors = Parser::new(&tokens).parse().unwrap_err();
+        Diagnostic::from(&errors[0])
+    }
+
+    fn semantic_error(source: &str) -> Diagnostic {
+        let tokens: Vec<Token> = Lexer::from(source).collect();
+        let mut statements = Parser::new(&tokens).parse().unwrap();
+        let diagnostics = SemanticAnalyzer::new()
+            .analyze(&mut statements)
+            .unwrap_err();
+        diagnostics[0].clone()
+    }
+
+    #[test]
+    fn test_render_with_help() {
+        let source = "let a = 1;\nx = a + 1;\n";
+        let diagnostic = semantic_error(source);
+        let rendered = Renderer::new("test.scrpt", source, false).render(&diagnostic);
+
+        let expected = "\
+error: Identifier x is never declared
+ --> test.scrpt:2:1
+  |
+2 | x = a + 1;
+  | ^
+  |
+  = help: did you mean `let x = ...`?
+";
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn test_render_underlines_whole_span() {
+        let source = "let a = 1;\nwhile a < 3 {\n\tprint a\n}";
+        let diagnostic = parse_error(source);
+        let rendered = Renderer::new("test.scrpt", source, false).render(&diagnostic);
+
+        let expected = "\
+error: Expected `;`
+ --> test.scrpt:4:1
+  |
+4 | }
+  | ^ found `}`
+";
+        assert_eq!(rendered, expected);
+
+        let diagnostic = Diagnostic::error("Something is off", Span::new(26, 33, 3, 2));
+        let rendered = Renderer::new("test.scrpt", source, false).render(&diagnostic);
+        assert!(rendered.contains("3 | \tprint a\n  | \t^^^^^^^\n"));
+    }
+
+    #[test]
+    fn test_render_invalid_tokens() {
+        let source = "print \"unclosed\n";
+        let token = Lexer::from(source)
+            .find(|t| t.kind == TokenKind::Invalid)
+            .unwrap();
+        let rendered = Renderer::new("test.scrpt", source, false).render(&Diagnostic::from(&token));
+        assert!(rendered.starts_with("error: Unterminated string literal\n"));
+        assert!(rendered.contains("1 | print \"unclosed\n  |       ^^^^^^^^^ string starts here\n"));
+    }
+
+    #[test]
+    fn test_render_without_source() {
+        let diagnostic = Diagnostic::error("Attempt to divide by zero", Span::new(12, 17, 2, 7))
+            .with_help("the right hand side of this `/` evaluated to 0");
+        let rendered = Renderer::without_source("test.mmcb", false).render(&diagnostic);
+
+        let expected = "\
+error: Attempt to divide by zero
+ --> test.mmcb:2:7
+  = help: the right hand side of this `/` evaluated to 0
+";
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn test_render_with_color() {
+        let source = "print b;";
+        let diagnostic = semantic_error(source);
+        let rendered = Renderer::new("test.scrpt", source, true).render(&diagnostic);
+        assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m: "));
+        assert!(rendered.contains(RESET));
+    }
+}
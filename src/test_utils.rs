@@ -0,0 +1,186 @@
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::analyzing::semantic::SemanticAnalyzer;
+use crate::interpreting::interpreter::Interpreter;
+use crate::lexing::lexer::{Lexer, Token};
+use crate::parsing::parser::{Parser, Statement};
+
+/// Strings with every escape and character the backends have to quote for their target.
+pub const SPECIAL_STRINGS: &str = r#"print "backslash \\ tab \t cr \r unknown \q percent 100% %d %s braces {} {{0}} dollar ${x} `x` ' unicode é ✓\n";
+print "";
+print "\";
+print 1;"#;
+
+/// Negations of negative operands, which must not turn into a decrement or overflow.
+pub const DOUBLE_NEGATION: &str = "\
+input x;
+print -(-5);
+print -(-x);
+print -(-(-x));
+print 0 - -x;";
+
+/// Chains only evaluate an operand when the links before it hold, and middle operands once.
+pub const LAZY_CHAINS: &str = "\
+input x;
+let y = 5;
+if 1 < 0 < 1 / x {
+    print 1;
+} else {
+    print 2;
+}
+if 0 <= x < y * 2 - 1 <= 9 {
+    print 3;
+}
+while 0 < y - x < 10 && y {
+    y = y - 1;
+}
+print y;";
+
+/// `break` and `continue` leave or restart the innermost loop only, `for` runs its step.
+pub const NESTED_LOOPS: &str = "\
+for let i = 0; i < 4; i = i + 1 {
+    if i == 1 {
+        continue;
+    }
+    let j = 0;
+    while j < 4 {
+        j = j + 1;
+        if j == 2 {
+            continue;
+        }
+        for let k = 0; k < 3; k = k + 1 {
+            if k == 1 {
+                break;
+            }
+            print i * 100 + j * 10 + k;
+        }
+        if j == i {
+            break;
+        }
+    }
+    if i == 3 {
+        break;
+    }
+    print i;
+}
+print 99;";
+
+/// The edge case programs with the input to run them on. `x` is read as 0 in `LAZY_CHAINS`, so
+/// evaluating `1 / x` too eagerly fails.
+pub const EDGE_CASES: [(&str, &str); 5] = [
+    (SPECIAL_STRINGS, ""),
+    (DOUBLE_NEGATION, "7"),
+    (DOUBLE_NEGATION, "-9223372036854775808"),
+    (LAZY_CHAINS, "0"),
+    (NESTED_LOOPS, ""),
+];
+
+/// Lexes, parses and analyzes `source`, which has to be a valid program.
+pub fn analyze(source: &str) -> Vec<Statement> {
+    let tokens: Vec<Token> = Lexer::from(source).collect();
+    let mut statements = Parser::new(&tokens)
+        .parse()
+        .expect("Expected source to parse");
+    SemanticAnalyzer::new()
+        .analyze(&mut statements)
+        .expect("Expected source to pass semantic analysis");
+    statements
+}
+
+/// The part of `code` after the first `start` and before the last `end`.
+pub fn between<'a>(code: &'a str, start: &str, end: &str) -> &'a str {
+    let (_, rest) = code
+        .split_once(start)
+        .expect("Expected code to contain start");
+    let (middle, _) = rest.rsplit_once(end).expect("Expected code to contain end");
+    middle
+}
+
+/// Runs `source` with `input` in the tree-walking interpreter and returns what it printed.
+pub fn interpret(source: &str, input: &str) -> String {
+    let statements = analyze(source);
+    let mut output = Vec::new();
+    Interpreter::new(input.as_bytes(), &mut output)
+        .run(&statements)
+        .expect("Expected source to run");
+    String::from_utf8(output).expect("Expected output to be UTF-8")
+}
+
+/// A path in the temporary directory that is unique to this test run and `name`.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mmc-test-{}-{}", std::process::id(), name))
+}
+
+/// Runs `program` with `input` on its standard input and returns what it printed, or `None`
+/// when `program` is not installed, so tests of a backend can be skipped without its toolchain.
+pub fn run(program: &str, args: &[&str], input: &str) -> Option<String> {
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(error) if error.kind() == ErrorKind::NotFound => return None,
+        Err(error) => panic!("Unable to run {}: {}", program, error),
+    };
+    child
+        .stdin
+        .take()
+        .expect("Expected a piped stdin")
+        .write_all(input.as_bytes())
+        .expect("Expected to write the input");
+    let output = child
+        .wait_with_output()
+        .expect("Expected program to finish");
+    assert!(
+        output.status.success(),
+        "{} failed: {}",
+        program,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Some(String::from_utf8(output.stdout).expect("Expected output to be UTF-8"))
+}
+
+/// Writes `code` to a temporary `file_name` and runs `program` with `args` and the file's path
+/// like `run` does.
+pub fn run_file(
+    program: &str,
+    args: &[&str],
+    file_name: &str,
+    code: &str,
+    input: &str,
+) -> Option<String> {
+    let path = temp_path(file_name);
+    std::fs::write(&path, code).expect("Expected to write the file");
+    let mut args = args.to_vec();
+    args.push(path.to_str().expect("Expected a UTF-8 path"));
+    let output = run(program, &args, input);
+    std::fs::remove_file(&path).expect("Expected to remove the file");
+    output
+}
+
+/// Decodes a quoted string escaped by `escape_gas_string`, as GNU as and QBE read it.
+pub fn decode_gas_string(operand: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chars = operand[1..operand.len() - 1].bytes();
+    while let Some(byte) = chars.next() {
+        bytes.push(if byte != b'\\' {
+            byte
+        } else {
+            match chars.next().unwrap() {
+                b'n' => b'\n',
+                b't' => b'\t',
+                digit @ b'0'..=b'7' => {
+                    let digits = [digit, chars.next().unwrap(), chars.next().unwrap()];
+                    u8::from_str_radix(std::str::from_utf8(&digits).unwrap(), 8).unwrap()
+                }
+                byte => byte,
+            }
+        });
+    }
+    bytes
+}